@@ -2,6 +2,9 @@ use copy_includes::copy_includes;
 
 fn main() {
     copy_includes("./shaders/ComputePatches.wgsl").unwrap();
+    copy_includes("./shaders/PersistentPatches.wgsl").unwrap();
     copy_includes("./shaders/CopyPatches.wgsl").unwrap();
+    copy_includes("./shaders/GroundPlane.wgsl").unwrap();
+    copy_includes("./shaders/ReusePatches.wgsl").unwrap();
     copy_includes("./shaders/Shader.wgsl").unwrap();
 }
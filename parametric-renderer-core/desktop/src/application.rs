@@ -2,13 +2,15 @@ use glam::{Vec2, Vec3};
 use renderer_core::{
     application::{AppCommand, Application, WasmCanvas},
     camera::camera_controller::{self, CameraController, IsCameraController},
-    game::{MaterialInfo, ModelInfo, ShaderId, ShaderInfo},
+    game::{EnvironmentInfo, MaterialInfo, ModelInfo, ShaderId, ShaderInfo},
+    hdr::HdrImage,
     input::WinitAppHelper,
     transform::Transform,
 };
+use std::sync::Arc;
 use winit::event_loop::EventLoop;
 
-use crate::config::{CacheFile, CachedCamera, CachedChosenController};
+use crate::config::{CacheFile, CachedCamera, CachedChosenController, SequenceFile};
 
 const CACHE_FILE: &'static str = "cache.json";
 const HEART_SPHERE_SHADER_CODE: &'static str = include_str!("../../shaders/HeartSphere.wgsl");
@@ -53,18 +55,54 @@ pub fn run() -> anyhow::Result<()> {
             position: Vec3::new(0.0, 0.0, 0.0),
             ..Default::default()
         },
+        parent: None,
         material_info: MaterialInfo {
             color: Vec3::new(0.6, 1.0, 1.0),
             emissive: Vec3::new(0.0, 0.0, 0.0),
             roughness: 0.7,
             metallic: 0.1,
             diffuse_texture: None,
+            normal_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
             texture_scale: Vec2::ONE,
+            opacity: 1.0,
+            alpha_from_texture: false,
         },
         shader_id,
         instance_count: 5,
+        instances: vec![],
+        cast_shadows: true,
+        receive_shadows: true,
     }]);
 
+    let mut environment_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // A JSON file that describes the animation that `I` renders
+            "--sequence" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Expected a path after --sequence"))?;
+                application.sequence_settings = Some(SequenceFile::from_file(path)?.into());
+            }
+            _ => environment_path = Some(arg),
+        }
+    }
+
+    // An optional Radiance .hdr image for lighting the scene
+    if let Some(path) = environment_path {
+        let image = HdrImage::from_radiance(&std::fs::read(path)?)?;
+        application
+            .app
+            .set_environment(Some(Arc::new(EnvironmentInfo {
+                image,
+                intensity: 1.0,
+            })));
+    }
+
     if let Some(CachedCamera {
         position,
         orientation,
@@ -1,5 +1,10 @@
 use core::fmt;
+use glam::{UVec2, Vec3};
 use nanoserde::{DeJson, DeJsonErr, SerJson};
+use renderer_core::{
+    renderer::{SequenceSettings, Turntable},
+    time::Seconds,
+};
 use std::path::Path;
 
 #[derive(DeJson, SerJson, Debug, Clone)]
@@ -42,11 +47,58 @@ impl Default for CacheFile {
     }
 }
 
+/// Describes an animation that gets rendered frame by frame
+#[derive(DeJson, Debug, Clone)]
+pub struct SequenceFile {
+    pub frame_count: u32,
+    pub fps: f32,
+    pub width: u32,
+    pub height: u32,
+    #[nserde(default)]
+    pub start_time: f32,
+    /// Rotates the camera around the center, this many times
+    #[nserde(default)]
+    pub turntable_revolutions: Option<f32>,
+    #[nserde(default)]
+    pub turntable_center: Option<[f32; 3]>,
+}
+
+impl SequenceFile {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LoadConfigError> {
+        let content = std::fs::read_to_string(path)?;
+        let sequence: Self = DeJson::deserialize_json(&content)?;
+        // Zero would turn the time of the first frame into NaN
+        if !sequence.fps.is_finite() || sequence.fps <= 0.0 {
+            return Err(LoadConfigError::Invalid(format!(
+                "fps must be a positive number, but is {}",
+                sequence.fps
+            )));
+        }
+        Ok(sequence)
+    }
+}
+
+impl From<SequenceFile> for SequenceSettings {
+    fn from(v: SequenceFile) -> Self {
+        Self {
+            frame_count: v.frame_count,
+            fps: v.fps,
+            size: UVec2::new(v.width, v.height),
+            start_time: Seconds(v.start_time),
+            turntable: v.turntable_revolutions.map(|revolutions| Turntable {
+                center: v.turntable_center.map(Vec3::from).unwrap_or(Vec3::ZERO),
+                revolutions,
+            }),
+        }
+    }
+}
+
 // See also https://www.reddit.com/r/rust/comments/gj8inf/comment/fqlmknt/
 #[derive(Debug)]
 pub enum LoadConfigError {
     Io(std::io::Error),
     Parse(DeJsonErr),
+    Invalid(String),
 }
 
 impl fmt::Display for LoadConfigError {
@@ -54,9 +106,11 @@ impl fmt::Display for LoadConfigError {
         match self {
             LoadConfigError::Io(v) => write!(fmt, "IO error: {v}"),
             LoadConfigError::Parse(v) => write!(fmt, "Failed to parse config file: {v}"),
+            LoadConfigError::Invalid(v) => write!(fmt, "Invalid config file: {v}"),
         }
     }
 }
+impl std::error::Error for LoadConfigError {}
 impl From<std::io::Error> for LoadConfigError {
     fn from(source: std::io::Error) -> Self {
         LoadConfigError::Io(source)
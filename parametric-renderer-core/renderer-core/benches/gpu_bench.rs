@@ -44,16 +44,26 @@ fn main() {
             position: Vec3::new(0.0, 0.0, 0.0),
             ..Default::default()
         },
+        parent: None,
         material_info: renderer_core::game::MaterialInfo {
             color: Vec3::new(0.6, 1.0, 1.0),
             emissive: Vec3::new(0.0, 0.0, 0.0),
             roughness: 0.7,
             metallic: 0.1,
             diffuse_texture: None,
+            normal_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
             texture_scale: glam::Vec2::ONE,
+            opacity: 1.0,
+            alpha_from_texture: false,
         },
         shader_id,
         instance_count: 1,
+        instances: vec![],
+        cast_shadows: true,
+        receive_shadows: true,
     }]);
 
     let window = {
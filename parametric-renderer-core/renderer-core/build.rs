@@ -30,9 +30,25 @@ fn main() {
         "../shaders/ComputePatches.wgsl",
         "compute_patches",
     ));
+    shaders.push(watch_shader(
+        "../shaders/PersistentPatches.wgsl",
+        "persistent_patches",
+    ));
     shaders.push(watch_shader("../shaders/CopyPatches.wgsl", "copy_patches"));
+    shaders.push(watch_shader(
+        "../shaders/ReusePatches.wgsl",
+        "reuse_patches",
+    ));
     shaders.push(watch_shader("../shaders/GroundPlane.wgsl", "ground_plane"));
     shaders.push(watch_shader("../shaders/Skybox.wgsl", "skybox"));
+    shaders.push(watch_shader("../shaders/Environment.wgsl", "environment"));
+    shaders.push(watch_shader("../shaders/Mipmap.wgsl", "mipmap"));
+    shaders.push(watch_shader(
+        "../shaders/ObjectIdResolve.wgsl",
+        "object_id_resolve",
+    ));
+    shaders.push(watch_shader("../shaders/Bloom.wgsl", "bloom"));
+    shaders.push(watch_shader("../shaders/ToneMapping.wgsl", "tone_mapping"));
 
     let mut text = String::new();
     writeln!(&mut text, "// File automatically generated by build.rs.").unwrap();
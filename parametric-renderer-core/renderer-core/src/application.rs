@@ -8,9 +8,10 @@ use winit::{
 };
 
 use crate::{
-    game::{GameRes, ShaderId},
+    game::{GameRes, ShaderId, ShaderInfo},
+    image::Image,
     input::{InputHandler, WindowInputs},
-    renderer::{GpuApplication, GpuApplicationBuilder},
+    renderer::{GpuApplication, GpuApplicationBuilder, SequenceSettings},
     time::{TimeCounters, TimeStats},
     window_or_fallback::WindowOrFallback,
 };
@@ -39,6 +40,8 @@ pub struct Application {
     app_commands: EventLoopProxy<AppCommand>,
     on_exit_callback: Option<Box<dyn FnOnce(&mut Application)>>,
     pub on_shader_compiled: Option<ShaderCompiledCallback>,
+    /// What to render when recording an animation
+    pub sequence_settings: Option<SequenceSettings>,
     _canvas: WasmCanvas,
 }
 #[derive(Clone)]
@@ -59,10 +62,48 @@ impl Application {
             app_commands,
             on_exit_callback: Some(Box::new(on_exit)),
             on_shader_compiled: None,
+            sequence_settings: None,
             _canvas: canvas,
         }
     }
 
+    /// Also recompiles the shaders that include this one
+    pub fn update_shader(&mut self, shader_id: ShaderId, info: ShaderInfo) {
+        let label = info.label.clone();
+        self.app.set_shader(shader_id.clone(), info);
+        let mut shader_ids = self.app.shaders_including(&[&shader_id.0, &label]);
+        if !shader_ids.contains(&shader_id) {
+            shader_ids.push(shader_id);
+        }
+        self.compile_shaders(&shader_ids);
+    }
+
+    /// Recompiles the shaders that include the file
+    pub fn update_shader_file(&mut self, name: String, code: Option<String>) {
+        match code {
+            Some(code) => self.app.set_shader_file(name.clone(), code),
+            None => self.app.remove_shader_file(&name),
+        }
+        let shader_ids = self.app.shaders_including(&[&name]);
+        self.compile_shaders(&shader_ids);
+    }
+
+    fn compile_shaders(&self, shader_ids: &[ShaderId]) {
+        let Some(renderer) = &self.renderer else {
+            return;
+        };
+        for shader_id in shader_ids {
+            if let Some(info) = self.app.shaders.get(shader_id) {
+                any_spawner::Executor::spawn_local(renderer.set_shader(
+                    shader_id.clone(),
+                    info,
+                    &|name| self.app.resolve_include(name),
+                    self.on_shader_compiled.clone(),
+                ));
+            }
+        }
+    }
+
     fn on_exit(&mut self) {
         self.window.take();
         self.renderer.take();
@@ -82,14 +123,30 @@ impl Application {
         let task = async move {
             let renderer = gpu_builder.await.unwrap().build();
             let _ = run_on_main(app_commands, move |app| {
+                // Before the shaders, so that they are compiled for it
+                renderer.set_sample_count(app.app.sample_count);
                 for (shader_id, shader_info) in &app.app.shaders {
                     any_spawner::Executor::spawn_local(renderer.set_shader(
                         shader_id.clone(),
                         shader_info,
+                        &|name| app.app.resolve_include(name),
                         on_shader_compiled.clone(),
                     ));
                 }
                 renderer.update_models(&app.app.models);
+                for (model_id, values) in &app.app.model_parameters {
+                    renderer.set_model_parameters(model_id, values.clone());
+                }
+                renderer.update_lights(&app.app.lights);
+                if let Err(e) = renderer.set_environment(app.app.environment.clone()) {
+                    error!("Failed to set the environment: {e}");
+                }
+                renderer.set_debug_view(app.app.debug_view);
+                renderer.set_lod_mode(app.app.lod_mode);
+                renderer.set_lod_kernel(app.app.lod_kernel);
+                renderer.set_post_process(app.app.post_process.clone());
+                renderer.set_color_grading(app.app.color_grading.clone());
+                renderer.set_clock(app.app.clock);
                 app.renderer = Some(renderer)
             })
             .await;
@@ -125,6 +182,46 @@ where
     receiver.await.expect("Was the main thread stopped?")
 }
 
+/// Renders an animation frame by frame, and hands every frame to `on_frame`.
+/// Afterwards, the clock continues the way it was set before.
+pub async fn render_sequence(
+    app_commands: EventLoopProxy<AppCommand>,
+    settings: SequenceSettings,
+    mut on_frame: impl FnMut(u32, Image) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let clock = run_on_main(app_commands.clone(), |app| {
+        app.renderer.as_ref().map(|renderer| renderer.clock())
+    })
+    .await
+    .ok_or_else(|| anyhow::anyhow!("Renderer is not ready"))?;
+
+    let mut result = Ok(());
+    for frame in 0..settings.frame_count {
+        let settings = settings.clone();
+        let capture = run_on_main(app_commands.clone(), move |app| {
+            app.renderer
+                .as_ref()
+                .map(|renderer| renderer.capture_sequence_frame(&settings, frame))
+        })
+        .await;
+        result = match capture {
+            Some(capture) => capture.await.and_then(|image| on_frame(frame, image)),
+            None => Err(anyhow::anyhow!("Renderer was removed")),
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+
+    let _ = run_on_main(app_commands, move |app| {
+        if let Some(renderer) = &app.renderer {
+            renderer.set_clock(clock);
+        }
+    })
+    .await;
+    result
+}
+
 impl ApplicationHandler<AppCommand> for Application {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         // A really good app might recreate the renderer here?
@@ -208,14 +305,7 @@ impl InputHandler for Application {
         {
             match &self.time_counters.last_results {
                 Some(data) => {
-                    let file_name = format!(
-                        "profile-{}.json",
-                        // use the current time as a unique-enugh identifier
-                        std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis()
-                    );
+                    let file_name = format!("profile-{}.json", unique_id());
                     wgpu_profiler::chrometrace::write_chrometrace(
                         std::path::Path::new(&file_name),
                         data,
@@ -229,6 +319,60 @@ impl InputHandler for Application {
             }
         }
 
+        // Press O to save a screenshot at twice the window resolution
+        #[cfg(not(target_arch = "wasm32"))]
+        if input
+            .keyboard
+            .just_pressed_physical(winit::keyboard::KeyCode::KeyO)
+            && let Some(renderer) = &self.renderer
+        {
+            let file_name = format!("screenshot-{}.png", unique_id());
+            let capture = renderer.capture_frame(2.0);
+            any_spawner::Executor::spawn_local(async move {
+                let result = capture
+                    .await
+                    .and_then(|image| image.to_png())
+                    .and_then(|png| Ok(std::fs::write(&file_name, png)?));
+                match result {
+                    Ok(()) => info!("Screenshot written to {file_name}"),
+                    Err(e) => error!("Failed to save screenshot: {e:?}"),
+                }
+            });
+        }
+
+        // Press I to render an animation into numbered PNG files
+        #[cfg(not(target_arch = "wasm32"))]
+        if input
+            .keyboard
+            .just_pressed_physical(winit::keyboard::KeyCode::KeyI)
+        {
+            let settings = self.sequence_settings.clone().unwrap_or_default();
+            let directory = std::path::PathBuf::from(format!("sequence-{}", unique_id()));
+            let app_commands = self.app_commands.clone();
+            info!(
+                "Rendering {} frames into {}",
+                settings.frame_count,
+                directory.display()
+            );
+            any_spawner::Executor::spawn_local(async move {
+                let result = match std::fs::create_dir_all(&directory) {
+                    Ok(()) => {
+                        render_sequence(app_commands, settings, |frame, image| {
+                            let png = image.to_png()?;
+                            std::fs::write(directory.join(format!("frame-{frame:04}.png")), png)?;
+                            Ok(())
+                        })
+                        .await
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                match result {
+                    Ok(()) => info!("Sequence written to {}", directory.display()),
+                    Err(e) => error!("Failed to render sequence: {e:?}"),
+                }
+            });
+        }
+
         if let Some(PhysicalSize { width, height }) = input.new_size {
             self.renderer
                 .as_mut()
@@ -276,3 +420,12 @@ impl InputHandler for Application {
         }
     }
 }
+
+/// Uses the current time as a unique-enugh identifier
+#[cfg(not(target_arch = "wasm32"))]
+fn unique_id() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
@@ -58,6 +58,18 @@ impl Camera {
         )
     }
 
+    /// Rotates the camera around a point, while it keeps looking at the same part of that point
+    pub fn orbit(&self, center: Vec3, rotation: Quat) -> Self {
+        let position = center + rotation * (self.position - center);
+        let orientation = rotation * self.orientation;
+        Self {
+            position,
+            orientation,
+            settings: self.settings.clone(),
+            view: calculate_view(position, orientation),
+        }
+    }
+
     pub fn update_camera(&mut self, controller: &impl IsCameraController) {
         self.position = controller.position();
         self.orientation = controller.orientation();
@@ -0,0 +1,110 @@
+//! Parser for .cube color lookup tables, which most color grading tools can export.
+
+use anyhow::{Context, bail};
+use glam::Vec3;
+
+/// A 3D lookup table from sRGB encoded colors to graded sRGB encoded colors
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGradingLut {
+    /// Number of entries along each axis
+    pub size: u32,
+    /// Red changes fastest, then green, then blue
+    pub data: Vec<Vec3>,
+}
+
+impl ColorGradingLut {
+    /// Decodes a 3D LUT in the .cube text format. Only the default domain from 0 to 1 is supported.
+    pub fn from_cube(text: &str) -> anyhow::Result<Self> {
+        let mut size = None;
+        let mut data = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("TITLE") => {}
+                Some("LUT_3D_SIZE") => {
+                    let value = parts.next().context("Missing LUT_3D_SIZE value")?;
+                    size = Some(value.parse::<u32>().context("Invalid LUT_3D_SIZE")?);
+                }
+                Some("LUT_1D_SIZE") => bail!("1D LUTs are not supported"),
+                Some(keyword @ ("DOMAIN_MIN" | "DOMAIN_MAX")) => {
+                    let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    if parse_vec3(parts)? != Vec3::splat(expected) {
+                        bail!("Custom LUT domains are not supported");
+                    }
+                }
+                _ => data.push(parse_vec3(line.split_whitespace())?),
+            }
+        }
+
+        let size = size.context("Missing LUT_3D_SIZE")?;
+        if size < 2 {
+            bail!("LUT_3D_SIZE has to be at least 2");
+        }
+        if data.len() != (size * size * size) as usize {
+            bail!(
+                "Expected {} LUT entries, but found {}",
+                size * size * size,
+                data.len()
+            );
+        }
+        Ok(Self { size, data })
+    }
+
+    /// Leaves every color unchanged
+    pub fn identity(size: u32) -> Self {
+        let max = (size - 1) as f32;
+        let data = (0..size)
+            .flat_map(|b| (0..size).flat_map(move |g| (0..size).map(move |r| (r, g, b))))
+            .map(|(r, g, b)| Vec3::new(r as f32, g as f32, b as f32) / max)
+            .collect();
+        Self { size, data }
+    }
+
+    /// RGBA8 texels, in the same order as the data
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .flat_map(|color| {
+                let [r, g, b] = (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0)
+                    .round()
+                    .to_array()
+                    .map(|v| v as u8);
+                [r, g, b, u8::MAX]
+            })
+            .collect()
+    }
+}
+
+fn parse_vec3<'a>(mut parts: impl Iterator<Item = &'a str>) -> anyhow::Result<Vec3> {
+    let mut next = || -> anyhow::Result<f32> {
+        parts
+            .next()
+            .context("Expected three numbers")?
+            .parse::<f32>()
+            .context("Invalid number in LUT")
+    };
+    Ok(Vec3::new(next()?, next()?, next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cube_file() {
+        let text = "# Comment\nTITLE \"Test\"\nLUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\n\n\
+            0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+        let lut = ColorGradingLut::from_cube(text).unwrap();
+        assert_eq!(lut, ColorGradingLut::identity(2));
+    }
+
+    #[test]
+    fn rejects_wrong_entry_count() {
+        let text = "LUT_3D_SIZE 2\n0 0 0\n1 1 1\n";
+        assert!(ColorGradingLut::from_cube(text).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::{collections::HashMap, sync::Arc};
 
-use glam::{Vec2, Vec3};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 use web_time::Instant;
 
 use crate::{
@@ -10,7 +10,12 @@ use crate::{
             CameraController, ChosenKind, GeneralController, GeneralControllerSettings,
         },
     },
+    color_grading::ColorGradingLut,
+    hdr::HdrImage,
     input::{CursorCaptureRequest, WindowCursorCapture, WindowInputs},
+    shader_includes::include_names,
+    shader_parameters::ParameterValues,
+    time::ClockMode,
     transform::Transform,
 };
 
@@ -22,10 +27,84 @@ pub struct ProfilerSettings {
 #[derive(Debug, Clone, PartialEq)]
 pub struct ModelInfo {
     pub id: String,
+    /// Relative to the parent, if there is one
     pub transform: Transform,
+    /// The ID of another model, which this one moves with
+    pub parent: Option<String>,
     pub material_info: MaterialInfo,
     pub shader_id: ShaderId,
     pub instance_count: u32,
+    /// Data for each instance, which the shader code can read. Missing entries use the defaults.
+    pub instances: Vec<InstanceInfo>,
+    /// Whether the model is rendered into the shadow maps
+    pub cast_shadows: bool,
+    /// Whether shadows are applied when shading the model
+    pub receive_shadows: bool,
+}
+
+impl ModelInfo {
+    /// The data of every instance, as many as `instance_count`
+    pub fn instances_padded(&self) -> impl Iterator<Item = InstanceInfo> + '_ {
+        (0..self.instance_count as usize)
+            .map(|i| self.instances.get(i).cloned().unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceInfo {
+    /// Relative to the model, and applied after `sampleObject`
+    pub transform: Transform,
+    /// Multiplies the color from `getColor`
+    pub tint: Vec3,
+    /// Free for the shader code
+    pub custom: Vec4,
+}
+
+impl Default for InstanceInfo {
+    fn default() -> Self {
+        Self {
+            transform: Transform::default(),
+            tint: Vec3::ONE,
+            custom: Vec4::ZERO,
+        }
+    }
+}
+
+/// The world matrix of every model, which includes the transforms of its parents.
+/// Unknown parents are ignored, and a cycle is broken at the parent that closes it.
+pub fn world_matrices(models: &[ModelInfo]) -> HashMap<String, Mat4> {
+    let models_by_id: HashMap<&str, &ModelInfo> = models
+        .iter()
+        .map(|model| (model.id.as_str(), model))
+        .collect();
+    let mut matrices: HashMap<String, Mat4> = HashMap::with_capacity(models.len());
+    for model in models {
+        if matrices.contains_key(&model.id) {
+            continue;
+        }
+        // Walks up until the root, or until an ancestor whose matrix is already known
+        let mut chain = vec![model];
+        let mut parent_matrix = Mat4::IDENTITY;
+        while let Some(parent) = chain
+            .last()
+            .and_then(|child| child.parent.as_deref())
+            .and_then(|id| models_by_id.get(id))
+        {
+            if let Some(matrix) = matrices.get(&parent.id) {
+                parent_matrix = *matrix;
+                break;
+            }
+            if chain.iter().any(|v| v.id == parent.id) {
+                break;
+            }
+            chain.push(parent);
+        }
+        for v in chain.into_iter().rev() {
+            parent_matrix *= v.transform.to_matrix();
+            matrices.insert(v.id.clone(), parent_matrix);
+        }
+    }
+    matrices
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -35,7 +114,172 @@ pub struct MaterialInfo {
     pub roughness: f32,
     pub metallic: f32,
     pub diffuse_texture: Option<TextureId>,
+    /// Tangent space normal map, where the tangent follows the u direction of the surface
+    pub normal_texture: Option<TextureId>,
+    /// Roughness in the green channel and metallic in the blue channel, like in glTF
+    pub metallic_roughness_texture: Option<TextureId>,
+    /// Ambient occlusion in the red channel
+    pub occlusion_texture: Option<TextureId>,
+    /// Multiplied with the emissive color
+    pub emissive_texture: Option<TextureId>,
+    /// Shared by all textures
     pub texture_scale: Vec2,
+    /// 1.0 is fully opaque
+    pub opacity: f32,
+    /// Multiplies the opacity with the alpha channel of the diffuse texture
+    pub alpha_from_texture: bool,
+}
+
+impl MaterialInfo {
+    /// Transparent models are blended in a separate pass, after the opaque ones
+    pub fn is_transparent(&self) -> bool {
+        self.opacity < 1.0 || (self.alpha_from_texture && self.diffuse_texture.is_some())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightInfo {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightKind {
+    Point {
+        position: Vec3,
+        range: f32,
+    },
+    /// Infinitely far away, like the sun. The first directional light casts shadows.
+    Directional {
+        direction: Vec3,
+    },
+}
+
+impl LightInfo {
+    /// The lights of a scene that does not specify any
+    pub fn default_lights() -> Vec<LightInfo> {
+        vec![
+            LightInfo {
+                kind: LightKind::Directional {
+                    direction: Vec3::new(1.0, -4.0, 1.0).normalize(),
+                },
+                color: Vec3::new(0.5, 0.55, 0.5),
+                intensity: 0.9,
+            },
+            LightInfo {
+                kind: LightKind::Point {
+                    position: Vec3::new(0.0, 8.0, 4.0),
+                    range: 80.0,
+                },
+                color: Vec3::new(1.0, 1.0, 1.0),
+                intensity: 1.0,
+            },
+            LightInfo {
+                kind: LightKind::Point {
+                    position: Vec3::new(1.0, 8.0, -6.0),
+                    range: 70.0,
+                },
+                color: Vec3::new(1.0, 1.0, 1.0),
+                intensity: 1.5,
+            },
+            LightInfo {
+                kind: LightKind::Point {
+                    position: Vec3::new(0.0, -8.0, 0.0),
+                    range: 80.0,
+                },
+                color: Vec3::new(0.8, 0.8, 1.0),
+                intensity: 0.9,
+            },
+        ]
+    }
+}
+
+/// Visualizations of the LOD algorithm, instead of the shaded models
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    None,
+    /// The triangles of the render meshes. Uses line rasterization when the device supports it.
+    Wireframe,
+    /// The outlines of the patches
+    PatchBoundaries,
+    /// A heatmap of the patch render sizes, from blue for 2 to red for 32
+    PatchSize,
+    /// The (u,v) parameters as red and green
+    Uv,
+    Normals,
+    /// A different color for each instance of a model
+    InstanceId,
+}
+
+/// How the LOD stage finds the patches of a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LodMode {
+    /// Subdivides every model from scratch
+    #[default]
+    Rebuild,
+    /// Starts from the patches of the last frame, and only merges or splits where needed.
+    /// Needs fewer compute passes, but takes a few frames to catch up with large camera jumps.
+    Incremental,
+}
+
+/// Which compute kernel subdivides the patches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LodKernel {
+    /// Ping-pongs between two patch buffers for a fixed number of rounds
+    #[default]
+    Rounds,
+    /// A fixed number of workgroups that work through a queue, until every patch is small enough.
+    /// Patches that are `max_depth` splits away from the root are rendered, no matter how large they are.
+    /// A model switches to `Rounds` for good if the GPU does not get through the queue.
+    Persistent { max_depth: u32 },
+}
+
+/// Turns the HDR render result into the final image
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessInfo {
+    /// In stops, where 0.0 leaves the brightness unchanged
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    /// Disabled when `None`
+    pub bloom: Option<BloomInfo>,
+}
+
+impl Default for PostProcessInfo {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapping: ToneMapping::Aces,
+            bloom: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// Clamps the colors
+    None,
+    #[default]
+    Aces,
+    AgX,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomInfo {
+    /// How much of the blurred bright parts get added to the image
+    pub intensity: f32,
+    /// Brightness where the bloom starts
+    pub threshold: f32,
+}
+
+/// An environment that lights the scene and is visible as the skybox
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvironmentInfo {
+    /// Equirectangular image
+    pub image: HdrImage,
+    /// Scales the environment, for both the skybox and the lighting
+    pub intensity: f32,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -55,6 +299,39 @@ pub struct TextureInfo {
     pub height: u32,
     /// RGBA
     pub data: TextureData,
+    pub sampler: SamplerInfo,
+}
+
+/// How a texture is sampled on a material
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerInfo {
+    pub address_mode: AddressMode,
+    pub filter: FilterMode,
+    /// Maximum anisotropy, from 1 to 16. Only used with linear filtering.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerInfo {
+    fn default() -> Self {
+        Self {
+            address_mode: AddressMode::Repeat,
+            filter: FilterMode::Linear,
+            anisotropy: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressMode {
+    Repeat,
+    MirrorRepeat,
+    ClampToEdge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
 }
 
 pub enum TextureData {
@@ -66,8 +343,24 @@ pub enum TextureData {
 pub struct GameRes {
     pub camera_controller: CameraController,
     pub models: Vec<ModelInfo>,
+    /// Values of the shader parameters, by model ID
+    pub model_parameters: HashMap<String, ParameterValues>,
+    pub lights: Vec<LightInfo>,
+    /// Uses a default sky when not set
+    pub environment: Option<Arc<EnvironmentInfo>>,
     pub shaders: HashMap<ShaderId, ShaderInfo>,
+    /// Code that shaders can include, but that is not a shader by itself
+    pub shader_files: HashMap<String, String>,
     pub textures: HashMap<TextureId, TextureInfo>,
+    pub debug_view: DebugView,
+    /// Requested MSAA samples, the renderer rounds it down to a supported count
+    pub sample_count: u32,
+    pub lod_mode: LodMode,
+    pub lod_kernel: LodKernel,
+    pub post_process: PostProcessInfo,
+    /// No color grading when not set
+    pub color_grading: Option<Arc<ColorGradingLut>>,
+    pub clock: ClockMode,
     last_update_instant: Option<Instant>,
     pub camera: Camera,
     pub mouse: Vec2,
@@ -98,8 +391,19 @@ impl GameRes {
             camera,
             camera_controller,
             models: vec![],
+            model_parameters: HashMap::new(),
+            lights: LightInfo::default_lights(),
+            environment: None,
             shaders: HashMap::new(),
+            shader_files: HashMap::new(),
             textures: Default::default(),
+            debug_view: DebugView::default(),
+            sample_count: 1,
+            lod_mode: LodMode::default(),
+            lod_kernel: LodKernel::default(),
+            post_process: PostProcessInfo::default(),
+            color_grading: None,
+            clock: ClockMode::default(),
             last_update_instant: None,
             mouse: Vec2::ZERO,
             mouse_held: false,
@@ -113,6 +417,14 @@ impl GameRes {
         self.models = models;
     }
 
+    pub fn update_lights(&mut self, lights: Vec<LightInfo>) {
+        self.lights = lights;
+    }
+
+    pub fn set_environment(&mut self, environment: Option<Arc<EnvironmentInfo>>) {
+        self.environment = environment;
+    }
+
     pub fn set_shader(&mut self, shader_id: ShaderId, info: ShaderInfo) {
         self.shaders.insert(shader_id, info);
     }
@@ -121,6 +433,47 @@ impl GameRes {
         self.shaders.remove(shader_id);
     }
 
+    pub fn set_shader_file(&mut self, name: String, code: String) {
+        self.shader_files.insert(name, code);
+    }
+
+    pub fn remove_shader_file(&mut self, name: &str) {
+        self.shader_files.remove(name);
+    }
+
+    /// The code of a `////#include "name"` statement.
+    /// Looks for a shader file first, and then for a shader with that id or label.
+    /// Fails when several shaders have that label.
+    pub fn resolve_include(&self, name: &str) -> Result<String, String> {
+        if let Some(code) = self.shader_files.get(name).or_else(|| {
+            self.shaders
+                .get(&ShaderId(name.to_string()))
+                .map(|v| &v.code)
+        }) {
+            return Ok(code.clone());
+        }
+        let mut labelled = self.shaders.values().filter(|v| v.label == name);
+        match (labelled.next(), labelled.next()) {
+            (Some(shader), None) => Ok(shader.code.clone()),
+            (Some(_), Some(_)) => Err(format!(
+                "Ambiguous include {name}, several shaders have that label"
+            )),
+            (None, _) => Err(format!("Unknown include {name}")),
+        }
+    }
+
+    /// The shaders that include one of the names, directly or indirectly
+    pub fn shaders_including(&self, names: &[&str]) -> Vec<ShaderId> {
+        self.shaders
+            .iter()
+            .filter(|(_, info)| {
+                let includes = include_names(&info.code, |name| self.resolve_include(name));
+                names.iter().any(|name| includes.contains(*name))
+            })
+            .map(|(shader_id, _)| shader_id.clone())
+            .collect()
+    }
+
     pub fn set_texture(&mut self, id: TextureId, info: TextureInfo) {
         self.textures.insert(id, info);
     }
@@ -149,3 +502,69 @@ impl GameRes {
         self.mouse_held = inputs.mouse.pressed(winit::event::MouseButton::Left);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(id: &str, parent: Option<&str>, position: Vec3) -> ModelInfo {
+        ModelInfo {
+            id: id.to_string(),
+            transform: Transform {
+                position,
+                ..Default::default()
+            },
+            parent: parent.map(str::to_string),
+            material_info: MaterialInfo::default(),
+            shader_id: ShaderId("shader".to_string()),
+            instance_count: 1,
+            instances: vec![],
+            cast_shadows: true,
+            receive_shadows: true,
+        }
+    }
+
+    #[test]
+    fn propagates_parent_transforms() {
+        let models = [
+            model("tower-top", Some("tower-base"), Vec3::Y),
+            model("tower-base", Some("tower"), Vec3::Y),
+            model("tower", None, Vec3::X),
+            model("orphan", Some("missing"), Vec3::Z),
+        ];
+        let matrices = world_matrices(&models);
+        let position = |id: &str| matrices[id].w_axis.truncate();
+        assert_eq!(position("tower"), Vec3::X);
+        assert_eq!(position("tower-base"), Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(position("tower-top"), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(position("orphan"), Vec3::Z);
+    }
+
+    #[test]
+    fn pads_missing_instances() {
+        let mut model = model("trees", None, Vec3::ZERO);
+        model.instance_count = 3;
+        model.instances = vec![InstanceInfo {
+            tint: Vec3::X,
+            ..Default::default()
+        }];
+        let instances: Vec<_> = model.instances_padded().collect();
+        assert_eq!(instances.len(), 3);
+        assert_eq!(instances[0].tint, Vec3::X);
+        assert_eq!(instances[2], InstanceInfo::default());
+
+        model.instance_count = 0;
+        assert_eq!(model.instances_padded().count(), 0);
+    }
+
+    #[test]
+    fn breaks_cycles() {
+        let models = [
+            model("a", Some("b"), Vec3::X),
+            model("b", Some("a"), Vec3::Y),
+        ];
+        let matrices = world_matrices(&models);
+        assert_eq!(matrices["b"].w_axis.truncate(), Vec3::Y);
+        assert_eq!(matrices["a"].w_axis.truncate(), Vec3::new(1.0, 1.0, 0.0));
+    }
+}
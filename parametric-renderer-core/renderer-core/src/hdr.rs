@@ -0,0 +1,185 @@
+//! Decoder for Radiance .hdr images.
+//! See https://www.graphics.cornell.edu/~bjw/rgbe.html
+
+use anyhow::{Context, bail};
+use glam::Vec3;
+
+/// A linear, high dynamic range image
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row, starting at the top
+    pub data: Vec<Vec3>,
+}
+
+impl HdrImage {
+    /// Decodes a Radiance RGBE image. Only the standard `-Y height +X width` orientation is supported.
+    pub fn from_radiance(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        let magic = reader.line()?;
+        if magic != "#?RADIANCE" && magic != "#?RGBE" {
+            bail!("Not a Radiance HDR image");
+        }
+        loop {
+            let line = reader.line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=")
+                && format != "32-bit_rle_rgbe"
+            {
+                bail!("Unsupported HDR format {format}");
+            }
+        }
+
+        let resolution = reader.line()?;
+        let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (
+                width.parse::<u32>().context("Invalid HDR width")?,
+                height.parse::<u32>().context("Invalid HDR height")?,
+            ),
+            _ => bail!("Unsupported HDR orientation {resolution}"),
+        };
+
+        let pixel_count = width
+            .checked_mul(height)
+            .context("HDR image is too large")?;
+        let mut data = Vec::with_capacity(pixel_count as usize);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        for _ in 0..height {
+            reader.scanline(&mut scanline)?;
+            data.extend(scanline.iter().map(|rgbe| rgbe_to_linear(*rgbe)));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+fn rgbe_to_linear([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        Vec3::ZERO
+    } else {
+        let scale = 2.0f32.powi(e as i32 - (128 + 8));
+        Vec3::new(r as f32, g as f32, b as f32) * scale
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .context("Unexpected end of HDR image")?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn line(&mut self) -> anyhow::Result<&str> {
+        let rest = &self.bytes[self.position..];
+        let end = rest
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .context("Unexpected end of HDR header")?;
+        self.position += end + 1;
+        std::str::from_utf8(&rest[..end]).context("Invalid HDR header")
+    }
+
+    fn scanline(&mut self, scanline: &mut [[u8; 4]]) -> anyhow::Result<()> {
+        let width = scanline.len();
+        let rest = &self.bytes[self.position..];
+        let is_run_length_encoded = (8..0x8000).contains(&width)
+            && rest.len() >= 4
+            && rest[0] == 2
+            && rest[1] == 2
+            && rest[2] & 0x80 == 0;
+        if !is_run_length_encoded {
+            for pixel in scanline.iter_mut() {
+                *pixel = [self.byte()?, self.byte()?, self.byte()?, self.byte()?];
+            }
+            return Ok(());
+        }
+
+        let encoded_width = ((rest[2] as usize) << 8) | rest[3] as usize;
+        if encoded_width != width {
+            bail!("HDR scanline width mismatch");
+        }
+        self.position += 4;
+        // Each channel is encoded separately
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = self.byte()? as usize;
+                if count > 128 {
+                    let count = count - 128;
+                    let value = self.byte()?;
+                    if x + count > width {
+                        bail!("HDR run exceeds the scanline");
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    if count == 0 || x + count > width {
+                        bail!("Invalid HDR run");
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = self.byte()?;
+                    }
+                    x += count;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n").into_bytes()
+    }
+
+    #[test]
+    fn decodes_flat_pixels() {
+        let mut bytes = header(2, 1);
+        bytes.extend([128, 64, 0, 129, 0, 0, 0, 0]);
+        let image = HdrImage::from_radiance(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.data, vec![Vec3::new(1.0, 0.5, 0.0), Vec3::ZERO]);
+    }
+
+    #[test]
+    fn decodes_run_length_encoded_scanlines() {
+        let mut bytes = header(8, 1);
+        bytes.extend([2, 2, 0, 8]);
+        // Red is a run, green is literal, blue and exponent are runs
+        bytes.extend([128 + 8, 128]);
+        bytes.extend([8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        bytes.extend([128 + 8, 0]);
+        bytes.extend([128 + 8, 128]);
+        let image = HdrImage::from_radiance(&bytes).unwrap();
+        assert_eq!(image.data.len(), 8);
+        assert_eq!(image.data[0], Vec3::new(0.5, 0.0, 0.0));
+        assert_eq!(image.data[7], Vec3::new(0.5, 112.0 / 256.0, 0.0));
+    }
+
+    #[test]
+    fn rejects_overflowing_dimensions() {
+        let bytes = header(u32::MAX, 2);
+        assert!(HdrImage::from_radiance(&bytes).is_err());
+    }
+}
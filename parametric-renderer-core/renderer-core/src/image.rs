@@ -0,0 +1,52 @@
+//! Images that the renderer produces, for example screenshots.
+
+use anyhow::Context;
+
+/// An 8 bit sRGB image with an alpha channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA bytes, row by row, starting at the top
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Encodes the image as a PNG file
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        let mut writer = encoder
+            .write_header()
+            .context("Failed to write PNG header")?;
+        writer
+            .write_image_data(&self.data)
+            .context("Failed to write PNG data")?;
+        writer.finish().context("Failed to finish PNG")?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_round_trip() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            data: vec![255, 0, 0, 255, 0, 0, 255, 128],
+        };
+        let png = image.to_png().unwrap();
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(data, image.data);
+    }
+}
@@ -1,12 +1,17 @@
 pub mod application;
 pub mod buffer;
 pub mod camera;
+pub mod color_grading;
 pub mod game;
+pub mod hdr;
+pub mod image;
 pub mod input;
 pub mod local_executor;
 pub mod mesh;
 pub mod reactive;
 pub mod renderer;
+pub mod shader_includes;
+pub mod shader_parameters;
 pub mod shaders;
 pub mod texture;
 pub mod time;
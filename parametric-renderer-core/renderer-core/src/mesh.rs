@@ -1,7 +1,10 @@
 use glam::{Vec2, Vec3};
 use wgpu::util::DeviceExt;
 
-use crate::shaders::shader;
+use crate::{
+    reactive::{RenderCommand, ValueView},
+    shaders::shader,
+};
 
 pub struct Mesh {
     pub vertex_buffer: wgpu::Buffer,
@@ -33,6 +36,27 @@ impl Mesh {
         }
     }
 
+    /// Binds the buffers and draws the whole mesh once
+    pub fn draw_commands(&self) -> Vec<RenderCommand> {
+        vec![
+            RenderCommand::VertexBuffer {
+                slot: 0,
+                buffer: ValueView::Raw(self.vertex_buffer.clone()),
+                slice: 0..self.vertex_buffer.size(),
+            },
+            RenderCommand::IndexBuffer {
+                buffer: ValueView::Raw(self.index_buffer.clone()),
+                slice: 0..self.index_buffer.size(),
+                format: wgpu::IndexFormat::Uint16,
+            },
+            RenderCommand::DrawIndexed {
+                indices: 0..self.num_indices,
+                base_vertex: 0,
+                instances: 0..1,
+            },
+        ]
+    }
+
     /// Create a new tesselated quad mesh
     pub fn new_tesselated_quad(device: &wgpu::Device, split_count: u32) -> Self {
         let (vertices, indices) = tesselated_quad(split_count);
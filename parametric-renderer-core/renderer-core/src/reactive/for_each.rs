@@ -66,4 +66,11 @@ where
             }
         });
     }
+
+    /// Like `for_each`, but with all outputs at once. Useful for sorting them.
+    pub fn with_all(&self, callback: impl FnOnce(Vec<&Output>)) {
+        self.effect.with_value_mut(|last_run| {
+            callback(last_run.values().map(|(item, _)| item).collect());
+        });
+    }
 }
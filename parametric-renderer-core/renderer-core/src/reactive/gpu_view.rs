@@ -1,6 +1,9 @@
 // TODO: https://docs.rs/leptos/latest/leptos/trait.IntoView.html
 
-use std::{ops::Range, sync::Arc};
+use std::{
+    ops::{Deref, Range},
+    sync::Arc,
+};
 
 use reactive_graph::{
     computed::{ArcMemo, Memo},
@@ -12,14 +15,11 @@ use reactive_graph::{
     traits::{Read, ReadValue, With, WithValue},
     wrappers::read::{ArcSignal, Signal, SignalReadGuard},
 };
+use wgpu_profiler::{GpuProfiler, OwningScope};
 
-// How do we deal with the lifetimes part?
-// As in
-// - RenderPass has a lifetime
-// - Things that I add to a RenderPass also have a lifetime (like a texture view)
-//   But those things can be hidden behind a ValueView
-//   So we use with
-//        entire "loop { texture.with(|value| commands.add(texture) ) }" block
+// Commands are plain data, where each GPU object is a ValueView.
+// Recording reads the values, so a render effect re-runs when one of their signals changes,
+// and the objects themselves are only recreated by the memos that produce them.
 pub trait IntoValueView<T: Send + Sync + 'static> {
     fn into_value_view(self) -> ValueView<T>;
 }
@@ -48,6 +48,7 @@ impl<T: Send + Sync + 'static> ValueView<T> {
             ValueView::ArcStored(value) => value.with_value(fun),
         }
     }
+    /// Keeps the value borrowed, for passing references to wgpu
     pub fn read<'a>(&'a self) -> ValueGuard<'a, T> {
         match self {
             ValueView::Raw(value) => ValueGuard::Raw(value),
@@ -59,6 +60,18 @@ impl<T: Send + Sync + 'static> ValueView<T> {
     }
 }
 
+impl<T: Send + Sync + 'static> Deref for ValueGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            ValueGuard::Raw(value) => value,
+            ValueGuard::Signal(guard) => guard,
+            ValueGuard::StoredValue(guard) => guard,
+        }
+    }
+}
+
 macro_rules! impl_into_value_view {
     ($expr:expr, $($ty:ty),*) => {
         $(
@@ -130,6 +143,65 @@ impl RenderPass {
             ..self
         }
     }
+
+    pub fn commands(self, commands: impl IntoValueView<Vec<RenderCommand>>) -> Self {
+        Self {
+            commands: commands.into_value_view(),
+            ..self
+        }
+    }
+
+    /// Begins the pass with the current attachments, and records its commands into it
+    pub fn record(&self, encoder: &mut wgpu_profiler::Scope<'_, wgpu::CommandEncoder>) {
+        let label = self.label.as_ref().map(ValueView::read);
+        let label = label.as_deref().map_or("Render Pass", String::as_str);
+        let color_attachments = self.color_attachments.read();
+        let color_views = color_attachments
+            .iter()
+            .map(|attachment| {
+                (
+                    attachment.view.read(),
+                    attachment.resolve_target.as_ref().map(ValueView::read),
+                )
+            })
+            .collect::<Vec<_>>();
+        let depth_stencil_attachment = self.depth_stencil_attachment.as_ref().map(ValueView::read);
+        let depth_stencil_view = depth_stencil_attachment
+            .as_ref()
+            .map(|attachment| attachment.view.read());
+
+        let mut render_pass = encoder.scoped_render_pass(
+            label,
+            wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &color_attachments
+                    .iter()
+                    .zip(&color_views)
+                    .map(|(attachment, (view, resolve_target))| {
+                        Some(wgpu::RenderPassColorAttachment {
+                            view,
+                            resolve_target: resolve_target.as_deref(),
+                            ops: attachment.ops,
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+                depth_stencil_attachment: depth_stencil_attachment
+                    .as_ref()
+                    .zip(depth_stencil_view.as_ref())
+                    .map(
+                        |(attachment, view)| wgpu::RenderPassDepthStencilAttachment {
+                            view,
+                            depth_ops: attachment.depth_ops,
+                            stencil_ops: attachment.stencil_ops,
+                        },
+                    ),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            },
+        );
+        self.commands
+            .with(|commands| record_render_commands(commands, &mut render_pass));
+    }
 }
 
 pub struct ColorAttachment {
@@ -144,11 +216,18 @@ pub struct DepthStencilAttachment {
     pub stencil_ops: Option<wgpu::Operations<u32>>,
 }
 
+pub type RenderAction = Arc<dyn Fn(&mut wgpu::RenderPass<'_>) + Send + Sync>;
+type UpdateFn<Input> = Box<dyn Fn(&Input) + Send + Sync>;
+
 pub enum RenderCommand {
-    Scope(String),
+    /// Profiles the nested commands
+    Scope {
+        label: String,
+        commands: Vec<RenderCommand>,
+    },
     SetPipeline(ValueView<wgpu::RenderPipeline>),
     /// Use this for "set_bind_groups"
-    DoAction(ValueView<Arc<dyn Fn(&mut RenderPass) + Send + Sync>>),
+    DoAction(ValueView<RenderAction>),
     VertexBuffer {
         slot: u32,
         buffer: ValueView<wgpu::Buffer>,
@@ -159,80 +238,105 @@ pub enum RenderCommand {
         slice: Range<wgpu::BufferAddress>,
         format: wgpu::IndexFormat,
     },
+    Draw {
+        vertices: Range<u32>,
+        instances: Range<u32>,
+    },
+    DrawIndexed {
+        indices: Range<u32>,
+        base_vertex: i32,
+        instances: Range<u32>,
+    },
     DrawIndexedIndirect {
         buffer: ValueView<wgpu::Buffer>,
         offset: wgpu::BufferAddress,
     },
 }
 
-/*
-/*
-pub struct ComputePass {
-    pub label: Option<ValueView<String>>,
-    pub descriptor: ComputePassDescriptor,
-    pub commands: Vec<ComputeCommand>,
-}
+impl RenderCommand {
+    /// The bind groups of wgsl_to_wgpu can only be set by themselves, so they go through this
+    pub fn action(action: impl Fn(&mut wgpu::RenderPass<'_>) + Send + Sync + 'static) -> Self {
+        RenderCommand::DoAction(ValueView::Raw(Arc::new(action)))
+    }
 
-impl ComputePass {
-    pub fn label(self, label: impl IntoValueView<String>) -> Self {
-        Self {
-            label: Some(label.into_value_view()),
-            ..self
+    pub fn record(&self, render_pass: &mut wgpu::RenderPass<'_>, profiler: &GpuProfiler) {
+        match self {
+            RenderCommand::Scope { label, commands } => {
+                let mut scope = profiler.scope(label.as_str(), render_pass);
+                for command in commands {
+                    command.record(&mut scope, profiler);
+                }
+            }
+            RenderCommand::SetPipeline(pipeline) => {
+                pipeline.with(|pipeline| render_pass.set_pipeline(pipeline))
+            }
+            RenderCommand::DoAction(action) => action.with(|action| action(render_pass)),
+            RenderCommand::VertexBuffer {
+                slot,
+                buffer,
+                slice,
+            } => buffer
+                .with(|buffer| render_pass.set_vertex_buffer(*slot, buffer.slice(slice.clone()))),
+            RenderCommand::IndexBuffer {
+                buffer,
+                slice,
+                format,
+            } => buffer
+                .with(|buffer| render_pass.set_index_buffer(buffer.slice(slice.clone()), *format)),
+            RenderCommand::Draw {
+                vertices,
+                instances,
+            } => render_pass.draw(vertices.clone(), instances.clone()),
+            RenderCommand::DrawIndexed {
+                indices,
+                base_vertex,
+                instances,
+            } => render_pass.draw_indexed(indices.clone(), *base_vertex, instances.clone()),
+            RenderCommand::DrawIndexedIndirect { buffer, offset } => {
+                buffer.with(|buffer| render_pass.draw_indexed_indirect(buffer, *offset))
+            }
         }
     }
 }
 
-pub struct ComputePassDescriptor {
-    pub bind_groups: Vec<BindGroup>,
-}
-
-     */
-
-trait IntoGpuView {
-    fn into_gpu_view(self) -> GpuView;
+/// For render passes that are started by hand, and shared by several components
+pub fn record_render_commands(
+    commands: &[RenderCommand],
+    render_pass: &mut OwningScope<'_, wgpu::RenderPass<'_>>,
+) {
+    for command in commands {
+        command.record(&mut render_pass.recorder, render_pass.profiler);
+    }
 }
 
-/// A list of commands that can be executed on the GPU.
-/// This data structure is the target of IntoGpuView.
+/// Draws into a render pass that it shares with other components, like the main pass
 #[must_use]
-pub enum GpuView {
-    RenderPass { commands: Vec<RenderCommandView> },
-    ComputePass { commands: Vec<ComputeCommandView> },
+pub struct RenderComponent<Input> {
+    pub commands: ValueView<Vec<RenderCommand>>,
+    /// Runs before the commands are recorded, for writing the uniforms of the frame
+    pub update: Option<UpdateFn<Input>>,
 }
 
-pub enum RenderCommandView {
-    SetPipeline {
-        pipeline: PipelineView,
-    },
-    SetBindGroup {
-        index: u32,
-        bind_group: BindGroupView,
-    },
-    SetVertexBuffer {
-        index: u32,
-        buffer: BufferView,
-    },
-    SetIndexBuffer {
-        buffer: BufferView,
-    },
-    Draw {
-        vertices: Range<u32>,
-        instances: Range<u32>,
-    },
-}
+impl<Input> RenderComponent<Input> {
+    pub fn new(commands: impl IntoValueView<Vec<RenderCommand>>) -> Self {
+        Self {
+            commands: commands.into_value_view(),
+            update: None,
+        }
+    }
 
-pub enum ComputeCommandView {
-    SetPipeline {
-        pipeline: PipelineView,
-    },
-    SetBindGroup {
-        index: u32,
-        bind_group: BindGroupView,
-    },
-    Dispatch {
-        x: u32,
-        y: u32,
-        z: u32,
-    },
+    pub fn on_update(self, update: impl Fn(&Input) + Send + Sync + 'static) -> Self {
+        Self {
+            update: Some(Box::new(update)),
+            ..self
+        }
+    }
+
+    pub fn record(&self, input: &Input, render_pass: &mut OwningScope<'_, wgpu::RenderPass<'_>>) {
+        if let Some(update) = &self.update {
+            update(input);
+        }
+        self.commands
+            .with(|commands| record_render_commands(commands, render_pass));
+    }
 }
- */
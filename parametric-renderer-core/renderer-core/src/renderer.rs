@@ -1,19 +1,36 @@
+mod capture;
+mod environment;
 mod frame_data;
 mod ground_plane;
+mod object_id_resolve;
+mod pipeline_cache;
+mod post_process;
 mod scene;
+mod sequence;
+mod shader_assembly;
+mod shadows;
 mod skybox;
 mod virtual_model;
 mod wgpu_context;
 
-pub use frame_data::FrameData;
+pub use frame_data::{FrameData, FrameTile};
 use ground_plane::ground_plane_component;
+use object_id_resolve::object_id_resolve_component;
+use post_process::{HDR_FORMAT, post_process_component};
 use skybox::skybox_component;
 use wgpu::BufferUsages;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+    },
+};
 
 use encase::ShaderType;
-use glam::UVec2;
+use glam::{Mat4, UVec2, Vec3};
+use log::warn;
 
 use reactive_graph::{
     computed::Memo,
@@ -25,28 +42,41 @@ use reactive_graph::{
     },
 };
 use scene::SceneData;
-use virtual_model::{ShaderPipelines, VirtualModel, make_empty_texture, make_missing_shader};
+pub use sequence::{SequenceSettings, Turntable};
+use uuid::Uuid;
+use virtual_model::{
+    RenderPipelines, ShaderPipelines, ShaderSource, VirtualModel, make_empty_texture,
+    make_missing_shader,
+};
 use wgpu_context::{SurfaceOrFallback, WgpuContext, create_profiler};
 use wgpu_profiler::GpuProfiler;
 
 use crate::{
     application::ShaderCompiledCallback,
     buffer::{CommandEncoderBufferExt, DeviceBufferExt, TypedBuffer},
-    game::{GameRes, MaterialInfo, ModelInfo, ShaderId, TextureId, TextureInfo},
+    color_grading::ColorGradingLut,
+    game::{
+        DebugView, EnvironmentInfo, GameRes, InstanceInfo, LightInfo, LodKernel, LodMode,
+        MaterialInfo, ModelInfo, PostProcessInfo, ShaderId, TextureId, TextureInfo,
+    },
+    image::Image,
     input::WindowCursorCapture,
     mesh::Mesh,
     reactive::{ForEach, MemoComputed, SignalVec},
-    shaders::{compute_patches, copy_patches, shader},
-    texture::Texture,
-    time::{FrameCounter, Seconds},
+    shader_parameters::{ParameterValues, ShaderParameters},
+    shaders::{compute_patches, copy_patches, persistent_patches, reuse_patches, shader},
+    texture::{MipmapGenerator, SampledTexture, Texture},
+    time::{ClockMode, FrameCounter, Seconds},
+    transform::normal_matrix,
     window_or_fallback::WindowOrFallback,
 };
 struct ComputePatchesStep {
     bind_group_0: compute_patches::bind_groups::BindGroup0,
     patches_buffer_reset: TypedBuffer<compute_patches::Patches>,
-    indirect_compute_buffer_reset: TypedBuffer<compute_patches::DispatchIndirectArgs>,
+    reuse_patches_pipeline: wgpu::ComputePipeline,
     force_render_false: TypedBuffer<compute_patches::ForceRenderFlag>,
     force_render_true: TypedBuffer<compute_patches::ForceRenderFlag>,
+    persistent_bind_group_0: persistent_patches::bind_groups::BindGroup0,
 }
 #[must_use]
 pub struct GpuApplicationBuilder {
@@ -72,28 +102,63 @@ pub struct GpuApplication {
     surface: RwSignal<SurfaceOrFallback>,
     profiler: StoredValue<GpuProfiler>,
     profiling_enabled: bool,
+    frame_counter: StoredValue<FrameCounter>,
     runtime: Option<Owner>,
     render_effect: RenderEffect<Result<Option<RenderResults>, wgpu::SurfaceError>>,
+    render_data: ArcReadSignal<FrameData>,
     set_render_data: ArcWriteSignal<FrameData>,
     shaders: RwSignal<HashMap<ShaderId, Arc<ShaderPipelines>>>,
-    textures: RwSignal<HashMap<TextureId, Arc<Texture>>>,
+    /// Shaders whose pipelines are being created, with the ID of the latest request.
+    /// Until that finishes, the previous pipelines keep rendering.
+    compiling: RwSignal<HashMap<ShaderId, Uuid>>,
+    textures: RwSignal<HashMap<TextureId, Arc<SampledTexture>>>,
+    /// Values of the shader parameters, by model ID
+    model_parameters: RwSignal<HashMap<String, ParameterValues>>,
     set_desired_size: WriteSignal<UVec2>,
     set_force_wait: WriteSignal<bool>,
     /// Sets the threshold factor for the LOD algorithm
     set_threshold_factor: WriteSignal<f32>,
+    set_lod_mode: WriteSignal<LodMode>,
+    set_lod_kernel: WriteSignal<LodKernel>,
     /// Sets the value for hot slider updates
     set_hot_value: WriteSignal<f32>,
     cursor_capture: WindowCursorCapture,
     models: SignalVec<ModelInfo>,
+    set_lights: WriteSignal<Vec<LightInfo>>,
+    set_environment: WriteSignal<Option<Arc<EnvironmentInfo>>>,
+    set_debug_view: WriteSignal<DebugView>,
+    sample_count: ReadSignal<u32>,
+    set_sample_count: WriteSignal<u32>,
+    /// The last sample count that was asked for, which takes effect once its pipelines are ready
+    requested_sample_count: StoredValue<u32>,
+    set_post_process: WriteSignal<PostProcessInfo>,
+    set_color_grading: WriteSignal<Option<Arc<ColorGradingLut>>>,
+    mipmap_generator: MipmapGenerator,
+    missing_shader: Arc<ShaderPipelines>,
+}
+
+/// Compilations that finish after a newer one started are outdated
+fn is_latest_request(
+    compiling: RwSignal<HashMap<ShaderId, Uuid>>,
+    shader_id: &ShaderId,
+    request_id: Uuid,
+) -> bool {
+    compiling.with_untracked(|compiling| compiling.get(shader_id) == Some(&request_id))
 }
 
 const PATCH_SIZES: [u32; 5] = [2, 4, 8, 16, 32];
 const MAX_PATCH_COUNT: u32 = 524_288;
+/// Slots in the hash map of the subdivision tree, which is used for stitching neighbouring patches
+const LOD_TREE_CAPACITY: u32 = 262_144;
+/// How many workgroups the persistent LOD kernel launches. They stay alive until the queue is empty.
+const PERSISTENT_WORKGROUPS: u32 = 256;
+/// Deeper patches would be smaller than the precision of the stitching in the render shader
+const MAX_LOD_DEPTH: u32 = 16;
 
 #[derive(Clone)]
 struct MissingShader(Arc<ShaderPipelines>);
 #[derive(Clone)]
-struct EmptyTexture(Arc<Texture>);
+struct EmptyTexture(Arc<SampledTexture>);
 
 impl GpuApplication {
     pub fn new(context: WgpuContext, surface: SurfaceOrFallback) -> Self {
@@ -104,50 +169,100 @@ impl GpuApplication {
         let (desired_size, set_desired_size) = signal(surface.size());
         let surface = RwSignal::new(surface);
         let profiler = StoredValue::new(create_profiler(&context));
+        let frame_counter = StoredValue::new(FrameCounter::new());
         let (force_wait, set_force_wait) = signal(false);
         let (threshold_factor, set_threshold_factor) = signal(1.0f32);
+        let (lod_mode, set_lod_mode) = signal(LodMode::default());
+        let (lod_kernel, set_lod_kernel) = signal(LodKernel::default());
         let (hot_value, set_hot_value) = signal(0.0f32);
         let models = SignalVec::new();
-
-        provide_context(MissingShader(make_missing_shader(&context)));
-        provide_context(EmptyTexture(make_empty_texture(&context)));
+        let (lights, set_lights) = signal(LightInfo::default_lights());
+        let (environment, set_environment) = signal(None);
+        let (debug_view, set_debug_view) = signal(DebugView::None);
+        let (sample_count, set_sample_count) = signal(1);
+        let (post_process, set_post_process) = signal(PostProcessInfo::default());
+        let (color_grading, set_color_grading) = signal(None);
+
+        let missing_shader = make_missing_shader(&context);
+        provide_context(MissingShader(missing_shader.clone()));
+        let mipmap_generator = MipmapGenerator::new(&context.device);
+        provide_context(EmptyTexture(make_empty_texture(
+            &context,
+            &mipmap_generator,
+        )));
         let shaders = RwSignal::new(HashMap::new());
+        let compiling = RwSignal::new(HashMap::new());
         let textures = RwSignal::new(HashMap::new());
+        let model_parameters = RwSignal::new(HashMap::new());
 
         let render_tree = Owner::with(&runtime, || {
             Arc::new(render_component(
                 surface,
                 profiler,
-                desired_size,
-                threshold_factor,
-                hot_value,
-                force_wait,
-                shaders,
-                textures,
-                models.clone(),
+                frame_counter,
+                RenderSettings {
+                    desired_size,
+                    lod_settings: LodSettings {
+                        threshold_factor,
+                        mode: lod_mode,
+                        kernel: lod_kernel,
+                    },
+                    hot_value,
+                    force_wait,
+                    debug_view,
+                    sample_count,
+                    post_process,
+                    color_grading,
+                },
+                SharedResources { shaders, textures },
+                SceneInputs {
+                    model_parameters,
+                    models: models.clone(),
+                    lights,
+                    environment,
+                },
             ))
         });
 
         let (render_data, set_render_data) = arc_signal(FrameData::default());
-        let render_effect = RenderEffect::new(move |_| (render_tree)(&render_data.read()));
+        let render_effect = RenderEffect::new({
+            let render_data = render_data.clone();
+            move |_| (render_tree)(&render_data.read())
+        });
 
         Self {
             context,
             surface,
             profiler,
             profiling_enabled: false,
+            frame_counter,
             runtime: Some(runtime),
             render_effect,
+            render_data,
             set_render_data,
             shaders,
+            compiling,
             textures,
+            model_parameters,
 
             set_desired_size,
             set_threshold_factor,
+            set_lod_mode,
+            set_lod_kernel,
             set_hot_value,
             set_force_wait,
             cursor_capture: WindowCursorCapture::Free,
             models,
+            set_lights,
+            set_environment,
+            set_debug_view,
+            sample_count,
+            set_sample_count,
+            requested_sample_count: StoredValue::new(1),
+            set_post_process,
+            set_color_grading,
+            mipmap_generator,
+            missing_shader,
         }
     }
 
@@ -171,36 +286,119 @@ impl GpuApplication {
         });
     }
 
+    pub fn update_lights(&self, lights: &[LightInfo]) {
+        self.set_lights.set(lights.to_vec());
+    }
+
+    /// Rebakes the environment maps, which is expensive.
+    /// Fails for images that are larger than the textures of the device.
+    pub fn set_environment(&self, environment: Option<Arc<EnvironmentInfo>>) -> anyhow::Result<()> {
+        if let Some(environment) = &environment {
+            let max_size = self.context.device.limits().max_texture_dimension_2d;
+            let (width, height) = (environment.image.width, environment.image.height);
+            if width > max_size || height > max_size {
+                anyhow::bail!(
+                    "The environment is {width}x{height} pixels, but the GPU supports at most {max_size} pixels per side"
+                );
+            }
+        }
+        self.set_environment.set(environment);
+        Ok(())
+    }
+
+    /// `resolve_include` looks up the code of the `////#include` statements.
+    /// The pipelines are created in the returned future, and replace the old ones once they are ready.
+    /// When the shader gets set again in the meantime, only the latest version is kept and reported.
     pub fn set_shader(
         &self,
         shader_id: ShaderId,
         info: &crate::game::ShaderInfo,
+        resolve_include: &dyn Fn(&str) -> Result<String, String>,
         on_shader_compiled: Option<ShaderCompiledCallback>,
     ) -> impl Future<Output = ()> + use<> {
         let shaders = self.shaders;
-        let new_shaders = ShaderPipelines::new(&info.label, &info.code, &self.context);
+        let compiling = self.compiling;
+        let context = self.context.clone();
+        let sample_count = self.sample_count.get_untracked();
+        let request_id = Uuid::new_v4();
+        compiling.update(|compiling| {
+            compiling.insert(shader_id.clone(), request_id);
+        });
+        let source = ShaderSource::new(
+            info.label.clone(),
+            shader_id.0.clone(),
+            info.code.clone(),
+            resolve_include,
+        );
         async move {
-            let compilation_results = new_shaders.get_compilation_info().await;
-            let is_error = compilation_results
-                .iter()
-                .any(|v| v.message_type == wgpu::CompilationMessageType::Error);
-            on_shader_compiled.map(|f| (f.0)(&shader_id, compilation_results));
-            if !is_error {
-                shaders.update(move |shaders| {
-                    shaders.insert(shader_id, Arc::new(new_shaders));
-                });
+            let compiled = ShaderPipelines::compile(source, sample_count, context).await;
+            if !is_latest_request(compiling, &shader_id, request_id) {
+                return;
+            }
+            let compilation_results = match compiled {
+                Ok((new_shaders, compilation_results)) => {
+                    let is_error = compilation_results
+                        .iter()
+                        .any(|v| v.message_type == wgpu::CompilationMessageType::Error);
+                    if !is_error {
+                        shaders.update(|shaders| {
+                            shaders.insert(shader_id.clone(), Arc::new(new_shaders));
+                        });
+                    }
+                    compilation_results
+                }
+                Err(error) => vec![error],
+            };
+            compiling.update(|compiling| {
+                compiling.remove(&shader_id);
+            });
+            if let Some(f) = on_shader_compiled {
+                (f.0)(&shader_id, compilation_results);
             }
         }
     }
 
+    /// Whether new pipelines are being created for the shader. The previous ones are used meanwhile.
+    pub fn is_shader_compiling(&self, shader_id: &ShaderId) -> bool {
+        self.compiling
+            .with_untracked(|compiling| compiling.contains_key(shader_id))
+    }
+
+    pub fn compiling_shaders(&self) -> Vec<ShaderId> {
+        self.compiling
+            .with_untracked(|compiling| compiling.keys().cloned().collect())
+    }
+
+    /// The `// @param` annotations of the last version of the shader that compiled
+    pub fn shader_parameters(&self, shader_id: &ShaderId) -> Option<ShaderParameters> {
+        self.shaders
+            .with_untracked(|shaders| shaders.get(shader_id).map(|v| v.parameters.clone()))
+    }
+
+    /// Missing values use the defaults of the shader
+    pub fn set_model_parameters(&self, model_id: &str, values: ParameterValues) {
+        self.model_parameters.update(|parameters| {
+            parameters.insert(model_id.to_string(), values);
+        });
+    }
+
     pub fn remove_shader(&self, shader_id: &ShaderId) {
         self.shaders.update(|shaders| {
             shaders.remove(shader_id);
         });
+        // A compilation that is still running gets discarded
+        self.compiling.update(|compiling| {
+            compiling.remove(shader_id);
+        });
     }
 
     pub fn set_texture(&mut self, id: TextureId, info: &TextureInfo) {
-        let texture = Texture::new_rgba(&self.context.device, &self.context.queue, info);
+        let texture = SampledTexture::new(
+            &self.context.device,
+            &self.context.queue,
+            info,
+            &self.mipmap_generator,
+        );
         self.textures.update(move |textures| {
             textures.insert(id, Arc::new(texture));
         });
@@ -231,6 +429,7 @@ impl GpuApplication {
             mouse_pos: game.mouse,
             mouse_held: game.mouse_held,
             lod_stage: game.lod_stage.clone(),
+            tile: None,
         });
         any_spawner::Executor::poll_local();
         self.render_effect
@@ -238,6 +437,94 @@ impl GpuApplication {
             .expect("Render effect should have re-executed")
     }
 
+    /// Renders the current frame again, at `scale` times the window resolution.
+    /// Images that are too large for the GPU are rendered in tiles.
+    pub fn capture_frame(&self, scale: f32) -> impl Future<Output = anyhow::Result<Image>> + use<> {
+        let window_size = self.surface.with_untracked(|surface| surface.size());
+        let full_size = (window_size.as_vec2() * scale)
+            .round()
+            .as_uvec2()
+            .max(UVec2::ONE);
+        self.capture(full_size, self.render_data.get_untracked())
+    }
+
+    /// Renders one frame of an animation. This pauses the clock at the time of that frame.
+    pub fn capture_sequence_frame(
+        &self,
+        settings: &SequenceSettings,
+        frame: u32,
+    ) -> impl Future<Output = anyhow::Result<Image>> + use<> {
+        self.set_clock(ClockMode::Paused);
+        self.set_elapsed(settings.frame_time(frame));
+        let mut frame_data = self.render_data.get_untracked();
+        frame_data.camera = settings.camera(&frame_data.camera, frame);
+        self.capture(settings.size.max(UVec2::ONE), frame_data)
+    }
+
+    fn capture(
+        &self,
+        full_size: UVec2,
+        frame_data: FrameData,
+    ) -> impl Future<Output = anyhow::Result<Image>> + use<> {
+        let context = self.context.clone();
+        let tile_size = capture::tile_size(&context.device, full_size);
+        let previous_frame_data = self.render_data.get_untracked();
+
+        let capture_surface = SurfaceOrFallback::new_fallback(&context, tile_size);
+        let SurfaceOrFallback::Fallback {
+            texture: capture_texture,
+            ..
+        } = &capture_surface
+        else {
+            unreachable!("Expected an offscreen texture");
+        };
+        let capture_texture = capture_texture.clone();
+        let window_surface = self
+            .surface
+            .try_update(|surface| std::mem::replace(surface, capture_surface))
+            .expect("Surface should exist");
+
+        // All tiles have to show the same point in time
+        let clock = self.clock();
+        self.set_clock(ClockMode::Paused);
+        let tiles = capture::tiles(full_size, tile_size)
+            .map(|tile| {
+                self.set_render_data.set(FrameData {
+                    tile: Some(tile),
+                    ..frame_data.clone()
+                });
+                any_spawner::Executor::poll_local();
+                let _ = self.render_effect.take_value();
+                capture::read_tile(&context, &capture_texture, tile)
+            })
+            .collect::<Vec<_>>();
+        self.set_clock(clock);
+
+        self.surface.set(window_surface);
+        self.set_render_data.set(previous_frame_data);
+        context.device.poll(wgpu::Maintain::Wait);
+
+        let format = context.view_format;
+        async move { capture::assemble(full_size, format, tiles).await }
+    }
+
+    pub fn clock(&self) -> ClockMode {
+        self.frame_counter
+            .with_value(|frame_counter| frame_counter.mode)
+    }
+
+    /// Decides how the time advances, which animated shaders read
+    pub fn set_clock(&self, mode: ClockMode) {
+        self.frame_counter
+            .update_value(|frame_counter| frame_counter.set_mode(mode));
+    }
+
+    /// Jumps to a point in time
+    pub fn set_elapsed(&self, elapsed: Seconds) {
+        self.frame_counter
+            .update_value(|frame_counter| frame_counter.set_elapsed(elapsed));
+    }
+
     pub fn resize(&self, new_size: UVec2) {
         self.set_desired_size.set(new_size);
     }
@@ -251,10 +538,66 @@ impl GpuApplication {
             .set(factor.clamp(0.0001, 100000.0));
     }
 
+    pub fn set_lod_mode(&self, lod_mode: LodMode) {
+        self.set_lod_mode.set(lod_mode);
+    }
+
+    pub fn set_lod_kernel(&self, lod_kernel: LodKernel) {
+        self.set_lod_kernel.set(match lod_kernel {
+            LodKernel::Rounds => LodKernel::Rounds,
+            LodKernel::Persistent { max_depth } => LodKernel::Persistent {
+                max_depth: max_depth.clamp(1, MAX_LOD_DEPTH),
+            },
+        });
+    }
+
     pub fn set_hot_value(&self, hot_value: f32) {
         self.set_hot_value.set(hot_value);
     }
 
+    pub fn set_debug_view(&self, debug_view: DebugView) {
+        self.set_debug_view.set(debug_view);
+    }
+
+    /// Sets the MSAA sample count of the main render pass.
+    /// Falls back to the highest supported count that does not exceed the requested one.
+    /// The render pipelines for it are created in the background, and the old count stays in use until then.
+    pub fn set_sample_count(&self, sample_count: u32) {
+        let sample_count = self
+            .context
+            .supported_sample_counts
+            .iter()
+            .copied()
+            .filter(|&count| count <= sample_count)
+            .max()
+            .unwrap_or(1);
+        self.requested_sample_count.set_value(sample_count);
+        let preparing = self.shaders.with_untracked(|shaders| {
+            shaders
+                .values()
+                .chain([&self.missing_shader])
+                .map(|shader| shader.prepare_render_pipelines(sample_count, self.context.clone()))
+                .collect::<Vec<_>>()
+        });
+        let requested_sample_count = self.requested_sample_count;
+        let set_sample_count = self.set_sample_count;
+        any_spawner::Executor::spawn_local(async move {
+            futures::future::join_all(preparing).await;
+            if requested_sample_count.get_value() == sample_count {
+                set_sample_count.set(sample_count);
+            }
+        });
+    }
+
+    pub fn set_post_process(&self, post_process: PostProcessInfo) {
+        self.set_post_process.set(post_process);
+    }
+
+    /// Applies a lookup table after tone mapping, or disables color grading with `None`
+    pub fn set_color_grading(&self, color_grading: Option<Arc<ColorGradingLut>>) {
+        self.set_color_grading.set(color_grading);
+    }
+
     fn update_cursor_capture(&mut self, cursor_capture: WindowCursorCapture) {
         if let Some(window) = self.surface.with_untracked(|surface| match surface {
             wgpu_context::SurfaceOrFallback::Surface { window, .. } => Some(window.clone()),
@@ -278,42 +621,178 @@ pub fn get_context() -> Arc<WgpuContext> {
     expect_context::<Arc<WgpuContext>>()
 }
 
-/// We're using Leptos :)
-fn render_component(
-    surface: RwSignal<SurfaceOrFallback>,
-    profiler: StoredValue<GpuProfiler>,
+/// Color targets of the main render pass, which renders HDR colors and object IDs
+fn main_pass_targets(blend: Option<wgpu::BlendState>) -> [Option<wgpu::ColorTargetState>; 2] {
+    [
+        Some(wgpu::ColorTargetState {
+            format: HDR_FORMAT,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        }),
+        Some(wgpu::ColorTargetState {
+            format: wgpu::TextureFormat::R32Uint,
+            blend: None,
+            write_mask: wgpu::ColorWrites::empty(),
+        }),
+    ]
+}
+
+/// The settings of the renderer, as set through [`GpuApplication`]
+#[derive(Clone, Copy)]
+struct RenderSettings {
     desired_size: ReadSignal<UVec2>,
-    threshold_factor: ReadSignal<f32>,
+    lod_settings: LodSettings,
     hot_value: ReadSignal<f32>,
     force_wait: ReadSignal<bool>,
+    debug_view: ReadSignal<DebugView>,
+    sample_count: ReadSignal<u32>,
+    post_process: ReadSignal<PostProcessInfo>,
+    color_grading: ReadSignal<Option<Arc<ColorGradingLut>>>,
+}
+
+/// The shaders and textures that models refer to by their IDs
+#[derive(Clone, Copy)]
+struct SharedResources {
     shaders: RwSignal<HashMap<ShaderId, Arc<ShaderPipelines>>>,
-    textures: RwSignal<HashMap<TextureId, Arc<Texture>>>,
+    textures: RwSignal<HashMap<TextureId, Arc<SampledTexture>>>,
+}
+
+/// Everything that is in the scene
+struct SceneInputs {
+    model_parameters: RwSignal<HashMap<String, ParameterValues>>,
     models: SignalVec<ModelInfo>,
+    lights: ReadSignal<Vec<LightInfo>>,
+    environment: ReadSignal<Option<Arc<EnvironmentInfo>>>,
+}
+
+/// We're using Leptos :)
+fn render_component(
+    surface: RwSignal<SurfaceOrFallback>,
+    profiler: StoredValue<GpuProfiler>,
+    frame_counter: StoredValue<FrameCounter>,
+    settings: RenderSettings,
+    resources: SharedResources,
+    scene: SceneInputs,
 ) -> impl Fn(&FrameData) -> Result<Option<RenderResults>, wgpu::SurfaceError> {
+    let RenderSettings {
+        desired_size,
+        lod_settings,
+        hot_value,
+        force_wait,
+        debug_view,
+        sample_count,
+        post_process,
+        color_grading,
+    } = settings;
+    let SceneInputs {
+        model_parameters,
+        models,
+        lights,
+        environment,
+    } = scene;
     let context = &get_context();
-    let frame_counter = RwSignal::new(FrameCounter::new());
-    let new_frame_time = move || frame_counter.write().new_frame();
+    let new_frame_time = move || frame_counter.write_value().new_frame();
 
     let depth_texture = Memo::new_computed(move |_| {
         Texture::create_depth_texture(
             &get_context().device,
             surface.read().size(),
+            sample_count.get(),
             "Depth Texture",
         )
     });
 
+    // The main render pass renders into this, and post processing turns it into the final image
+    let hdr_texture = Memo::new_computed(move |_| {
+        Texture::create_render_target(
+            &get_context().device,
+            surface.read().size(),
+            HDR_FORMAT,
+            1,
+            "HDR Texture",
+        )
+    });
+
+    // Multisampled textures cannot be sampled, so we render into this one and resolve it
+    let multisampled_texture = Memo::new_computed(move |_| {
+        let context = &get_context();
+        let sample_count = sample_count.get();
+        (sample_count > 1).then(|| {
+            Texture::create_multisampled_texture(
+                &context.device,
+                surface.read().size(),
+                HDR_FORMAT,
+                sample_count,
+                "Multisampled Texture",
+            )
+        })
+    });
+
     let object_id_texture = Memo::new_computed(move |_| {
         Texture::create_object_id_texture(
             &get_context().device,
             surface.read().size(),
+            1,
             "Object ID Texture",
         )
     });
+    // Rendered alongside the multisampled texture, and then copied into the object ID texture
+    let multisampled_object_id_texture = Memo::new_computed(move |_| {
+        let sample_count = sample_count.get();
+        (sample_count > 1).then(|| {
+            Texture::create_object_id_texture(
+                &get_context().device,
+                surface.read().size(),
+                sample_count,
+                "Multisampled Object ID Texture",
+            )
+        })
+    });
+    let object_id_resolve_component =
+        object_id_resolve_component(object_id_texture, multisampled_object_id_texture);
 
-    let scene_data = StoredValue::new(SceneData::new(&context.device));
+    let scene_data = StoredValue::new(SceneData::new(&context.device, &context.queue));
     let render_bind_group_0 = StoredValue::new(
         scene_data.with_value(|scene_data| scene_data.as_bind_group_0(&context.device)),
     );
+    let shadow_bind_groups_0 = StoredValue::new(
+        scene_data.with_value(|scene_data| scene_data.as_shadow_bind_groups_0(&context.device)),
+    );
+
+    Effect::new(move |_| {
+        let context = &get_context();
+        let reallocated = lights.with(|lights| {
+            scene_data
+                .write_value()
+                .update_lights(lights, &context.device, &context.queue)
+        });
+        if reallocated {
+            scene_data.with_value(|scene_data| {
+                render_bind_group_0.set_value(scene_data.as_bind_group_0(&context.device));
+                shadow_bind_groups_0.set_value(scene_data.as_shadow_bind_groups_0(&context.device));
+            });
+        }
+    });
+
+    Effect::new(move |previous: Option<()>| {
+        let context = &get_context();
+        environment.with(|environment| {
+            let scene_data = scene_data.read_value();
+            match environment {
+                Some(environment) => scene_data.environment.bake(
+                    &context.device,
+                    &context.queue,
+                    &environment.image,
+                    environment.intensity,
+                ),
+                // The default sky has already been baked on creation
+                None if previous.is_none() => {}
+                None => scene_data
+                    .environment
+                    .bake_default_sky(&context.device, &context.queue),
+            }
+        });
+    });
 
     let compute_patches = StoredValue::new(ComputePatchesStep {
         bind_group_0: scene_data.with_value(|scene_data| {
@@ -331,6 +810,10 @@ fn render_component(
             &context.device,
             "Patches Buffer Reset",
             &compute_patches::Patches {
+                // We only write to x. y and z have their default value.
+                dispatch_x: 0,
+                dispatch_y: 1,
+                dispatch_z: 1,
                 patches_length: 0,
                 patches_capacity: MAX_PATCH_COUNT,
                 patches: vec![],
@@ -338,12 +821,15 @@ fn render_component(
             1,
             wgpu::BufferUsages::COPY_SRC,
         ),
-        indirect_compute_buffer_reset: TypedBuffer::new_storage(
-            &context.device,
-            "Indirect Compute Dispatch Buffer Reset",
-            // We only write to x. y and z have their default value.
-            &compute_patches::DispatchIndirectArgs { x: 0, y: 1, z: 1 },
-            wgpu::BufferUsages::COPY_SRC,
+        reuse_patches_pipeline: context.device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some("Reuse Patches"),
+                layout: Some(&reuse_patches::create_pipeline_layout(&context.device)),
+                module: &reuse_patches::create_shader_module(&context.device),
+                entry_point: Some(reuse_patches::ENTRY_MAIN),
+                compilation_options: Default::default(),
+                cache: Default::default(),
+            },
         ),
         force_render_false: TypedBuffer::new_uniform(
             &context.device,
@@ -357,6 +843,17 @@ fn render_component(
             &compute_patches::ForceRenderFlag { flag: 1 },
             wgpu::BufferUsages::COPY_SRC,
         ),
+        persistent_bind_group_0: scene_data.with_value(|scene_data| {
+            persistent_patches::bind_groups::BindGroup0::from_bindings(
+                &context.device,
+                persistent_patches::bind_groups::BindGroupLayout0 {
+                    mouse: scene_data.mouse_buffer.as_entire_buffer_binding(),
+                    screen: scene_data.screen_buffer.as_entire_buffer_binding(),
+                    time: scene_data.time_buffer.as_entire_buffer_binding(),
+                    extra: scene_data.extra_buffer.as_entire_buffer_binding(),
+                },
+            )
+        }),
     });
 
     let copy_patches_pipeline = StoredValue::new(context.device.create_compute_pipeline(
@@ -386,6 +883,28 @@ fn render_component(
             .map(|splits| Mesh::new_tesselated_quad(&context.device, splits))
             .collect::<Vec<_>>(),
     );
+    let patch_infos = StoredValue::new(
+        PATCH_SIZES
+            .iter()
+            .map(|size| {
+                context.device.uniform_buffer(
+                    &format!("Patch Info {size}"),
+                    &shader::PatchInfo {
+                        size: *size,
+                        quads_per_side: *size / 2,
+                    },
+                    wgpu::BufferUsages::empty(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    Effect::new(move |_| {
+        let context = &get_context();
+        scene_data
+            .read_value()
+            .set_debug_view(debug_view.get(), &context.queue);
+    });
 
     Effect::new(move |_| {
         let context = &get_context();
@@ -398,8 +917,18 @@ fn render_component(
         );
     });
 
-    let skybox_component = skybox_component(surface);
-    let ground_plane_component = ground_plane_component(surface);
+    let skybox_component = skybox_component(surface, scene_data, sample_count);
+    let ground_plane_component = ground_plane_component(surface, scene_data, sample_count);
+    let post_process_component = post_process_component(surface, post_process, color_grading);
+
+    // Every model change recomputes all of them. But each model only reacts to its own matrix.
+    let world_matrices = Memo::new({
+        let models = models.clone();
+        move |_| {
+            let models = models.iter().map(|model| model.get()).collect::<Vec<_>>();
+            crate::game::world_matrices(&models)
+        }
+    });
 
     let models_components = ForEach::new(
         move || models.iter(),
@@ -408,16 +937,23 @@ fn render_component(
             move |key: &String, model: ArcReadSignal<ModelInfo>| {
                 model_component(
                     surface,
-                    shaders,
-                    textures,
+                    resources,
                     key,
                     model.clone(),
-                    threshold_factor,
-                    compute_patches,
-                    copy_patches_pipeline,
+                    world_matrices,
+                    LodInfo {
+                        compute_patches,
+                        copy_patches_pipeline,
+                        settings: lod_settings,
+                    },
                     RenderInfo {
                         render_bind_group_0,
+                        shadow_bind_groups_0,
                         meshes: quad_meshes,
+                        patch_infos,
+                        debug_view,
+                        sample_count,
+                        model_parameters,
                     },
                 )
             }
@@ -463,18 +999,65 @@ fn render_component(
                 (v.lod_stage)(render_data, &mut commands);
             });
 
+            if scene_data.with_value(|scene_data| scene_data.shadow_light.is_some()) {
+                let scene_data = scene_data.read_value();
+                for (cascade, view) in scene_data.shadow_maps.cascade_views.iter().enumerate() {
+                    let mut shadow_pass = commands.scoped_render_pass(
+                        format!("Shadow Pass {cascade}"),
+                        wgpu::RenderPassDescriptor {
+                            label: Some("Shadow Pass"),
+                            color_attachments: &[],
+                            depth_stencil_attachment: Some(
+                                wgpu::RenderPassDepthStencilAttachment {
+                                    view,
+                                    depth_ops: Some(wgpu::Operations {
+                                        load: wgpu::LoadOp::Clear(0.0), // Reverse Z
+                                        store: wgpu::StoreOp::Store,
+                                    }),
+                                    stencil_ops: None,
+                                },
+                            ),
+                            timestamp_writes: None,
+                            occlusion_query_set: None,
+                        },
+                    );
+                    models_components.for_each(|v| {
+                        (v.shadow_stage)(cascade, &mut shadow_pass);
+                    });
+                }
+            }
+
+            let hdr_texture = hdr_texture.read();
+            let multisampled_texture = multisampled_texture.read();
+            let object_id_texture = object_id_texture.read();
+            let multisampled_object_id_texture = multisampled_object_id_texture.read();
+            let color_attachment = match multisampled_texture.as_ref() {
+                Some(multisampled) => wgpu::RenderPassColorAttachment {
+                    view: &multisampled.view,
+                    resolve_target: Some(&hdr_texture.view),
+                    ops: wgpu::Operations {
+                        // Only the resolved result is needed
+                        store: wgpu::StoreOp::Discard,
+                        ..Default::default()
+                    },
+                },
+                None => wgpu::RenderPassColorAttachment {
+                    view: &hdr_texture.view,
+                    resolve_target: None,
+                    ops: Default::default(),
+                },
+            };
             let mut render_pass = commands.scoped_render_pass(
                 "Render Pass",
                 wgpu::RenderPassDescriptor {
                     label: Some("Render Pass"),
                     color_attachments: &[
+                        Some(color_attachment),
                         Some(wgpu::RenderPassColorAttachment {
-                            view: surface_texture.texture_view(),
-                            resolve_target: None,
-                            ops: Default::default(),
-                        }),
-                        Some(wgpu::RenderPassColorAttachment {
-                            view: &object_id_texture.read().view,
+                            view: &multisampled_object_id_texture
+                                .as_ref()
+                                .unwrap_or(&object_id_texture)
+                                .view,
                             resolve_target: None,
                             ops: Default::default(),
                         }),
@@ -492,16 +1075,37 @@ fn render_component(
                 },
             );
 
-            // Render the models
-            models_components.for_each(|v| {
-                (v.render_stage)(&mut render_pass);
+            models_components.with_all(|models| {
+                let camera_position = render_data.camera.position;
+                let mut transparent_models = Vec::new();
+
+                // Render the opaque models
+                for v in models {
+                    match v.transparent_position.get() {
+                        Some(position) => {
+                            transparent_models.push((position.distance_squared(camera_position), v))
+                        }
+                        None => (v.render_stage)(&mut render_pass),
+                    }
+                }
+
+                // Skybox is rendered after opaque objects
+                skybox_component.record(render_data, &mut render_pass);
+
+                // Transparent models are blended back to front
+                transparent_models.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+                for (_, v) in transparent_models {
+                    (v.render_stage)(&mut render_pass);
+                }
             });
 
-            // Skybox is rendered after opaque objects
-            (skybox_component)(render_data, &mut render_pass);
+            // And now overlay the ground plane
+            ground_plane_component.record(render_data, &mut render_pass);
+            drop(render_pass);
 
-            // And now overlay transparent objects
-            (ground_plane_component)(render_data, &mut render_pass);
+            (object_id_resolve_component)(&mut commands);
+
+            (post_process_component)(&mut commands, &hdr_texture, surface_texture.texture_view());
         };
         profiler.update_value(|profiler| profiler.resolve_queries(&mut command_encoder));
         context
@@ -515,7 +1119,7 @@ fn render_component(
         surface_texture.present();
 
         let render_results = {
-            let delta_time = frame_time.delta;
+            let delta_time = frame_time.real_delta;
             let mut profiler = profiler.write_value();
             profiler.end_frame().unwrap();
             let profiler_results =
@@ -534,20 +1138,21 @@ fn render_component(
     }
 }
 
+type ScopedRenderPass<'a, 'b> = wgpu_profiler::OwningScope<'a, wgpu::RenderPass<'b>>;
+
 /// Returns multiple render functions
 fn model_component(
     surface: RwSignal<SurfaceOrFallback>,
-    shaders: RwSignal<HashMap<ShaderId, Arc<ShaderPipelines>>>,
-    textures: RwSignal<HashMap<TextureId, Arc<Texture>>>,
+    resources: SharedResources,
     key: &str,
     model: ArcReadSignal<ModelInfo>,
-    threshold_factor: ReadSignal<f32>,
-    compute_patches: StoredValue<ComputePatchesStep>,
-    copy_patches_pipeline: StoredValue<wgpu::ComputePipeline>,
+    world_matrices: Memo<HashMap<String, Mat4>>,
+    lod_stage: LodInfo,
     render_stage: RenderInfo,
 ) -> ModelRenderers<
     impl Fn(&FrameData, &mut wgpu_profiler::Scope<'_, wgpu::CommandEncoder>) + use<>,
-    impl Fn(&mut wgpu_profiler::OwningScope<'_, wgpu::RenderPass<'_>>) + use<>,
+    impl Fn(&mut ScopedRenderPass<'_, '_>) + use<>,
+    impl Fn(usize, &mut ScopedRenderPass<'_, '_>) + use<>,
 > {
     let virtual_model = Arc::new(VirtualModel::new(
         &get_context(),
@@ -555,45 +1160,265 @@ fn model_component(
         &format!("ID{}", key),
     ));
 
-    let lod_stage_component = lod_stage_component(
-        surface,
-        shaders,
-        model.clone(),
-        virtual_model.clone(),
-        compute_patches,
-        copy_patches_pipeline,
-        threshold_factor,
-    );
+    let world_matrix = Memo::new({
+        let key = key.to_string();
+        move |_| world_matrices.with(|matrices| matrices.get(&key).copied().unwrap_or_default())
+    });
 
-    let render_component = render_model_component(
-        render_stage.render_bind_group_0,
-        shaders,
-        textures,
-        model.clone(),
+    let instance_count = Memo::new({
+        let model = model.clone();
+        move |_| model.with(|model| model.instance_count)
+    });
+    // Only reallocated when the number of instances changes
+    let instances_texture = Memo::new_computed({
+        let key = key.to_string();
+        move |_| {
+            create_instances_texture(
+                &get_context().device,
+                &format!("ID{key} Instances Texture"),
+                instance_count.get().max(1),
+            )
+        }
+    });
+    Effect::new({
+        let model = model.clone();
+        move |_| {
+            let instances = model.with(instance_data);
+            write_instances(
+                &get_context().queue,
+                &instances_texture.read().texture,
+                &instances,
+            );
+        }
+    });
+
+    let inputs = ModelInputs {
+        model: model.clone(),
+        world_matrix,
         virtual_model,
-        render_stage.meshes,
-    );
+        instances_texture,
+    };
+    let lod_stage_component =
+        lod_stage_component(surface, resources.shaders, inputs.clone(), lod_stage);
+    let (render_component, shadow_component) =
+        render_model_component(render_stage, resources, inputs);
+
+    let transparent_position = Memo::new(move |_| {
+        model
+            .read()
+            .material_info
+            .is_transparent()
+            .then(|| world_matrix.get().w_axis.truncate())
+    });
 
     ModelRenderers {
         lod_stage: lod_stage_component,
         render_stage: render_component,
+        shadow_stage: shadow_component,
+        transparent_position,
+    }
+}
+
+/// The instance data for the shaders. A model without instances still gets one entry, since bindings cannot be empty.
+fn instance_data(model: &ModelInfo) -> Vec<shader::Instance> {
+    let mut instances: Vec<_> = model.instances_padded().collect();
+    if instances.is_empty() {
+        instances.push(InstanceInfo::default());
     }
+    instances
+        .into_iter()
+        .map(|instance| {
+            let matrix = instance.transform.to_matrix();
+            shader::Instance {
+                transform: matrix,
+                normal_matrix: normal_matrix(matrix),
+                tint: instance.tint,
+                custom: instance.custom,
+            }
+        })
+        .collect()
+}
+
+/// The instances are read from a texture, since the LOD stage cannot bind another storage buffer.
+/// Every instance takes up [`shader::INSTANCE_TEXELS`] texels, with [`shader::INSTANCES_PER_ROW`] instances per row.
+fn create_instances_texture(device: &wgpu::Device, label: &str, instance_count: u32) -> Texture {
+    let size = wgpu::Extent3d {
+        width: instance_count.min(shader::INSTANCES_PER_ROW) * shader::INSTANCE_TEXELS,
+        height: instance_count.div_ceil(shader::INSTANCES_PER_ROW),
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Uint,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&Default::default());
+    Texture { texture, view }
+}
+
+/// Uploads the instances with the same layout as in a storage buffer, which is exactly [`shader::INSTANCE_TEXELS`] texels each
+fn write_instances(queue: &wgpu::Queue, texture: &wgpu::Texture, instances: &[shader::Instance]) {
+    let mut data = encase::StorageBuffer::new(Vec::new());
+    data.write(instances).unwrap();
+    let mut data = data.into_inner();
+    let bytes_per_row = texture.width() * texture.format().block_copy_size(None).unwrap();
+    // The last row can be partially filled
+    data.resize((bytes_per_row * texture.height()) as usize, 0);
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        &data,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(bytes_per_row),
+            rows_per_image: Some(texture.height()),
+        },
+        texture.size(),
+    );
 }
 
-struct ModelRenderers<LodStage, RenderStage> {
+struct ModelRenderers<LodStage, RenderStage, ShadowStage> {
     lod_stage: LodStage,
     render_stage: RenderStage,
+    /// Renders the model into one shadow cascade
+    shadow_stage: ShadowStage,
+    /// Where the model is, for sorting it in the transparent pass. `None` for opaque models.
+    transparent_position: Memo<Option<Vec3>>,
+}
+
+#[derive(Clone, Copy)]
+struct LodSettings {
+    threshold_factor: ReadSignal<f32>,
+    mode: ReadSignal<LodMode>,
+    kernel: ReadSignal<LodKernel>,
+}
+
+/// Shared by the LOD stages of all models
+#[derive(Clone, Copy)]
+struct LodInfo {
+    compute_patches: StoredValue<ComputePatchesStep>,
+    copy_patches_pipeline: StoredValue<wgpu::ComputePipeline>,
+    settings: LodSettings,
+}
+
+/// The parts of a model that both its LOD stage and its render stage use
+#[derive(Clone)]
+struct ModelInputs {
+    model: ArcReadSignal<ModelInfo>,
+    world_matrix: Memo<Mat4>,
+    virtual_model: Arc<VirtualModel>,
+    instances_texture: Memo<Texture>,
+}
+
+/// The work queue of the persistent LOD kernel. Only allocated for models that use it.
+struct PersistentLodQueue {
+    id: String,
+    work_queue: TypedBuffer<persistent_patches::WorkQueue>,
+    bind_group_2: persistent_patches::bind_groups::BindGroup2,
+    /// The `stalled` flag of the queue. It is copied in one frame, and mapped in the next one, so that nothing waits for the GPU.
+    stalled_readback: wgpu::Buffer,
+    readback_state: Arc<AtomicU8>,
+    /// Set once the kernel gave up on an item. The model then uses the rounds kernel.
+    stalled: Arc<AtomicBool>,
+}
+
+const READBACK_IDLE: u8 = 0;
+const READBACK_COPIED: u8 = 1;
+const READBACK_MAPPING: u8 = 2;
+
+impl PersistentLodQueue {
+    /// Maps the flag that the last frame copied, since its commands have been submitted by now
+    fn map_stalled(&self) {
+        if self
+            .readback_state
+            .compare_exchange(
+                READBACK_COPIED,
+                READBACK_MAPPING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            return;
+        }
+        let buffer = self.stalled_readback.clone();
+        let readback_state = self.readback_state.clone();
+        let stalled = self.stalled.clone();
+        let id = self.id.clone();
+        self.stalled_readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    let flag = {
+                        let data = buffer.slice(..).get_mapped_range();
+                        u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+                    };
+                    buffer.unmap();
+                    if flag != 0 && !stalled.swap(true, Ordering::AcqRel) {
+                        warn!(
+                            "The persistent LOD kernel of {id} stalled, switching to the rounds kernel"
+                        );
+                    }
+                }
+                readback_state.store(READBACK_IDLE, Ordering::Release);
+            });
+    }
+
+    /// Only copies when the last readback is done, since a mapped buffer cannot be written to
+    fn copy_stalled(&self, commands: &mut wgpu_profiler::Scope<'_, wgpu::CommandEncoder>) {
+        if self
+            .readback_state
+            .compare_exchange(
+                READBACK_IDLE,
+                READBACK_COPIED,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+        {
+            // `stalled` is the first field of the queue
+            commands.copy_buffer_to_buffer(
+                &self.work_queue,
+                0,
+                &self.stalled_readback,
+                0,
+                self.stalled_readback.size(),
+            );
+        }
+    }
 }
 
 fn lod_stage_component(
     surface: RwSignal<SurfaceOrFallback>,
     shaders: RwSignal<HashMap<ShaderId, Arc<ShaderPipelines>>>,
-    model: ArcReadSignal<ModelInfo>,
-    virtual_model: Arc<VirtualModel>,
-    compute_patches: StoredValue<ComputePatchesStep>,
-    copy_patches_pipeline: StoredValue<wgpu::ComputePipeline>,
-    threshold_factor: ReadSignal<f32>,
+    inputs: ModelInputs,
+    lod_stage: LodInfo,
 ) -> impl Fn(&FrameData, &mut wgpu_profiler::Scope<'_, wgpu::CommandEncoder>) {
+    let ModelInputs {
+        model,
+        world_matrix,
+        virtual_model,
+        instances_texture,
+    } = inputs;
+    let LodInfo {
+        compute_patches,
+        copy_patches_pipeline,
+        settings:
+            LodSettings {
+                threshold_factor,
+                mode: lod_mode,
+                kernel: lod_kernel,
+            },
+    } = lod_stage;
     let context = &get_context();
     let device = &context.device;
     let id = model.read_untracked().id.clone(); // I wonder if this ID stays the same
@@ -623,6 +1448,9 @@ fn lod_stage_component(
     ));
 
     let patches_buffer_empty = compute_patches::Patches {
+        dispatch_x: 0,
+        dispatch_y: 0,
+        dispatch_z: 0,
         patches_length: 0,
         patches_capacity: 0,
         patches: vec![],
@@ -632,27 +1460,13 @@ fn lod_stage_component(
             &format!("{id} Patches Buffer 0"),
             &patches_buffer_empty,
             MAX_PATCH_COUNT as u64,
-            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            BufferUsages::INDIRECT | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
         ),
         device.storage_buffer_with_array(
             &format!("{id} Patches Buffer 1"),
             &patches_buffer_empty,
             MAX_PATCH_COUNT as u64,
-            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
-        ),
-    ];
-
-    let indirect_compute_buffer = [
-        device.storage_buffer(
-            &format!("{id} Indirect Compute Dispatch Buffer 0"),
-            // None of these values will ever be read
-            &compute_patches::DispatchIndirectArgs { x: 0, y: 0, z: 0 },
-            BufferUsages::INDIRECT | BufferUsages::COPY_DST,
-        ),
-        device.storage_buffer(
-            &format!("{id} Indirect Compute Dispatch Buffer 1"),
-            &compute_patches::DispatchIndirectArgs { x: 0, y: 0, z: 0 },
-            BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            BufferUsages::INDIRECT | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
         ),
     ];
 
@@ -662,27 +1476,117 @@ fn lod_stage_component(
         wgpu::BufferUsages::COPY_DST,
     );
 
-    let bind_group_1 = {
-        let render_buffer = &virtual_model.render_buffer;
-        compute_patches::bind_groups::BindGroup1::from_bindings(
-            device,
-            compute_patches::bind_groups::BindGroupLayout1 {
-                input_buffer: input_buffer.read().as_entire_buffer_binding(),
-                render_buffer_2: render_buffer[0].as_entire_buffer_binding(),
-                render_buffer_4: render_buffer[1].as_entire_buffer_binding(),
-                render_buffer_8: render_buffer[2].as_entire_buffer_binding(),
-                render_buffer_16: render_buffer[3].as_entire_buffer_binding(),
-                render_buffer_32: render_buffer[4].as_entire_buffer_binding(),
-            },
-        )
+    let bind_group_1 = Memo::new_computed({
+        let virtual_model = virtual_model.clone();
+        move |_| {
+            let render_buffer = &virtual_model.render_buffer;
+            compute_patches::bind_groups::BindGroup1::from_bindings(
+                &get_context().device,
+                compute_patches::bind_groups::BindGroupLayout1 {
+                    input_buffer: input_buffer.read().as_entire_buffer_binding(),
+                    render_buffer_2: render_buffer[0].as_entire_buffer_binding(),
+                    render_buffer_4: render_buffer[1].as_entire_buffer_binding(),
+                    render_buffer_8: render_buffer[2].as_entire_buffer_binding(),
+                    render_buffer_16: render_buffer[3].as_entire_buffer_binding(),
+                    render_buffer_32: render_buffer[4].as_entire_buffer_binding(),
+                    lod_tree: virtual_model.lod_tree.as_entire_buffer_binding(),
+                    parameters: virtual_model.parameters.as_entire_buffer_binding(),
+                    instances: &instances_texture.read().view,
+                },
+            )
+        }
+    });
+    let reuse_input = device.uniform_buffer(
+        &format!("{id} Reuse Patches Input"),
+        &reuse_patches::ReuseInput { instance_count: 0 },
+        wgpu::BufferUsages::COPY_DST,
+    );
+    let reuse_bind_group_0 = reuse_patches::bind_groups::BindGroup0::from_bindings(
+        device,
+        reuse_patches::bind_groups::BindGroupLayout0 {
+            lod_tree: virtual_model.lod_tree.as_entire_buffer_binding(),
+            patches_to_buffer: patches_buffer[0].as_entire_buffer_binding(),
+            reuse_input: reuse_input.as_entire_buffer_binding(),
+        },
+    );
+    // The LOD tree of the last frame can only be reused if it was built for the same instances
+    let reusable_instance_count = StoredValue::new(None::<u32>);
+
+    let persistent_bind_group_1 = Memo::new_computed({
+        let virtual_model = virtual_model.clone();
+        move |_| {
+            let render_buffer = &virtual_model.render_buffer;
+            persistent_patches::bind_groups::BindGroup1::from_bindings(
+                &get_context().device,
+                persistent_patches::bind_groups::BindGroupLayout1 {
+                    input_buffer: input_buffer.read().as_entire_buffer_binding(),
+                    render_buffer_2: render_buffer[0].as_entire_buffer_binding(),
+                    render_buffer_4: render_buffer[1].as_entire_buffer_binding(),
+                    render_buffer_8: render_buffer[2].as_entire_buffer_binding(),
+                    render_buffer_16: render_buffer[3].as_entire_buffer_binding(),
+                    render_buffer_32: render_buffer[4].as_entire_buffer_binding(),
+                    lod_tree: virtual_model.lod_tree.as_entire_buffer_binding(),
+                    parameters: virtual_model.parameters.as_entire_buffer_binding(),
+                    instances: &instances_texture.read().view,
+                },
+            )
+        }
+    });
+    let persistent_input = device.uniform_buffer(
+        &format!("{id} Persistent Patches Input"),
+        &persistent_patches::PersistentInput { max_depth: 0 },
+        wgpu::BufferUsages::COPY_DST,
+    );
+    let persistent_queue = StoredValue::new(None::<PersistentLodQueue>);
+    let create_persistent_queue = {
+        let id = id.clone();
+        let seeds = patches_buffer[0].clone();
+        let persistent_input = persistent_input.clone();
+        move || {
+            let device = &get_context().device;
+            let work_queue = device.storage_buffer_with_array(
+                &format!("{id} Persistent Work Queue"),
+                &persistent_patches::WorkQueue {
+                    stalled: 0,
+                    head: 0,
+                    tail: 0,
+                    finished: 0,
+                    items: vec![],
+                },
+                MAX_PATCH_COUNT as u64,
+                BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            );
+            let bind_group_2 = persistent_patches::bind_groups::BindGroup2::from_bindings(
+                device,
+                persistent_patches::bind_groups::BindGroupLayout2 {
+                    seeds: seeds.as_entire_buffer_binding(),
+                    queue: work_queue.as_entire_buffer_binding(),
+                    persistent_input: persistent_input.as_entire_buffer_binding(),
+                },
+            );
+            let stalled_readback = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("{id} Persistent Stalled Readback")),
+                size: std::mem::size_of::<u32>() as u64,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+            PersistentLodQueue {
+                id: id.clone(),
+                work_queue,
+                bind_group_2,
+                stalled_readback,
+                readback_state: Arc::new(AtomicU8::new(READBACK_IDLE)),
+                stalled: Arc::new(AtomicBool::new(false)),
+            }
+        }
     };
+
     let bind_group_2 = [
         compute_patches::bind_groups::BindGroup2::from_bindings(
             device,
             compute_patches::bind_groups::BindGroupLayout2 {
                 patches_from_buffer: patches_buffer[0].as_entire_buffer_binding(),
                 patches_to_buffer: patches_buffer[1].as_entire_buffer_binding(),
-                dispatch_next: indirect_compute_buffer[1].as_entire_buffer_binding(),
                 force_render: force_render_uniform.as_entire_buffer_binding(),
             },
         ),
@@ -691,7 +1595,6 @@ fn lod_stage_component(
             compute_patches::bind_groups::BindGroupLayout2 {
                 patches_from_buffer: patches_buffer[1].as_entire_buffer_binding(), // Swap the order :)
                 patches_to_buffer: patches_buffer[0].as_entire_buffer_binding(),
-                dispatch_next: indirect_compute_buffer[0].as_entire_buffer_binding(),
                 force_render: force_render_uniform.as_entire_buffer_binding(),
             },
         ),
@@ -711,9 +1614,10 @@ fn lod_stage_component(
         let context = &get_context();
         let queue = &context.queue;
         force_render_uniform.write_buffer(queue, &compute_patches::ForceRenderFlag { flag: 0 });
-        let model_view_projection = frame_data.camera.projection_matrix(surface.read().size())
+        // Any affine world matrix works here, since the culling and the LOD measure in clip space
+        let model_view_projection = frame_data.projection_matrix(surface.read().size())
             * frame_data.camera.view_matrix()
-            * model.read().transform.to_matrix();
+            * world_matrix.get();
         input_buffer.read().write_buffer(
             queue,
             &compute_patches::InputBuffer {
@@ -722,31 +1626,32 @@ fn lod_stage_component(
             },
         );
         let instance_count = model.with(|v| v.instance_count);
-        patches_buffer[0].write_buffer(
-            queue,
-            &compute_patches::Patches {
-                patches_length: instance_count,
-                patches_capacity: MAX_PATCH_COUNT,
-                patches: (0..instance_count)
-                    .map(|i| {
-                        compute_patches::EncodedPatch {
-                            // Just the leading 1 bit
-                            u: 1,
-                            v: 1,
-                            instance: i,
-                        }
-                    })
-                    .collect(),
-            },
-        );
-        indirect_compute_buffer[0].write_buffer(
-            queue,
-            &compute_patches::DispatchIndirectArgs {
-                x: instance_count,
-                y: 1,
-                z: 1,
-            },
-        );
+        // Captures jump between tiles, so they always start from scratch
+        let is_incremental = lod_mode.get() == LodMode::Incremental
+            && frame_data.tile.is_none()
+            && reusable_instance_count.get_value() == Some(instance_count);
+        if !is_incremental {
+            patches_buffer[0].write_buffer(
+                queue,
+                &compute_patches::Patches {
+                    dispatch_x: instance_count,
+                    dispatch_y: 1,
+                    dispatch_z: 1,
+                    patches_length: instance_count,
+                    patches_capacity: MAX_PATCH_COUNT,
+                    patches: (0..instance_count)
+                        .map(|i| {
+                            compute_patches::EncodedPatch {
+                                // Just the leading 1 bit
+                                u: 1,
+                                v: 1,
+                                instance: i,
+                            }
+                        })
+                        .collect(),
+                },
+            );
+        }
 
         let render_buffer_reset = compute_patches::RenderBuffer {
             patches_length: 0,
@@ -758,69 +1663,120 @@ fn lod_stage_component(
         }
 
         if let Some(overriden_lod_stage) = frame_data.lod_stage.as_ref() {
+            commands.clear_buffer(&virtual_model.lod_tree, 0, None);
             (overriden_lod_stage)(&model.read().shader_id, &model.read().id);
+            reusable_instance_count.set_value(None);
         } else {
-            // Each round, we do a ping-pong and pong-ping
-            // 2*4 rounds is enough to subdivide a 4k screen into 16x16 pixel patches
             let compute_patches = compute_patches.read_value();
-            let double_number_of_rounds = 4;
-            for i in 0..double_number_of_rounds {
-                let is_last_round = i == double_number_of_rounds - 1;
-                // TODO: Should I create many compute passes, or just one?
-                {
-                    commands.copy_tbuffer_to_tbuffer(
-                        &compute_patches.patches_buffer_reset,
-                        &patches_buffer[1],
-                    );
-                    commands.copy_tbuffer_to_tbuffer(
-                        &compute_patches.indirect_compute_buffer_reset,
-                        &indirect_compute_buffer[1],
-                    );
-                    let mut compute_pass =
-                        commands.scoped_compute_pass(format!("Compute Patches From-To {i}"));
-                    compute_pass.set_pipeline(&shader.read().compute_patches);
-                    compute_patches::set_bind_groups(
-                        &mut compute_pass.recorder,
-                        &compute_patches.bind_group_0,
-                        &bind_group_1,
-                        &bind_group_2[0],
-                    );
-                    compute_pass.dispatch_workgroups_indirect(&indirect_compute_buffer[0], 0);
-                }
-                if is_last_round {
-                    commands.copy_tbuffer_to_tbuffer(
-                        &compute_patches.force_render_true,
-                        &force_render_uniform,
-                    );
-                }
-                {
-                    commands.copy_tbuffer_to_tbuffer(
-                        &compute_patches.patches_buffer_reset,
-                        &patches_buffer[0],
-                    );
-                    commands.copy_tbuffer_to_tbuffer(
-                        &compute_patches.indirect_compute_buffer_reset,
-                        &indirect_compute_buffer[0],
-                    );
-                    let mut compute_pass =
-                        commands.scoped_compute_pass(format!("Compute Patches To-From {i}"));
-                    compute_pass.set_pipeline(&shader.read().compute_patches);
-                    compute_patches::set_bind_groups(
-                        &mut compute_pass.recorder,
-                        // Maybe refactor so that parent components set bind groups, and children just assume that they're set?
-                        &compute_patches.bind_group_0,
-                        &bind_group_1,
-                        &bind_group_2[1],
-                    );
-                    compute_pass.dispatch_workgroups_indirect(&indirect_compute_buffer[1], 0);
+            if is_incremental {
+                reuse_input.write_buffer(queue, &reuse_patches::ReuseInput { instance_count });
+                commands.copy_tbuffer_to_tbuffer(
+                    &compute_patches.patches_buffer_reset,
+                    &patches_buffer[0],
+                );
+                let mut compute_pass = commands.scoped_compute_pass("Reuse Patches");
+                compute_pass.set_pipeline(&compute_patches.reuse_patches_pipeline);
+                reuse_patches::set_bind_groups(&mut compute_pass.recorder, &reuse_bind_group_0);
+                compute_pass.dispatch_workgroups(
+                    LOD_TREE_CAPACITY
+                        .max(instance_count)
+                        .div_ceil(reuse_patches::compute::MAIN_WORKGROUP_SIZE[0]),
+                    1,
+                    1,
+                );
+            }
+            commands.clear_buffer(&virtual_model.lod_tree, 0, None);
+
+            let persistent_max_depth = match lod_kernel.get() {
+                LodKernel::Persistent { max_depth } => {
+                    if persistent_queue.with_value(|v| v.is_none()) {
+                        persistent_queue.set_value(Some(create_persistent_queue()));
+                    }
+                    persistent_queue.with_value(|persistent_queue| {
+                        let persistent_queue = persistent_queue.as_ref().unwrap();
+                        persistent_queue.map_stalled();
+                        // A kernel that stalled once would keep dropping patches
+                        (!persistent_queue.stalled.load(Ordering::Acquire)).then_some(max_depth)
+                    })
                 }
-                if is_last_round {
-                    commands.copy_tbuffer_to_tbuffer(
-                        &compute_patches.force_render_false,
-                        &force_render_uniform,
-                    );
+                LodKernel::Rounds => None,
+            };
+            if let Some(max_depth) = persistent_max_depth {
+                persistent_input
+                    .write_buffer(queue, &persistent_patches::PersistentInput { max_depth });
+                persistent_queue.with_value(|persistent_queue| {
+                    let persistent_queue = persistent_queue.as_ref().unwrap();
+                    // The patches of the frame come straight from patches_buffer[0], so only the queue needs a reset
+                    commands.clear_buffer(&persistent_queue.work_queue, 0, None);
+                    {
+                        let mut compute_pass = commands.scoped_compute_pass("Persistent Patches");
+                        compute_pass.set_pipeline(&shader.read().persistent_patches);
+                        persistent_patches::set_bind_groups(
+                            &mut compute_pass.recorder,
+                            &compute_patches.persistent_bind_group_0,
+                            &persistent_bind_group_1.read(),
+                            &persistent_queue.bind_group_2,
+                        );
+                        compute_pass.dispatch_workgroups(PERSISTENT_WORKGROUPS, 1, 1);
+                    }
+                    persistent_queue.copy_stalled(commands);
+                });
+            } else {
+                // Each round, we do a ping-pong and pong-ping
+                // 2*4 rounds is enough to subdivide a 4k screen into 16x16 pixel patches.
+                // When starting from the last frame, 2*2 rounds let a patch merge once and split three times.
+                let double_number_of_rounds = if is_incremental { 2 } else { 4 };
+                for i in 0..double_number_of_rounds {
+                    let is_last_round = i == double_number_of_rounds - 1;
+                    // TODO: Should I create many compute passes, or just one?
+                    {
+                        commands.copy_tbuffer_to_tbuffer(
+                            &compute_patches.patches_buffer_reset,
+                            &patches_buffer[1],
+                        );
+                        let mut compute_pass =
+                            commands.scoped_compute_pass(format!("Compute Patches From-To {i}"));
+                        compute_pass.set_pipeline(&shader.read().compute_patches);
+                        compute_patches::set_bind_groups(
+                            &mut compute_pass.recorder,
+                            &compute_patches.bind_group_0,
+                            &bind_group_1.read(),
+                            &bind_group_2[0],
+                        );
+                        compute_pass.dispatch_workgroups_indirect(&patches_buffer[0], 0);
+                    }
+                    if is_last_round {
+                        commands.copy_tbuffer_to_tbuffer(
+                            &compute_patches.force_render_true,
+                            &force_render_uniform,
+                        );
+                    }
+                    {
+                        commands.copy_tbuffer_to_tbuffer(
+                            &compute_patches.patches_buffer_reset,
+                            &patches_buffer[0],
+                        );
+                        let mut compute_pass =
+                            commands.scoped_compute_pass(format!("Compute Patches To-From {i}"));
+                        compute_pass.set_pipeline(&shader.read().compute_patches);
+                        compute_patches::set_bind_groups(
+                            &mut compute_pass.recorder,
+                            // Maybe refactor so that parent components set bind groups, and children just assume that they're set?
+                            &compute_patches.bind_group_0,
+                            &bind_group_1.read(),
+                            &bind_group_2[1],
+                        );
+                        compute_pass.dispatch_workgroups_indirect(&patches_buffer[1], 0);
+                    }
+                    if is_last_round {
+                        commands.copy_tbuffer_to_tbuffer(
+                            &compute_patches.force_render_false,
+                            &force_render_uniform,
+                        );
+                    }
                 }
             }
+            reusable_instance_count.set_value(Some(instance_count));
         }
         {
             let mut compute_pass = commands.scoped_compute_pass("Copy Patch Sizes Pass");
@@ -833,19 +1789,41 @@ fn lod_stage_component(
 
 struct RenderInfo {
     render_bind_group_0: StoredValue<shader::bind_groups::BindGroup0>,
+    shadow_bind_groups_0: StoredValue<Vec<shader::bind_groups::BindGroup0>>,
     meshes: StoredValue<Vec<Mesh>>,
+    /// One per patch size, like the meshes
+    patch_infos: StoredValue<Vec<TypedBuffer<shader::PatchInfo>>>,
+    debug_view: ReadSignal<DebugView>,
+    sample_count: ReadSignal<u32>,
+    model_parameters: RwSignal<HashMap<String, ParameterValues>>,
 }
 
 /// Renders a single model
 /// A model can change even when its ID stays the same. But the number of allocated buffers stays the same.
 fn render_model_component(
-    render_bind_group_0: StoredValue<shader::bind_groups::BindGroup0>,
-    shaders: RwSignal<HashMap<ShaderId, Arc<ShaderPipelines>>>,
-    textures: RwSignal<HashMap<TextureId, Arc<Texture>>>,
-    model: ArcReadSignal<ModelInfo>,
-    virtual_model: Arc<VirtualModel>,
-    meshes: StoredValue<Vec<Mesh>>,
-) -> impl Fn(&mut wgpu_profiler::OwningScope<'_, wgpu::RenderPass<'_>>) {
+    render_info: RenderInfo,
+    resources: SharedResources,
+    inputs: ModelInputs,
+) -> (
+    impl Fn(&mut ScopedRenderPass<'_, '_>),
+    impl Fn(usize, &mut ScopedRenderPass<'_, '_>),
+) {
+    let SharedResources { shaders, textures } = resources;
+    let ModelInputs {
+        model,
+        world_matrix,
+        virtual_model,
+        instances_texture,
+    } = inputs;
+    let RenderInfo {
+        render_bind_group_0,
+        shadow_bind_groups_0,
+        meshes,
+        patch_infos,
+        debug_view,
+        sample_count,
+        model_parameters,
+    } = render_info;
     let shader = Memo::new({
         let model = model.clone();
         move |_| {
@@ -855,18 +1833,21 @@ fn render_model_component(
             shader.unwrap_or_else(|| use_context::<MissingShader>().unwrap().0.clone())
         }
     });
-    let texture = Memo::new_computed({
+    let texture_memo = |get_id: fn(&MaterialInfo) -> &Option<TextureId>| {
         let model = model.clone();
-        move |_| {
+        Memo::new_computed(move |_| {
             let model = model.read();
-            model
-                .material_info
-                .diffuse_texture
+            get_id(&model.material_info)
                 .as_ref()
-                .and_then(|id| textures.with(|t| t.get(&id).cloned()))
+                .and_then(|id| textures.with(|t| t.get(id).cloned()))
                 .unwrap_or_else(|| use_context::<EmptyTexture>().unwrap().0.clone())
-        }
-    });
+        })
+    };
+    let diffuse_texture = texture_memo(|material| &material.diffuse_texture);
+    let normal_texture = texture_memo(|material| &material.normal_texture);
+    let metallic_roughness_texture = texture_memo(|material| &material.metallic_roughness_texture);
+    let occlusion_texture = texture_memo(|material| &material.occlusion_texture);
+    let emissive_texture = texture_memo(|material| &material.emissive_texture);
 
     let context = &get_context();
     let device = &context.device;
@@ -874,8 +1855,10 @@ fn render_model_component(
     let model_buffer = StoredValue::new(device.uniform_buffer(
         "Model Buffer",
         &shader::Model {
-            model_similarity: glam::Mat4::IDENTITY,
+            model_matrix: glam::Mat4::IDENTITY,
             object_id: 0,
+            receive_shadows: 1,
+            normal_matrix: glam::Mat3::IDENTITY,
         },
         wgpu::BufferUsages::COPY_DST,
     ));
@@ -890,63 +1873,156 @@ fn render_model_component(
         let virtual_model = virtual_model.clone();
         move |_| {
             let context = &get_context();
-            let t_diffuse = texture.read();
+            let t_diffuse = diffuse_texture.read();
+            let t_normal = normal_texture.read();
+            let t_metallic_roughness = metallic_roughness_texture.read();
+            let t_occlusion = occlusion_texture.read();
+            let t_emissive = emissive_texture.read();
+            let patch_infos = patch_infos.read_value();
             virtual_model
                 .render_buffer
                 .iter()
-                .map(|render| {
+                .zip(patch_infos.iter())
+                .map(|(render, patch_info)| {
                     shader::bind_groups::BindGroup1::from_bindings(
                         &context.device,
                         shader::bind_groups::BindGroupLayout1 {
                             model: model_buffer.read_value().as_entire_buffer_binding(),
                             render_buffer: render.as_entire_buffer_binding(),
                             material: material_buffer.read_value().as_entire_buffer_binding(),
-                            t_diffuse: &t_diffuse.view,
+                            t_diffuse: &t_diffuse.texture.view,
+                            t_diffuse_sampler: &t_diffuse.sampler,
+                            t_normal: &t_normal.data.view,
+                            t_normal_sampler: &t_normal.sampler,
+                            t_metallic_roughness: &t_metallic_roughness.data.view,
+                            t_metallic_roughness_sampler: &t_metallic_roughness.sampler,
+                            t_occlusion: &t_occlusion.data.view,
+                            t_occlusion_sampler: &t_occlusion.sampler,
+                            t_emissive: &t_emissive.texture.view,
+                            t_emissive_sampler: &t_emissive.sampler,
+                            patch_info: patch_info.as_entire_buffer_binding(),
+                            lod_tree: virtual_model.lod_tree.as_entire_buffer_binding(),
+                            parameters: virtual_model.parameters.as_entire_buffer_binding(),
+                            instances: &instances_texture.read().view,
                         },
                     )
                 })
                 .collect::<Vec<_>>()
         }
     });
+    let receive_shadows = Memo::new({
+        let model = model.clone();
+        move |_| model.with(|model| model.receive_shadows)
+    });
+    // Moving a parent only rewrites the model buffers of its children, not their materials
     Effect::new(move |_| {
-        let model = model.read();
-        let queue = &get_context().queue;
         model_buffer.read_value().write_buffer(
-            queue,
+            &get_context().queue,
             &shader::Model {
-                model_similarity: model.transform.to_matrix(),
+                model_matrix: world_matrix.get(),
                 object_id: 0, // TODO: set this
+                receive_shadows: if receive_shadows.get() { 1 } else { 0 },
+                normal_matrix: normal_matrix(world_matrix.get()),
             },
         );
-        material_buffer
-            .read_value()
-            .write_buffer(queue, &model.material_info.to_shader());
+    });
+    Effect::new({
+        let model = model.clone();
+        move |_| {
+            material_buffer.read_value().write_buffer(
+                &get_context().queue,
+                &model.read().material_info.to_shader(),
+            );
+        }
+    });
+    let parameter_values = Memo::new({
+        let model = model.clone();
+        move |_| {
+            let id = model.with(|model| model.id.clone());
+            model_parameters.with(|values| values.get(&id).cloned().unwrap_or_default())
+        }
+    });
+    Effect::new({
+        let virtual_model = virtual_model.clone();
+        move |_| {
+            let bytes = parameter_values.with(|values| shader.read().parameters.to_bytes(values));
+            get_context()
+                .queue
+                .write_buffer(&virtual_model.parameters, 0, &bytes);
+        }
     });
 
-    move |render_pass: &mut wgpu_profiler::OwningScope<'_, wgpu::RenderPass<'_>>| {
-        render_pass.set_pipeline(&shader.read().render);
-
-        meshes.with_value(|meshes| {
-            for (i, (bind_group_1, mesh)) in
-                bind_group_1.read().iter().zip(meshes.iter()).enumerate()
-            {
-                let buffer_offset = (i as u64)
-                    * Vec::<copy_patches::DrawIndexedIndirectArgs>::METADATA
-                        .extra
-                        .stride
-                        .get();
-                shader::set_bind_groups(
-                    &mut render_pass.recorder,
-                    &render_bind_group_0.read_value(),
-                    bind_group_1,
+    // Both stages draw the same patches, only the pipeline and the camera differ
+    let draw_patches = {
+        let virtual_model = virtual_model.clone();
+        move |render_pass: &mut ScopedRenderPass<'_, '_>,
+              bind_group_0: &shader::bind_groups::BindGroup0| {
+            meshes.with_value(|meshes| {
+                for (i, (bind_group_1, mesh)) in
+                    bind_group_1.read().iter().zip(meshes.iter()).enumerate()
+                {
+                    let buffer_offset = (i as u64)
+                        * Vec::<copy_patches::DrawIndexedIndirectArgs>::METADATA
+                            .extra
+                            .stride
+                            .get();
+                    shader::set_bind_groups(&mut render_pass.recorder, bind_group_0, bind_group_1);
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass
+                        .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                    render_pass.draw_indexed_indirect(&virtual_model.indirect_draw, buffer_offset);
+                }
+            });
+        }
+    };
+    let draw_patches = Arc::new(draw_patches);
+    let is_transparent = Memo::new({
+        let model = model.clone();
+        move |_| model.read().material_info.is_transparent()
+    });
+
+    // A new shader may lack the pipelines for the sample count, which are then created in the background
+    let render_pipelines =
+        Memo::new_computed(move |previous: Option<&Option<Arc<RenderPipelines>>>| {
+            let shader = shader.read();
+            let sample_count = sample_count.get();
+            shader.render_pipelines(sample_count).or_else(|| {
+                any_spawner::Executor::spawn_local(
+                    shader.prepare_render_pipelines(sample_count, get_context()),
                 );
-                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-                render_pass
-                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                render_pass.draw_indexed_indirect(&virtual_model.indirect_draw, buffer_offset);
-            }
+                previous
+                    .and_then(Option::as_ref)
+                    .filter(|pipelines| pipelines.sample_count == sample_count)
+                    .cloned()
+            })
         });
-    }
+
+    let render_stage = {
+        let draw_patches = draw_patches.clone();
+        move |render_pass: &mut ScopedRenderPass<'_, '_>| {
+            let pipelines = render_pipelines.read();
+            let Some(pipelines) = pipelines.as_ref() else {
+                return;
+            };
+            let pipeline = match (debug_view.get(), &pipelines.wireframe) {
+                (DebugView::Wireframe, Some(wireframe)) => wireframe,
+                _ if is_transparent.get() => &pipelines.transparent,
+                _ => &pipelines.render,
+            };
+            render_pass.set_pipeline(pipeline);
+            draw_patches(render_pass, &render_bind_group_0.read_value());
+        }
+    };
+
+    let shadow_stage = move |cascade: usize, render_pass: &mut ScopedRenderPass<'_, '_>| {
+        if !model.read().cast_shadows {
+            return;
+        }
+        render_pass.set_pipeline(&shader.read().shadow);
+        draw_patches(render_pass, &shadow_bind_groups_0.read_value()[cascade]);
+    };
+
+    (render_stage, shadow_stage)
 }
 
 #[derive(Default)]
@@ -0,0 +1,147 @@
+//! Reads rendered frames back from the GPU, for screenshots that can be larger than the window.
+
+use anyhow::{Context, bail};
+use futures_channel::oneshot;
+use glam::UVec2;
+
+use super::{WgpuContext, frame_data::FrameTile};
+use crate::image::Image;
+
+/// Keeps the render targets of a single tile reasonably small
+const MAX_TILE_SIZE: u32 = 4096;
+
+pub fn tile_size(device: &wgpu::Device, full_size: UVec2) -> UVec2 {
+    let max_size = device.limits().max_texture_dimension_2d.min(MAX_TILE_SIZE);
+    full_size.min(UVec2::splat(max_size))
+}
+
+/// Covers the full image with tiles, row by row
+pub fn tiles(full_size: UVec2, tile_size: UVec2) -> impl Iterator<Item = FrameTile> {
+    let tile_count = (full_size + tile_size - UVec2::ONE) / tile_size;
+    (0..tile_count.y).flat_map(move |y| {
+        (0..tile_count.x).map(move |x| FrameTile {
+            offset: UVec2::new(x, y) * tile_size,
+            full_size,
+        })
+    })
+}
+
+/// A rendered tile that is being copied to the CPU
+pub struct TileReadback {
+    tile: FrameTile,
+    /// Tiles at the edges are cut off
+    size: UVec2,
+    padded_bytes_per_row: u32,
+    buffer: wgpu::Buffer,
+    mapped: oneshot::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+/// Copies the part of the texture that belongs to the image, and starts mapping it.
+pub fn read_tile(context: &WgpuContext, texture: &wgpu::Texture, tile: FrameTile) -> TileReadback {
+    let size = UVec2::new(texture.width(), texture.height()).min(tile.full_size - tile.offset);
+    let padded_bytes_per_row = (size.x * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Capture Buffer"),
+        size: (padded_bytes_per_row * size.y) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut command_encoder =
+        context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+    command_encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+    );
+    context
+        .queue
+        .submit(std::iter::once(command_encoder.finish()));
+
+    let (sender, mapped) = oneshot::channel();
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| _ = sender.send(result));
+
+    TileReadback {
+        tile,
+        size,
+        padded_bytes_per_row,
+        buffer,
+        mapped,
+    }
+}
+
+/// Waits for all tiles and stitches them together
+pub async fn assemble(
+    full_size: UVec2,
+    format: wgpu::TextureFormat,
+    tiles: Vec<TileReadback>,
+) -> anyhow::Result<Image> {
+    let swap_red_blue = match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        format => bail!("Capturing {format:?} surfaces is not supported"),
+    };
+
+    let row_bytes = (full_size.x * 4) as usize;
+    let mut data = vec![0u8; row_bytes * full_size.y as usize];
+    for tile in tiles {
+        tile.mapped
+            .await
+            .context("Capture buffer was dropped")?
+            .context("Failed to map capture buffer")?;
+        {
+            let mapped = tile.buffer.slice(..).get_mapped_range();
+            let tile_row_bytes = (tile.size.x * 4) as usize;
+            for y in 0..tile.size.y {
+                let source_start = (y * tile.padded_bytes_per_row) as usize;
+                let source = &mapped[source_start..source_start + tile_row_bytes];
+                let target_start =
+                    (tile.tile.offset.y + y) as usize * row_bytes + tile.tile.offset.x as usize * 4;
+                data[target_start..target_start + tile_row_bytes].copy_from_slice(source);
+            }
+        }
+        tile.buffer.unmap();
+    }
+
+    if swap_red_blue {
+        data.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+    }
+
+    Ok(Image {
+        width: full_size.x,
+        height: full_size.y,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_image() {
+        let full_size = UVec2::new(10, 7);
+        let tiles = tiles(full_size, UVec2::new(4, 4)).collect::<Vec<_>>();
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[0].offset, UVec2::ZERO);
+        assert_eq!(tiles[5].offset, UVec2::new(8, 4));
+        assert!(tiles.iter().all(|tile| tile.full_size == full_size));
+    }
+}
@@ -0,0 +1,419 @@
+//! Image based lighting from an equirectangular HDR image.
+
+use glam::Vec3;
+
+use crate::{
+    buffer::{DeviceBufferExt, TypedBuffer},
+    hdr::HdrImage,
+    shaders::environment,
+};
+
+const ENVIRONMENT_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Has to match the constant in Shader.wgsl
+const PREFILTERED_MIP_COUNT: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+const SAMPLE_COUNT: u32 = 512;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// The cubemaps for the skybox and for the ambient lighting
+pub struct EnvironmentMaps {
+    pub environment_view: wgpu::TextureView,
+    pub irradiance_view: wgpu::TextureView,
+    pub prefiltered_view: wgpu::TextureView,
+    pub brdf_lut_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    environment_storage: wgpu::TextureView,
+    irradiance_storage: wgpu::TextureView,
+    /// One per mip level
+    prefiltered_storage: Vec<wgpu::TextureView>,
+    /// One per mip level, with the roughness of that level
+    prefiltered_params: Vec<TypedBuffer<environment::BakeParams>>,
+    cube_params: TypedBuffer<environment::BakeParams>,
+    pipelines: EnvironmentPipelines,
+    dummy_cube_view: wgpu::TextureView,
+}
+
+struct EnvironmentPipelines {
+    equirectangular_to_cube: wgpu::ComputePipeline,
+    irradiance: wgpu::ComputePipeline,
+    prefilter: wgpu::ComputePipeline,
+    brdf_lut: wgpu::ComputePipeline,
+}
+
+impl EnvironmentMaps {
+    /// Creates the maps, and bakes the default sky into them
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let environment = create_cube_texture(device, "Environment Map", ENVIRONMENT_SIZE, 1);
+        let irradiance = create_cube_texture(device, "Irradiance Map", IRRADIANCE_SIZE, 1);
+        let prefiltered = create_cube_texture(
+            device,
+            "Prefiltered Environment Map",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_COUNT,
+        );
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let dummy_cube = create_cube_texture(device, "Environment Dummy", 1, 1);
+
+        let shader = environment::create_shader_module(device);
+        let layout = environment::create_pipeline_layout(device);
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(&format!("Environment {entry_point}")),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: Default::default(),
+            })
+        };
+
+        let environment_maps = Self {
+            environment_view: cube_view(&environment),
+            irradiance_view: cube_view(&irradiance),
+            prefiltered_view: cube_view(&prefiltered),
+            brdf_lut_view: brdf_lut.create_view(&Default::default()),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Environment Sampler"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            environment_storage: storage_view(&environment, 0),
+            irradiance_storage: storage_view(&irradiance, 0),
+            prefiltered_storage: (0..PREFILTERED_MIP_COUNT)
+                .map(|mip| storage_view(&prefiltered, mip))
+                .collect(),
+            prefiltered_params: (0..PREFILTERED_MIP_COUNT)
+                .map(|mip| {
+                    device.uniform_buffer(
+                        &format!("Prefilter Params {mip}"),
+                        &environment::BakeParams {
+                            roughness: (mip as f32) / ((PREFILTERED_MIP_COUNT - 1) as f32),
+                            intensity: 1.0,
+                            sample_count: SAMPLE_COUNT,
+                        },
+                        wgpu::BufferUsages::empty(),
+                    )
+                })
+                .collect(),
+            cube_params: device.uniform_buffer(
+                "Environment Params",
+                &environment::BakeParams {
+                    roughness: 0.0,
+                    intensity: 1.0,
+                    sample_count: SAMPLE_COUNT,
+                },
+                wgpu::BufferUsages::COPY_DST,
+            ),
+            pipelines: EnvironmentPipelines {
+                equirectangular_to_cube: create_pipeline(
+                    environment::ENTRY_EQUIRECTANGULAR_TO_CUBE,
+                ),
+                irradiance: create_pipeline(environment::ENTRY_IRRADIANCE),
+                prefilter: create_pipeline(environment::ENTRY_PREFILTER),
+                brdf_lut: create_pipeline(environment::ENTRY_BRDF_LUT),
+            },
+            dummy_cube_view: cube_view(&dummy_cube),
+        };
+
+        // The BRDF LUT does not depend on the environment
+        let dummy_equirectangular = upload_equirectangular(
+            device,
+            queue,
+            &HdrImage {
+                width: 1,
+                height: 1,
+                data: vec![Vec3::ZERO],
+            },
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        environment_maps.dispatch(
+            &mut encoder,
+            device,
+            &environment_maps.pipelines.brdf_lut,
+            environment::bind_groups::BindGroupLayout0 {
+                params: environment_maps.prefiltered_params[0].as_entire_buffer_binding(),
+                equirectangular: &dummy_equirectangular,
+                environment: &environment_maps.dummy_cube_view,
+                environment_sampler: &environment_maps.sampler,
+                output: &storage_view(&brdf_lut, 0),
+            },
+            BRDF_LUT_SIZE,
+            1,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        environment_maps.bake_default_sky(device, queue);
+        environment_maps
+    }
+
+    pub fn bake_default_sky(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.bake(device, queue, &default_sky(), 1.0);
+    }
+
+    /// Converts an equirectangular image into the cubemaps
+    pub fn bake(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &HdrImage,
+        intensity: f32,
+    ) {
+        let equirectangular = upload_equirectangular(device, queue, image);
+        self.cube_params.write_buffer(
+            queue,
+            &environment::BakeParams {
+                roughness: 0.0,
+                intensity,
+                sample_count: SAMPLE_COUNT,
+            },
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        self.dispatch(
+            &mut encoder,
+            device,
+            &self.pipelines.equirectangular_to_cube,
+            environment::bind_groups::BindGroupLayout0 {
+                params: self.cube_params.as_entire_buffer_binding(),
+                equirectangular: &equirectangular,
+                environment: &self.dummy_cube_view,
+                environment_sampler: &self.sampler,
+                output: &self.environment_storage,
+            },
+            ENVIRONMENT_SIZE,
+            6,
+        );
+        self.dispatch(
+            &mut encoder,
+            device,
+            &self.pipelines.irradiance,
+            environment::bind_groups::BindGroupLayout0 {
+                params: self.cube_params.as_entire_buffer_binding(),
+                equirectangular: &equirectangular,
+                environment: &self.environment_view,
+                environment_sampler: &self.sampler,
+                output: &self.irradiance_storage,
+            },
+            IRRADIANCE_SIZE,
+            6,
+        );
+        for (mip, (output, params)) in self
+            .prefiltered_storage
+            .iter()
+            .zip(self.prefiltered_params.iter())
+            .enumerate()
+        {
+            self.dispatch(
+                &mut encoder,
+                device,
+                &self.pipelines.prefilter,
+                environment::bind_groups::BindGroupLayout0 {
+                    params: params.as_entire_buffer_binding(),
+                    equirectangular: &equirectangular,
+                    environment: &self.environment_view,
+                    environment_sampler: &self.sampler,
+                    output,
+                },
+                (PREFILTERED_SIZE >> mip).max(1),
+                6,
+            );
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Runs one pass over every texel of the output
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        pipeline: &wgpu::ComputePipeline,
+        bindings: environment::bind_groups::BindGroupLayout0<'_>,
+        size: u32,
+        layers: u32,
+    ) {
+        let bind_group_0 = environment::bind_groups::BindGroup0::from_bindings(device, bindings);
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Environment Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(pipeline);
+        environment::set_bind_groups(&mut compute_pass, &bind_group_0);
+        compute_pass.dispatch_workgroups(size.div_ceil(8), size.div_ceil(8), layers);
+    }
+}
+
+/// A sky with a soft sun, used when no environment image is set
+fn default_sky() -> HdrImage {
+    let (width, height) = (128, 64);
+    let background_color = Vec3::new(0.09, 0.59, 0.85) * 0.8;
+    let sun_direction = Vec3::new(1., 1., 1.).normalize();
+    let data = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let direction = equirectangular_direction(
+                (x as f32 + 0.5) / (width as f32),
+                (y as f32 + 0.5) / (height as f32),
+            );
+            let directional_light = direction.dot(sun_direction).clamp(0.0, 1.0).powi(3) * 0.4;
+            background_color * (1.0 + directional_light)
+        })
+        .collect();
+    HdrImage {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Inverse of `equirectangular_uv` in Environment.wgsl
+fn equirectangular_direction(u: f32, v: f32) -> Vec3 {
+    let phi = (u - 0.5) * std::f32::consts::TAU;
+    let theta = v * std::f32::consts::PI;
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+fn upload_equirectangular(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    image: &HdrImage,
+) -> wgpu::TextureView {
+    let size = wgpu::Extent3d {
+        width: image.width,
+        height: image.height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Equirectangular Environment"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let data = image
+        .data
+        .iter()
+        .flat_map(|color| color.extend(1.0).to_array())
+        .map(f32_to_f16)
+        .collect::<Vec<_>>();
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        bytemuck::cast_slice(&data),
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(image.width * 4 * 2),
+            rows_per_image: Some(image.height),
+        },
+        size,
+    );
+    texture.create_view(&Default::default())
+}
+
+fn create_cube_texture(
+    device: &wgpu::Device,
+    label: &str,
+    size: u32,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn cube_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    })
+}
+
+/// Storage textures can only be written one mip level at a time
+fn storage_view(texture: &wgpu::Texture, mip_level: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: mip_level,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
+
+/// Converts to a half precision float. Values that are too large are clamped to the largest finite half.
+fn f32_to_f16(value: f32) -> u16 {
+    let sign = ((value.to_bits() >> 16) & 0x8000) as u16;
+    let value = value.abs();
+    if value.is_nan() {
+        sign | 0x7e00
+    } else if value >= 65504.0 {
+        sign | 0x7bff
+    } else if value < 6.103_515_6e-5 {
+        // Subnormal
+        sign | (value / 5.960_464_5e-8).round() as u16
+    } else {
+        let bits = value.to_bits();
+        let exponent = (bits >> 23) + 15 - 127;
+        let mantissa = bits & 0x7f_ffff;
+        // Rounding can carry into the exponent, which is correct
+        sign | ((exponent << 10) + ((mantissa + 0x1000) >> 13)) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_half_precision() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1.0e9), 0x7bff);
+    }
+}
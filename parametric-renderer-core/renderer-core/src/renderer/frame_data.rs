@@ -7,11 +7,51 @@ pub struct FrameData {
     pub mouse_pos: glam::Vec2,
     pub mouse_held: bool,
     pub lod_stage: Option<std::sync::Arc<dyn Fn(&crate::game::ShaderId, &str) + 'static>>,
+    /// Only renders a part of a larger image
+    pub tile: Option<FrameTile>,
+}
+
+/// A rectangle of a larger image, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameTile {
+    pub offset: UVec2,
+    pub full_size: UVec2,
 }
 
 impl FrameData {
+    /// The projection for a render target of the given size.
+    /// With a tile, the target only shows that part of the full image.
+    pub fn projection_matrix(&self, size: UVec2) -> glam::Mat4 {
+        match self.tile {
+            Some(tile) => tile.crop_matrix(size) * self.camera.projection_matrix(tile.full_size),
+            None => self.camera.projection_matrix(size),
+        }
+    }
+
     pub fn view_projection_matrix(&self, size: UVec2) -> glam::Mat4 {
-        self.camera.projection_matrix(size) * self.camera.view_matrix()
+        self.projection_matrix(size) * self.camera.view_matrix()
+    }
+
+    /// Size of the whole image, which is larger than the render target when rendering a tile
+    pub fn full_size(&self, size: UVec2) -> UVec2 {
+        self.tile.map(|tile| tile.full_size).unwrap_or(size)
+    }
+}
+
+impl FrameTile {
+    /// Maps the tile's part of clip space to the whole clip space
+    fn crop_matrix(&self, size: UVec2) -> glam::Mat4 {
+        let full_size = self.full_size.as_vec2();
+        // In normalized device coordinates, y points up
+        let min = self.offset.as_vec2() / full_size;
+        let max = (self.offset + size).as_vec2() / full_size;
+        let min_ndc = glam::Vec2::new(min.x * 2.0 - 1.0, 1.0 - max.y * 2.0);
+        let max_ndc = glam::Vec2::new(max.x * 2.0 - 1.0, 1.0 - min.y * 2.0);
+
+        let scale = 2.0 / (max_ndc - min_ndc);
+        let center = (min_ndc + max_ndc) * 0.5;
+        glam::Mat4::from_scale(scale.extend(1.0))
+            * glam::Mat4::from_translation((-center).extend(0.0))
     }
 }
 
@@ -22,6 +62,7 @@ impl Default for FrameData {
             mouse_pos: Default::default(),
             mouse_held: Default::default(),
             lod_stage: Default::default(),
+            tile: None,
         }
     }
 }
@@ -1,20 +1,28 @@
 use reactive_graph::{
     computed::Memo,
-    signal::RwSignal,
-    traits::{Read, Set},
+    owner::StoredValue,
+    prelude::*,
+    signal::{ReadSignal, RwSignal},
 };
 
 use crate::{
-    buffer::DeviceBufferExt, mesh::Mesh, reactive::MemoComputed, shaders::ground_plane,
+    buffer::DeviceBufferExt,
+    mesh::Mesh,
+    reactive::{IntoValueView, MemoComputed, RenderCommand, RenderComponent},
+    shaders::ground_plane,
     texture::Texture,
 };
 
-use super::{FrameData, get_context, wgpu_context::SurfaceOrFallback};
+use super::{
+    FrameData, get_context, main_pass_targets, scene::SceneData, wgpu_context::SurfaceOrFallback,
+};
 
 /// Renders the ground plane
 pub fn ground_plane_component(
     surface: RwSignal<SurfaceOrFallback>,
-) -> impl Fn(&FrameData, &mut wgpu_profiler::OwningScope<'_, wgpu::RenderPass<'_>>) {
+    scene_data: StoredValue<SceneData>,
+    sample_count: ReadSignal<u32>,
+) -> RenderComponent<FrameData> {
     let context = &get_context();
     let quad_mesh = Mesh::new_tesselated_quad(&context.device, 2);
 
@@ -74,18 +82,9 @@ pub fn ground_plane_component(
                     ),
                     fragment: Some(ground_plane::fragment_state(
                         &shader,
-                        &ground_plane::fs_main_entry([
-                            Some(wgpu::ColorTargetState {
-                                format: context.view_format,
-                                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                                write_mask: wgpu::ColorWrites::ALL,
-                            }),
-                            Some(wgpu::ColorTargetState {
-                                format: wgpu::TextureFormat::R32Uint,
-                                blend: None,
-                                write_mask: wgpu::ColorWrites::empty(),
-                            }),
-                        ]),
+                        &ground_plane::fs_main_entry(main_pass_targets(Some(
+                            wgpu::BlendState::ALPHA_BLENDING,
+                        ))),
                     )),
                     primitive: Default::default(),
                     depth_stencil: Some(wgpu::DepthStencilState {
@@ -95,7 +94,10 @@ pub fn ground_plane_component(
                         stencil: Default::default(),
                         bias: Default::default(),
                     }),
-                    multisample: Default::default(),
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count.get(),
+                        ..Default::default()
+                    },
                     multiview: None,
                     cache: Default::default(),
                 })
@@ -112,14 +114,27 @@ pub fn ground_plane_component(
         wgpu::BufferUsages::COPY_DST,
     );
 
-    let bind_group_0 = ground_plane::bind_groups::BindGroup0::from_bindings(
-        &context.device,
-        ground_plane::bind_groups::BindGroupLayout0 {
-            uniforms: uniforms.as_entire_buffer_binding(),
-        },
-    );
+    let bind_group_0 = scene_data.with_value(|scene_data| {
+        ground_plane::bind_groups::BindGroup0::from_bindings(
+            &context.device,
+            ground_plane::bind_groups::BindGroupLayout0 {
+                uniforms: uniforms.as_entire_buffer_binding(),
+                shadows: scene_data.shadows_buffer.as_entire_buffer_binding(),
+                shadow_map: &scene_data.shadow_maps.array_view,
+                shadow_sampler: &scene_data.shadow_maps.sampler,
+            },
+        )
+    });
 
-    move |render_data: &FrameData, render_pass| {
+    let mut commands = vec![
+        RenderCommand::SetPipeline(pipeline.into_value_view()),
+        RenderCommand::action(move |render_pass: &mut wgpu::RenderPass<'_>| {
+            bind_group_0.set(render_pass)
+        }),
+    ];
+    commands.extend(quad_mesh.draw_commands());
+
+    RenderComponent::new(commands).on_update(move |render_data: &FrameData| {
         let context = &get_context();
         #[cfg(feature = "desktop")]
         let _watcher = &_file_watcher;
@@ -137,11 +152,5 @@ pub fn ground_plane_component(
                 grid_scale,
             },
         );
-
-        render_pass.set_pipeline(&pipeline.read());
-        render_pass.set_vertex_buffer(0, quad_mesh.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(quad_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        bind_group_0.set(&mut render_pass.recorder);
-        render_pass.draw_indexed(0..quad_mesh.num_indices, 0, 0..1);
-    }
+    })
 }
@@ -0,0 +1,83 @@
+//! The object IDs stay single-sampled when the main pass is multisampled.
+//! Integer textures cannot be resolved, so the first sample of every pixel gets copied instead.
+
+use reactive_graph::{computed::Memo, prelude::*};
+
+use crate::{
+    reactive::{
+        ColorAttachment, DepthStencilAttachment, IntoValueView, MemoComputed, RenderCommand,
+        render_pass,
+    },
+    shaders::object_id_resolve,
+    texture::Texture,
+};
+
+use super::get_context;
+
+type Commands<'a> = wgpu_profiler::Scope<'a, wgpu::CommandEncoder>;
+
+/// Copies the multisampled object IDs into the single-sampled texture. Does nothing without multisampling.
+pub fn object_id_resolve_component(
+    object_id_texture: Memo<Texture>,
+    multisampled_object_id_texture: Memo<Option<Texture>>,
+) -> impl Fn(&mut Commands<'_>) {
+    let device = &get_context().device;
+    let shader = object_id_resolve::create_shader_module(device);
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Object ID Resolve"),
+        layout: Some(&object_id_resolve::create_pipeline_layout(device)),
+        vertex: object_id_resolve::vertex_state(&shader, &object_id_resolve::vs_main_entry()),
+        fragment: Some(object_id_resolve::fragment_state(
+            &shader,
+            &object_id_resolve::fs_main_entry([Some(wgpu::TextureFormat::R32Uint.into())]),
+        )),
+        primitive: Default::default(),
+        depth_stencil: None,
+        multisample: Default::default(),
+        multiview: None,
+        cache: Default::default(),
+    });
+
+    let bind_group = Memo::new_computed(move |_| {
+        multisampled_object_id_texture
+            .read()
+            .as_ref()
+            .map(|source| {
+                object_id_resolve::bind_groups::BindGroup0::from_bindings(
+                    &get_context().device,
+                    object_id_resolve::bind_groups::BindGroupLayout0 {
+                        source: &source.view,
+                    },
+                )
+            })
+    });
+    let target = Memo::new_computed(move |_| object_id_texture.read().view.clone());
+
+    let pass = render_pass(
+        vec![ColorAttachment {
+            view: target.into_value_view(),
+            resolve_target: None,
+            ops: Default::default(),
+        }],
+        None::<DepthStencilAttachment>,
+    )
+    .label("Object ID Resolve".to_string())
+    .commands(vec![
+        RenderCommand::SetPipeline(pipeline.into_value_view()),
+        RenderCommand::action(move |render_pass: &mut wgpu::RenderPass<'_>| {
+            if let Some(bind_group) = bind_group.read().as_ref() {
+                bind_group.set(render_pass);
+            }
+        }),
+        RenderCommand::Draw {
+            vertices: 0..3,
+            instances: 0..1,
+        },
+    ]);
+
+    move |commands: &mut Commands<'_>| {
+        if bind_group.with(Option::is_some) {
+            pass.record(commands);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Compiled pipelines, which are kept on disk between runs.
+//! Only some native backends support this, everywhere else the pipelines are always compiled from scratch.
+//! There is one file per adapter and shader, which the latest version of the shader overwrites.
+
+use std::path::PathBuf;
+
+use log::warn;
+
+use super::wgpu_context::WgpuContext;
+
+/// Relative to the working directory, like the cache file of the desktop app
+const PIPELINE_CACHE_DIR: &str = "pipeline-cache";
+
+pub struct PipelineCache {
+    cache: wgpu::PipelineCache,
+    path: PathBuf,
+    /// Stored in front of the data, so that the pipelines of an older version of the shader are not loaded
+    sources_hash: u64,
+}
+
+impl PipelineCache {
+    /// Loads the cache of a shader, if it was saved for the same sources.
+    /// `None` if the device does not support pipeline caches.
+    pub fn new(context: &WgpuContext, shader_key: &str, sources: &[&str]) -> Option<Self> {
+        let key = context.pipeline_cache_key.as_ref()?;
+        let path = PathBuf::from(PIPELINE_CACHE_DIR)
+            .join(format!("{key}-{:016x}", stable_hash(&[shader_key])));
+        let sources_hash = stable_hash(sources);
+        let file = std::fs::read(&path).ok();
+        let data = file
+            .as_deref()
+            .and_then(|file| file.split_first_chunk::<8>())
+            .filter(|(hash, _)| u64::from_le_bytes(**hash) == sources_hash)
+            .map(|(_, data)| data);
+        // SAFETY: The data was written by `save` for the same adapter and driver, since those are part of the key.
+        // With `fallback`, data that the driver rejects leads to an empty cache instead of an error.
+        let cache = unsafe {
+            context
+                .device
+                .create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                    label: Some("Pipeline Cache"),
+                    data,
+                    fallback: true,
+                })
+        };
+        Some(Self {
+            cache,
+            path,
+            sources_hash,
+        })
+    }
+
+    pub fn get(&self) -> &wgpu::PipelineCache {
+        &self.cache
+    }
+
+    /// Writes the pipelines that have been created so far
+    pub fn save(&self) {
+        let Some(data) = self.cache.get_data() else {
+            return;
+        };
+        let mut file = self.sources_hash.to_le_bytes().to_vec();
+        file.extend(data);
+        if let Err(error) = std::fs::create_dir_all(PIPELINE_CACHE_DIR)
+            .and_then(|_| std::fs::write(&self.path, file))
+        {
+            warn!(
+                "Failed to save the pipeline cache {:?}: {}",
+                self.path, error
+            );
+        }
+    }
+}
+
+/// FNV-1a, which stays the same across Rust versions, unlike the hashers of the standard library
+fn stable_hash(parts: &[&str]) -> u64 {
+    let mut hash = 0xcbf29ce484222325_u64;
+    for part in parts {
+        // The length keeps ["ab", "c"] and ["a", "bc"] apart
+        for byte in (part.len() as u64)
+            .to_le_bytes()
+            .iter()
+            .chain(part.as_bytes())
+        {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
@@ -0,0 +1,444 @@
+//! Turns the HDR image of the main render pass into the final image.
+//! The effects form a chain, where each one reads the HDR image of the previous one and writes a new one.
+//! Tone mapping always comes last, since it writes to the screen.
+
+use std::sync::Arc;
+
+use glam::UVec2;
+use reactive_graph::{
+    computed::Memo,
+    effect::Effect,
+    owner::StoredValue,
+    prelude::*,
+    signal::{ReadSignal, RwSignal},
+};
+
+use crate::{
+    buffer::DeviceBufferExt,
+    color_grading::ColorGradingLut,
+    game::{PostProcessInfo, ToneMapping},
+    reactive::MemoComputed,
+    shaders::{bloom, tone_mapping},
+    texture::Texture,
+};
+
+use super::{get_context, wgpu_context::SurfaceOrFallback};
+
+/// The main render pass renders into this, so that bright colors do not clip
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Upper limit for the length of the bloom chain, which starts at half resolution
+const BLOOM_MIP_COUNT: u32 = 6;
+
+type Commands<'a> = wgpu_profiler::Scope<'a, wgpu::CommandEncoder>;
+type RenderHdrEffect = dyn Fn(&mut Commands<'_>, &Texture, &Texture) + Send + Sync;
+
+/// One step of the post processing chain.
+/// Reads the input and overwrites the whole output, which are HDR images with the same size.
+struct HdrEffect {
+    /// Disabled effects get skipped
+    enabled: Box<dyn Fn() -> bool + Send + Sync>,
+    render: Box<RenderHdrEffect>,
+}
+
+/// Applies the effects to the HDR texture, and then writes the tone mapped result to the output
+pub fn post_process_component(
+    surface: RwSignal<SurfaceOrFallback>,
+    settings: ReadSignal<PostProcessInfo>,
+    color_grading: ReadSignal<Option<Arc<ColorGradingLut>>>,
+) -> impl Fn(&mut Commands<'_>, &Texture, &wgpu::TextureView) {
+    let effects: Arc<[HdrEffect]> = Arc::new([bloom_effect(surface, settings)]);
+    let tone_mapping = tone_mapping_component(settings, color_grading);
+
+    // The effects alternate between the HDR texture and this one
+    let scratch_texture = Memo::new_computed({
+        let effects = effects.clone();
+        move |_| {
+            let any_enabled = effects.iter().any(|effect| (effect.enabled)());
+            any_enabled.then(|| {
+                Texture::create_render_target(
+                    &get_context().device,
+                    surface.read().size(),
+                    HDR_FORMAT,
+                    1,
+                    "Post Process Texture",
+                )
+            })
+        }
+    });
+
+    move |commands, hdr_texture, output| {
+        let scratch_texture = scratch_texture.read();
+        let mut input = hdr_texture;
+        if let Some(scratch_texture) = scratch_texture.as_ref() {
+            let mut output = scratch_texture;
+            for effect in effects.iter().filter(|effect| (effect.enabled)()) {
+                (effect.render)(commands, input, output);
+                std::mem::swap(&mut input, &mut output);
+            }
+        }
+        tone_mapping(commands, input, output);
+    }
+}
+
+fn fullscreen_pipeline(
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    vertex: wgpu::VertexState<'_>,
+    fragment: wgpu::FragmentState<'_>,
+) -> wgpu::RenderPipeline {
+    get_context()
+        .device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex,
+            fragment: Some(fragment),
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: Default::default(),
+        })
+}
+
+fn fullscreen_pass<'a>(
+    commands: &'a mut Commands<'_>,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) -> wgpu_profiler::OwningScope<'a, wgpu::RenderPass<'a>> {
+    commands.scoped_render_pass(
+        label,
+        wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        },
+    )
+}
+
+fn linear_clamp_sampler(label: &str) -> wgpu::Sampler {
+    get_context()
+        .device
+        .create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        })
+}
+
+struct BloomChain {
+    /// One per mip level
+    views: Vec<wgpu::TextureView>,
+    /// One per mip level, for reading from that level
+    bind_groups: Vec<bloom::bind_groups::BindGroup0>,
+}
+
+/// Blurs the bright parts of the image and adds them back onto it
+fn bloom_effect(
+    surface: RwSignal<SurfaceOrFallback>,
+    settings: ReadSignal<PostProcessInfo>,
+) -> HdrEffect {
+    let context = &get_context();
+    let device = &context.device;
+    let shader = bloom::create_shader_module(device);
+    let layout = bloom::create_pipeline_layout(device);
+    let vertex_entry = bloom::vs_main_entry();
+    let vertex = || bloom::vertex_state(&shader, &vertex_entry);
+    let target = |blend| {
+        [Some(wgpu::ColorTargetState {
+            format: HDR_FORMAT,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        })]
+    };
+    let blend = |src_factor| {
+        let component = wgpu::BlendComponent {
+            src_factor,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        Some(wgpu::BlendState {
+            color: component,
+            alpha: component,
+        })
+    };
+
+    let prefilter = fullscreen_pipeline(
+        "Bloom Prefilter",
+        &layout,
+        vertex(),
+        bloom::fragment_state(&shader, &bloom::fs_prefilter_entry(target(None))),
+    );
+    let downsample = fullscreen_pipeline(
+        "Bloom Downsample",
+        &layout,
+        vertex(),
+        bloom::fragment_state(&shader, &bloom::fs_downsample_entry(target(None))),
+    );
+    let upsample = fullscreen_pipeline(
+        "Bloom Upsample",
+        &layout,
+        vertex(),
+        bloom::fragment_state(
+            &shader,
+            &bloom::fs_upsample_entry(target(blend(wgpu::BlendFactor::One))),
+        ),
+    );
+    // The last upsample adds the bloom onto the image, scaled by the blend constant
+    let composite = fullscreen_pipeline(
+        "Bloom Composite",
+        &layout,
+        vertex(),
+        bloom::fragment_state(
+            &shader,
+            &bloom::fs_upsample_entry(target(blend(wgpu::BlendFactor::Constant))),
+        ),
+    );
+    let sampler = StoredValue::new(linear_clamp_sampler("Bloom Sampler"));
+    let params = StoredValue::new(device.uniform_buffer(
+        "Bloom Params",
+        &bloom::BloomParams {
+            threshold: 1.0,
+            knee: 0.5,
+        },
+        wgpu::BufferUsages::COPY_DST,
+    ));
+
+    Effect::new(move |_| {
+        if let Some(bloom) = &settings.read().bloom {
+            params.read_value().write_buffer(
+                &get_context().queue,
+                &bloom::BloomParams {
+                    threshold: bloom.threshold,
+                    knee: bloom.threshold * 0.5,
+                },
+            );
+        }
+    });
+
+    let chain = Memo::new_computed(move |_| {
+        let device = &get_context().device;
+        let size = (surface.read().size() / 2).max(UVec2::ONE);
+        let mip_level_count = BLOOM_MIP_COUNT.min(size.max_element().ilog2() + 1);
+        let texture =
+            Texture::create_render_target(device, size, HDR_FORMAT, mip_level_count, "Bloom");
+        let views = (0..mip_level_count)
+            .map(|mip_level| {
+                texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom Mip"),
+                    base_mip_level: mip_level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let bind_groups = views
+            .iter()
+            .map(|view| {
+                bloom::bind_groups::BindGroup0::from_bindings(
+                    device,
+                    bloom::bind_groups::BindGroupLayout0 {
+                        source: view,
+                        source_sampler: &sampler.read_value(),
+                        params: params.read_value().as_entire_buffer_binding(),
+                    },
+                )
+            })
+            .collect();
+        BloomChain { views, bind_groups }
+    });
+
+    let render = move |commands: &mut Commands<'_>, input: &Texture, output: &Texture| {
+        let context = &get_context();
+        let Some(intensity) = settings.read().bloom.as_ref().map(|bloom| bloom.intensity) else {
+            return;
+        };
+        let chain = chain.read();
+        let input_bind_group = bloom::bind_groups::BindGroup0::from_bindings(
+            &context.device,
+            bloom::bind_groups::BindGroupLayout0 {
+                source: &input.view,
+                source_sampler: &sampler.read_value(),
+                params: params.read_value().as_entire_buffer_binding(),
+            },
+        );
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        {
+            let mut pass = fullscreen_pass(commands, "Bloom Prefilter", &chain.views[0], clear);
+            pass.set_pipeline(&prefilter);
+            input_bind_group.set(&mut pass.recorder);
+            pass.draw(0..3, 0..1);
+        }
+        for level in 1..chain.views.len() {
+            let mut pass =
+                fullscreen_pass(commands, "Bloom Downsample", &chain.views[level], clear);
+            pass.set_pipeline(&downsample);
+            chain.bind_groups[level - 1].set(&mut pass.recorder);
+            pass.draw(0..3, 0..1);
+        }
+        for level in (1..chain.views.len()).rev() {
+            let mut pass = fullscreen_pass(
+                commands,
+                "Bloom Upsample",
+                &chain.views[level - 1],
+                wgpu::LoadOp::Load,
+            );
+            pass.set_pipeline(&upsample);
+            chain.bind_groups[level].set(&mut pass.recorder);
+            pass.draw(0..3, 0..1);
+        }
+
+        commands.copy_texture_to_texture(
+            input.texture.as_image_copy(),
+            output.texture.as_image_copy(),
+            input.texture.size(),
+        );
+        let mut pass = fullscreen_pass(
+            commands,
+            "Bloom Composite",
+            &output.view,
+            wgpu::LoadOp::Load,
+        );
+        pass.set_pipeline(&composite);
+        pass.set_blend_constant(wgpu::Color {
+            r: intensity as f64,
+            g: intensity as f64,
+            b: intensity as f64,
+            a: 0.0,
+        });
+        chain.bind_groups[0].set(&mut pass.recorder);
+        pass.draw(0..3, 0..1);
+    };
+
+    HdrEffect {
+        enabled: Box::new(move || settings.read().bloom.is_some()),
+        render: Box::new(render),
+    }
+}
+
+/// Exposure, tone mapping and color grading
+fn tone_mapping_component(
+    settings: ReadSignal<PostProcessInfo>,
+    color_grading: ReadSignal<Option<Arc<ColorGradingLut>>>,
+) -> impl Fn(&mut Commands<'_>, &Texture, &wgpu::TextureView) {
+    let context = &get_context();
+    let device = &context.device;
+    let shader = tone_mapping::create_shader_module(device);
+    let pipeline = fullscreen_pipeline(
+        "Tone Mapping",
+        &tone_mapping::create_pipeline_layout(device),
+        tone_mapping::vertex_state(&shader, &tone_mapping::vs_main_entry()),
+        tone_mapping::fragment_state(
+            &shader,
+            &tone_mapping::fs_main_entry([Some(context.view_format.into())]),
+        ),
+    );
+    let sampler = linear_clamp_sampler("Tone Mapping Sampler");
+    let params = StoredValue::new(device.uniform_buffer(
+        "Tone Mapping Params",
+        &tone_mapping::ToneMappingParams {
+            exposure: 1.0,
+            tone_mapping: tone_mapping::TONE_MAPPING_NONE,
+            has_lut: 0,
+        },
+        wgpu::BufferUsages::COPY_DST,
+    ));
+
+    Effect::new(move |_| {
+        let settings = settings.read();
+        params.read_value().write_buffer(
+            &get_context().queue,
+            &tone_mapping::ToneMappingParams {
+                exposure: settings.exposure.exp2(),
+                tone_mapping: match settings.tone_mapping {
+                    ToneMapping::None => tone_mapping::TONE_MAPPING_NONE,
+                    ToneMapping::Aces => tone_mapping::TONE_MAPPING_ACES,
+                    ToneMapping::AgX => tone_mapping::TONE_MAPPING_AGX,
+                },
+                has_lut: if color_grading.read().is_some() { 1 } else { 0 },
+            },
+        );
+    });
+
+    let lut = Memo::new_computed(move |_| {
+        let context = &get_context();
+        let color_grading = color_grading.read();
+        // Unused placeholder when there is no color grading
+        let identity = ColorGradingLut::identity(2);
+        create_lut_texture(
+            &context.device,
+            &context.queue,
+            color_grading.as_deref().unwrap_or(&identity),
+        )
+    });
+
+    move |commands, input, output| {
+        let context = &get_context();
+        let lut = lut.read();
+        let bind_group = tone_mapping::bind_groups::BindGroup0::from_bindings(
+            &context.device,
+            tone_mapping::bind_groups::BindGroupLayout0 {
+                hdr: &input.view,
+                hdr_sampler: &sampler,
+                params: params.read_value().as_entire_buffer_binding(),
+                lut: &lut.view,
+                lut_sampler: &sampler,
+            },
+        );
+        let mut pass = fullscreen_pass(
+            commands,
+            "Tone Mapping",
+            output,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+        );
+        pass.set_pipeline(&pipeline);
+        bind_group.set(&mut pass.recorder);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_lut_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    lut: &ColorGradingLut,
+) -> Texture {
+    let size = wgpu::Extent3d {
+        width: lut.size,
+        height: lut.size,
+        depth_or_array_layers: lut.size,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Color Grading LUT"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        texture.as_image_copy(),
+        &lut.to_rgba8(),
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * lut.size),
+            rows_per_image: Some(lut.size),
+        },
+        size,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    Texture { texture, view }
+}
@@ -1,7 +1,16 @@
-use crate::{buffer::TypedBuffer, camera::Camera, shaders::shader, time::FrameTime};
-use glam::{Mat4, UVec2, Vec2, Vec4};
+use crate::{
+    buffer::TypedBuffer,
+    game::{DebugView, LightInfo, LightKind},
+    shaders::shader,
+    time::FrameTime,
+};
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec4};
 
-use super::FrameData;
+use super::{
+    FrameData,
+    environment::EnvironmentMaps,
+    shadows::{SHADOW_CASCADE_COUNT, SHADOW_MAP_SIZE, ShadowCascade, ShadowMaps, compute_cascades},
+};
 
 pub struct SceneData {
     pub time_buffer: TypedBuffer<shader::Time>,
@@ -10,11 +19,32 @@ pub struct SceneData {
     pub extra_buffer: TypedBuffer<shader::Extra>,
     pub camera_buffer: TypedBuffer<shader::Camera>,
     pub light_buffer: TypedBuffer<shader::Lights>,
+    /// How many lights fit into the light buffer
+    light_capacity: usize,
     pub linear_sampler: wgpu::Sampler,
+    pub shadows_buffer: TypedBuffer<shader::Shadows>,
+    /// The camera for rendering each shadow cascade
+    pub shadow_camera_buffers: Vec<TypedBuffer<shader::Camera>>,
+    pub shadow_maps: ShadowMaps,
+    /// The directional light that casts shadows
+    pub shadow_light: Option<ShadowLight>,
+    pub debug_buffer: TypedBuffer<shader::Debug>,
+    /// Image based lighting and the skybox
+    pub environment: EnvironmentMaps,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowLight {
+    /// Index into the lights buffer
+    pub index: u32,
+    pub direction: Vec3,
 }
 
 impl SceneData {
-    pub fn new(device: &wgpu::Device) -> Self {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let lights = LightInfo::default_lights();
+        let light_capacity = lights.len().max(1);
+        let shadow_light = find_shadow_light(&lights);
         Self {
             time_buffer: TypedBuffer::new_uniform(
                 device,
@@ -60,46 +90,48 @@ impl SceneData {
                 },
                 wgpu::BufferUsages::COPY_DST,
             ),
-            light_buffer: TypedBuffer::new_storage(
-                device,
-                "Light Buffer",
-                &shader::Lights {
-                    ambient: Vec4::new(0.05, 0.05, 0.05, 0.0),
-                    points_length: 4,
-                    points: vec![
-                        shader::LightSource {
-                            position_range: glam::Vec3::new(1.0, -4.0, 1.0).normalize().extend(1.0),
-                            color_intensity: Vec4::new(0.5, 0.55, 0.5, 0.9),
-                            light_type: shader::LIGHT_TYPE_DIRECTIONAL,
-                        },
-                        shader::LightSource {
-                            position_range: Vec4::new(0.0, 8.0, 4.0, 80.0),
-                            color_intensity: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                            light_type: shader::LIGHT_TYPE_POINT,
-                        },
-                        shader::LightSource {
-                            position_range: Vec4::new(1.0, 8.0, -6.0, 70.0),
-                            color_intensity: Vec4::new(1.0, 1.0, 1.0, 1.5),
-                            light_type: shader::LIGHT_TYPE_POINT,
-                        },
-                        shader::LightSource {
-                            position_range: Vec4::new(0.0, -8.0, 0.0, 80.0),
-                            color_intensity: Vec4::new(0.8, 0.8, 1.0, 0.9),
-                            light_type: shader::LIGHT_TYPE_POINT,
-                        },
-                    ],
-                },
-                wgpu::BufferUsages::COPY_DST,
-            ),
+            light_buffer: create_light_buffer(device, &lights, light_capacity),
+            light_capacity,
             linear_sampler: device.create_sampler(&wgpu::SamplerDescriptor {
                 address_mode_u: wgpu::AddressMode::Repeat,
                 address_mode_v: wgpu::AddressMode::Repeat,
                 address_mode_w: wgpu::AddressMode::Repeat,
                 mag_filter: wgpu::FilterMode::Linear,
                 min_filter: wgpu::FilterMode::Linear,
-                mipmap_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Linear,
                 ..Default::default()
             }),
+            shadows_buffer: TypedBuffer::new_uniform(
+                device,
+                "Shadows Buffer",
+                &disabled_shadows(),
+                wgpu::BufferUsages::COPY_DST,
+            ),
+            shadow_camera_buffers: (0..SHADOW_CASCADE_COUNT)
+                .map(|i| {
+                    TypedBuffer::new_uniform(
+                        device,
+                        &format!("Shadow Camera Buffer {i}"),
+                        &shader::Camera {
+                            view: Mat4::IDENTITY,
+                            projection: Mat4::IDENTITY,
+                            world_position: Vec4::ZERO,
+                        },
+                        wgpu::BufferUsages::COPY_DST,
+                    )
+                })
+                .collect(),
+            shadow_maps: ShadowMaps::new(device),
+            shadow_light,
+            debug_buffer: TypedBuffer::new_uniform(
+                device,
+                "Debug Buffer",
+                &shader::Debug {
+                    view: DebugView::None.to_shader(),
+                },
+                wgpu::BufferUsages::COPY_DST,
+            ),
+            environment: EnvironmentMaps::new(device, queue),
         }
     }
 
@@ -114,10 +146,82 @@ impl SceneData {
                 mouse: self.mouse_buffer.as_entire_buffer_binding(),
                 lights: self.light_buffer.as_entire_buffer_binding(),
                 linear_sampler: &self.linear_sampler,
+                shadows: self.shadows_buffer.as_entire_buffer_binding(),
+                shadow_map: &self.shadow_maps.array_view,
+                shadow_sampler: &self.shadow_maps.sampler,
+                irradiance_map: &self.environment.irradiance_view,
+                prefiltered_map: &self.environment.prefiltered_view,
+                brdf_lut: &self.environment.brdf_lut_view,
+                environment_sampler: &self.environment.sampler,
+                debug: self.debug_buffer.as_entire_buffer_binding(),
             },
         )
     }
 
+    /// Writes the lights, and reallocates the light buffer if they do not fit.
+    /// Returns true if the buffer was reallocated, in which case the bind groups must be recreated.
+    pub fn update_lights(
+        &mut self,
+        lights: &[LightInfo],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> bool {
+        self.shadow_light = find_shadow_light(lights);
+        if self.shadow_light.is_none() {
+            self.shadows_buffer.write_buffer(queue, &disabled_shadows());
+        }
+        if lights.len() > self.light_capacity {
+            self.light_capacity = lights.len().next_power_of_two();
+            self.light_buffer = create_light_buffer(device, lights, self.light_capacity);
+            true
+        } else {
+            self.light_buffer
+                .write_buffer(queue, &lights_to_shader(lights));
+            false
+        }
+    }
+
+    pub fn set_debug_view(&self, debug_view: DebugView, queue: &wgpu::Queue) {
+        self.debug_buffer.write_buffer(
+            queue,
+            &shader::Debug {
+                view: debug_view.to_shader(),
+            },
+        );
+    }
+
+    /// One bind group per shadow cascade, with the light as the camera
+    pub fn as_shadow_bind_groups_0(
+        &self,
+        device: &wgpu::Device,
+    ) -> Vec<shader::bind_groups::BindGroup0> {
+        self.shadow_camera_buffers
+            .iter()
+            .map(|camera_buffer| {
+                shader::bind_groups::BindGroup0::from_bindings(
+                    device,
+                    shader::bind_groups::BindGroupLayout0 {
+                        camera: camera_buffer.as_entire_buffer_binding(),
+                        time: self.time_buffer.as_entire_buffer_binding(),
+                        screen: self.screen_buffer.as_entire_buffer_binding(),
+                        extra: self.extra_buffer.as_entire_buffer_binding(),
+                        mouse: self.mouse_buffer.as_entire_buffer_binding(),
+                        lights: self.light_buffer.as_entire_buffer_binding(),
+                        linear_sampler: &self.linear_sampler,
+                        shadows: self.shadows_buffer.as_entire_buffer_binding(),
+                        shadow_map: &self.shadow_maps.dummy_view,
+                        shadow_sampler: &self.shadow_maps.sampler,
+                        irradiance_map: &self.environment.irradiance_view,
+                        prefiltered_map: &self.environment.prefiltered_view,
+                        brdf_lut: &self.environment.brdf_lut_view,
+                        environment_sampler: &self.environment.sampler,
+                        debug: self.debug_buffer.as_entire_buffer_binding(),
+                    },
+                )
+            })
+            .collect()
+    }
+
     pub fn write_buffers(
         &self,
         size: UVec2,
@@ -147,17 +251,125 @@ impl SceneData {
                 buttons: if render_data.mouse_held { 1 } else { 0 },
             },
         );
-        self.camera_buffer
-            .write_buffer(queue, &render_data.camera.to_shader(size));
+        self.camera_buffer.write_buffer(
+            queue,
+            &shader::Camera {
+                view: render_data.camera.view_matrix(),
+                projection: render_data.projection_matrix(size),
+                world_position: render_data.camera.position.extend(1.0),
+            },
+        );
+
+        if let Some(shadow_light) = self.shadow_light {
+            // Every tile of an image has to use the same cascades
+            let cascades = compute_cascades(
+                &render_data.camera,
+                render_data.full_size(size),
+                shadow_light.direction,
+            );
+            for (camera_buffer, cascade) in self.shadow_camera_buffers.iter().zip(cascades.iter()) {
+                camera_buffer.write_buffer(queue, &cascade.to_shader_camera());
+            }
+            self.shadows_buffer.write_buffer(
+                queue,
+                &shader::Shadows {
+                    cascades: cascades.map(|cascade| shader::ShadowCascade {
+                        view_projection: cascade.view_projection(),
+                        texel_world_size: cascade.texel_world_size,
+                    }),
+                    light_index: shadow_light.index,
+                    texel_size: 1.0 / (SHADOW_MAP_SIZE as f32),
+                },
+            );
+        }
+    }
+}
+
+fn disabled_shadows() -> shader::Shadows {
+    shader::Shadows {
+        cascades: std::array::from_fn(|_| shader::ShadowCascade {
+            view_projection: Mat4::IDENTITY,
+            texel_world_size: 0.0,
+        }),
+        light_index: shader::NO_SHADOW_LIGHT,
+        texel_size: 1.0 / (SHADOW_MAP_SIZE as f32),
+    }
+}
+
+fn create_light_buffer(
+    device: &wgpu::Device,
+    lights: &[LightInfo],
+    capacity: usize,
+) -> TypedBuffer<shader::Lights> {
+    TypedBuffer::new_storage_with_runtime_array(
+        device,
+        "Light Buffer",
+        &lights_to_shader(lights),
+        capacity as u64,
+        wgpu::BufferUsages::COPY_DST,
+    )
+}
+
+fn lights_to_shader(lights: &[LightInfo]) -> shader::Lights {
+    shader::Lights {
+        points_length: lights.len() as u32,
+        points: lights.iter().map(LightInfo::to_shader).collect(),
+    }
+}
+
+/// The first directional light casts shadows
+fn find_shadow_light(lights: &[LightInfo]) -> Option<ShadowLight> {
+    lights
+        .iter()
+        .enumerate()
+        .find_map(|(index, light)| match light.kind {
+            LightKind::Directional { direction } => Some(ShadowLight {
+                index: index as u32,
+                direction: direction.normalize_or(Vec3::NEG_Y),
+            }),
+            LightKind::Point { .. } => None,
+        })
+}
+
+impl LightInfo {
+    fn to_shader(&self) -> shader::LightSource {
+        let (position_range, light_type) = match self.kind {
+            LightKind::Point { position, range } => {
+                (position.extend(range), shader::LIGHT_TYPE_POINT)
+            }
+            LightKind::Directional { direction } => (
+                direction.normalize_or(Vec3::NEG_Y).extend(1.0),
+                shader::LIGHT_TYPE_DIRECTIONAL,
+            ),
+        };
+        shader::LightSource {
+            position_range,
+            color_intensity: self.color.extend(self.intensity),
+            light_type,
+        }
+    }
+}
+
+impl DebugView {
+    fn to_shader(self) -> u32 {
+        match self {
+            DebugView::None => shader::DEBUG_VIEW_NONE,
+            DebugView::Wireframe => shader::DEBUG_VIEW_WIREFRAME,
+            DebugView::PatchBoundaries => shader::DEBUG_VIEW_PATCH_BOUNDARIES,
+            DebugView::PatchSize => shader::DEBUG_VIEW_PATCH_SIZE,
+            DebugView::Uv => shader::DEBUG_VIEW_UV,
+            DebugView::Normals => shader::DEBUG_VIEW_NORMALS,
+            DebugView::InstanceId => shader::DEBUG_VIEW_INSTANCE_ID,
+        }
     }
 }
 
-impl Camera {
-    fn to_shader(&self, size: UVec2) -> shader::Camera {
+impl ShadowCascade {
+    fn to_shader_camera(self) -> shader::Camera {
         shader::Camera {
-            view: self.view_matrix(),
-            projection: self.projection_matrix(size),
-            world_position: self.position.extend(1.0),
+            view: self.view,
+            projection: self.projection,
+            world_position: self.eye.extend(1.0),
         }
     }
 }
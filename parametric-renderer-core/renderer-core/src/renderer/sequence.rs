@@ -0,0 +1,52 @@
+//! Settings for rendering an animation frame by frame, independent of how fast the GPU is.
+
+use glam::{Quat, UVec2, Vec3};
+
+use crate::{camera::Camera, time::Seconds};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceSettings {
+    pub frame_count: u32,
+    pub fps: f32,
+    /// Resolution of every frame, in pixels
+    pub size: UVec2,
+    pub start_time: Seconds,
+    pub turntable: Option<Turntable>,
+}
+
+/// Rotates the camera around the vertical axis over the course of the sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turntable {
+    pub center: Vec3,
+    /// How often the camera goes around the center. Negative values go clockwise.
+    pub revolutions: f32,
+}
+
+impl Default for SequenceSettings {
+    fn default() -> Self {
+        Self {
+            frame_count: 120,
+            fps: 30.0,
+            size: UVec2::new(1920, 1080),
+            start_time: Seconds(0.0),
+            turntable: None,
+        }
+    }
+}
+
+impl SequenceSettings {
+    pub fn frame_time(&self, frame: u32) -> Seconds {
+        Seconds(self.start_time.0 + frame as f32 / self.fps)
+    }
+
+    pub fn camera(&self, camera: &Camera, frame: u32) -> Camera {
+        match &self.turntable {
+            Some(turntable) => {
+                let progress = frame as f32 / self.frame_count as f32;
+                let angle = progress * turntable.revolutions * std::f32::consts::TAU;
+                camera.orbit(turntable.center, Quat::from_rotation_y(angle))
+            }
+            None => camera.clone(),
+        }
+    }
+}
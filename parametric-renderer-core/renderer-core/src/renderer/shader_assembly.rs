@@ -0,0 +1,615 @@
+//! Combines the user code of a parametric shader with the shaders of the renderer.
+//! The user code is parsed once with naga, next to the declarations of the renderer that it can use.
+//! Then every combined shader is parsed and validated, so that mistakes in the user code are reported
+//! with their location in the user code, before any pipeline gets created.
+
+use std::{collections::HashSet, ops::Range};
+
+use naga::{Block, Handle, Module, Span, Statement, TypeInner};
+
+use crate::{
+    shader_includes::ExpandedCode,
+    shader_parameters::ShaderParameters,
+    shaders::{compute_patches, persistent_patches, shader},
+};
+
+/// Functions that a user can optionally define.
+/// If they don't, the default implementation between the `//// START` and `//// END` markers is used.
+const OPTIONAL_FUNCTIONS: [&str; 2] = ["getColor", "sampleNormal"];
+
+/// Helpers with a `std_` prefix, which every user shader can use
+const STDLIB: &str = include_str!("../../../shaders/Stdlib.wgsl");
+
+/// Every user function has the signature `fn(input: vec2f) -> vec3f`
+const USER_FUNCTIONS: [&str; 3] = ["sampleObject", "getColor", "sampleNormal"];
+
+/// The parts of a renderer shader between `//// START` and `//// END` markers, which get replaced
+const REPLACED_REGIONS: [&str; 4] = ["sampleObject", "getColor", "sampleNormal", "Parameters"];
+
+/// The compute shaders that `sampleObject` gets put into
+const COMPUTE_TEMPLATES: [&str; 2] = [compute_patches::SOURCE, persistent_patches::SOURCE];
+
+/// User code that passed the checks, ready to be combined with each renderer shader
+pub struct AssembledShader {
+    pub code: ExpandedCode,
+    /// The complete render shader
+    pub render_source: String,
+    pub render_map: SourceMap,
+    /// The user code without the functions that `sampleObject` does not need, since those
+    /// can use bindings that only the render shader has.
+    /// Removed functions are blanked out, so that the line numbers stay the same.
+    compute_code: String,
+    parameters: String,
+}
+
+impl AssembledShader {
+    pub fn new(
+        code: &ExpandedCode,
+        parameters: &ShaderParameters,
+    ) -> Result<Self, wgpu::CompilationMessage> {
+        let parameters = parameters.to_wgsl();
+        let render_template = Template::parse(shader::SOURCE);
+
+        let mut source = render_template.prelude();
+        source.push_str(&parameters);
+        source.push('\n');
+        let user_map = push_user_code(&mut source, &code.expanded);
+        let module = parse(&source, &user_map, code)?;
+
+        let user_hooks = OPTIONAL_FUNCTIONS
+            .into_iter()
+            .filter(|name| find_function(&module, name).is_some())
+            .collect::<Vec<_>>();
+        for name in USER_FUNCTIONS {
+            if name == "sampleObject" || user_hooks.contains(&name) {
+                check_signature(&module, name, code, &user_map)?;
+            }
+        }
+
+        let compute_templates = COMPUTE_TEMPLATES.map(Template::parse);
+        let renderer_names = std::iter::once(&render_template)
+            .chain(&compute_templates)
+            .flat_map(Template::names)
+            .collect::<HashSet<_>>();
+        for (name, span) in declarations(&module, &source) {
+            if user_map.contains(span) && renderer_names.contains(name) {
+                return Err(user_map.diagnostic(
+                    format!("`{name}` is already declared by the renderer. Please rename it."),
+                    Some(span),
+                    code,
+                ));
+            }
+        }
+
+        let template = replace_parameters(shader::SOURCE, &parameters);
+        let (render_source, render_map) =
+            replace_render_code(&template, &code.expanded, &user_hooks);
+        check(&render_source, &render_map, code)?;
+
+        let shader = Self {
+            code: code.clone(),
+            render_source,
+            render_map,
+            compute_code: blank_unused_functions(&module, &source, &user_map),
+            parameters,
+        };
+        for template in COMPUTE_TEMPLATES {
+            let (source, source_map) = shader.compute_source(template);
+            check(&source, &source_map, code)?;
+        }
+        Ok(shader)
+    }
+
+    /// Puts the user code into a compute shader of the renderer
+    pub fn compute_source(&self, template: &str) -> (String, SourceMap) {
+        let template = replace_parameters(template, &self.parameters);
+        let start = template.find("//// START sampleObject").unwrap();
+        let end = template.find("//// END sampleObject").unwrap();
+
+        let mut result = String::new();
+        result.push_str(&template[..start]);
+        let source_map = push_user_code(&mut result, &self.compute_code);
+        result.push_str(&template[end..]);
+        (result, source_map)
+    }
+}
+
+/// A shader of the renderer, parsed with its default implementations
+struct Template {
+    source: &'static str,
+    module: Module,
+    replaced: Vec<Range<usize>>,
+}
+
+impl Template {
+    fn parse(source: &'static str) -> Self {
+        // The shaders of the renderer are already checked by the build script
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let replaced = REPLACED_REGIONS
+            .into_iter()
+            .filter_map(|name| {
+                let start = source.find(&format!("//// START {name}"))?;
+                let end = source.find(&format!("//// END {name}"))?;
+                Some(start..end)
+            })
+            .collect();
+        Self {
+            source,
+            module,
+            replaced,
+        }
+    }
+
+    fn is_replaced(&self, span: Span) -> bool {
+        span.to_range().is_some_and(|range| {
+            self.replaced
+                .iter()
+                .any(|replaced| replaced.start <= range.start && range.end <= replaced.end)
+        })
+    }
+
+    /// Everything that the renderer declares, which the user code must not declare again
+    fn names(&self) -> impl Iterator<Item = &str> {
+        declarations(&self.module, self.source)
+            .filter(|(_, span)| !self.is_replaced(*span))
+            .map(|(name, _)| name)
+            .chain(
+                self.module
+                    .entry_points
+                    .iter()
+                    .map(|entry| entry.name.as_str()),
+            )
+            .chain(self.aliases().map(|(name, _)| name))
+    }
+
+    /// naga resolves type aliases, so they are not in the module. Returns the name and the declaration.
+    fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.source.lines().filter_map(|line| {
+            let alias = line.trim().strip_prefix("alias ")?;
+            Some((alias.split('=').next()?.trim(), line))
+        })
+    }
+
+    /// The declarations that the user code can use. Leaves out the functions that call the user code.
+    fn prelude(&self) -> String {
+        let mut calls_user_code = self
+            .module
+            .functions
+            .iter()
+            .filter(|(handle, _)| self.is_replaced(self.module.functions.get_span(*handle)))
+            .map(|(handle, _)| handle)
+            .collect::<HashSet<_>>();
+        loop {
+            let callers = self
+                .module
+                .functions
+                .iter()
+                .filter(|(handle, function)| {
+                    let mut called = Vec::new();
+                    called_functions(&function.body, &mut called);
+                    !calls_user_code.contains(handle)
+                        && called.iter().any(|callee| calls_user_code.contains(callee))
+                })
+                .map(|(handle, _)| handle)
+                .collect::<Vec<_>>();
+            if callers.is_empty() {
+                break;
+            }
+            calls_user_code.extend(callers);
+        }
+
+        let excluded = calls_user_code
+            .iter()
+            .filter_map(|handle| self.module.functions[*handle].name.as_deref())
+            .collect::<HashSet<_>>();
+        let mut prelude = String::new();
+        for (_, alias) in self.aliases() {
+            prelude.push_str(alias);
+            prelude.push('\n');
+        }
+        for (name, span) in declarations(&self.module, self.source) {
+            if !self.is_replaced(span) && !excluded.contains(name) {
+                prelude.push_str(&self.source[span.to_range().unwrap()]);
+                prelude.push('\n');
+            }
+        }
+        prelude
+    }
+}
+
+/// The named declarations at module scope, except for the entry points
+fn declarations<'a>(module: &'a Module, source: &str) -> impl Iterator<Item = (&'a str, Span)> {
+    let functions = module.functions.iter().map(|(handle, function)| {
+        let span = braced_span(source, module.functions.get_span(handle));
+        (function.name.as_deref(), span)
+    });
+    let globals = module.global_variables.iter().map(|(handle, global)| {
+        (
+            global.name.as_deref(),
+            module.global_variables.get_span(handle),
+        )
+    });
+    let constants = module
+        .constants
+        .iter()
+        .map(|(handle, constant)| (constant.name.as_deref(), module.constants.get_span(handle)));
+    let overrides = module
+        .overrides
+        .iter()
+        .map(|(handle, constant)| (constant.name.as_deref(), module.overrides.get_span(handle)));
+    let types = module.types.iter().map(|(handle, ty)| {
+        (
+            ty.name.as_deref(),
+            braced_span(source, module.types.get_span(handle)),
+        )
+    });
+    functions
+        .chain(globals)
+        .chain(constants)
+        .chain(overrides)
+        .chain(types)
+        // Types that naga declares itself have no span
+        .filter_map(|(name, span)| Some((name?, span)).filter(|_| span.is_defined()))
+}
+
+/// Where the user code ended up in a combined shader.
+/// The user code is inserted in one piece, so the mapping is a single offset.
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    user_range: Range<usize>,
+}
+
+impl SourceMap {
+    fn contains(&self, span: Span) -> bool {
+        span.to_range().is_some_and(|range| {
+            self.user_range.start <= range.start && range.end <= self.user_range.end
+        })
+    }
+
+    /// `None` if the span is in the renderer code
+    fn user_location(&self, span: Span, code: &ExpandedCode) -> Option<wgpu::SourceLocation> {
+        if !self.contains(span) {
+            return None;
+        }
+        let range = span.to_range()?;
+        let range = code
+            .original_range(range.start - self.user_range.start..range.end - self.user_range.start);
+        let location = Span::new(range.start as u32, range.end as u32).location(&code.original);
+        Some(wgpu::SourceLocation {
+            line_number: location.line_number,
+            line_position: location.line_position,
+            offset: location.offset,
+            length: location.length,
+        })
+    }
+
+    /// Points at the user code, or at nothing if the problem is somewhere in the renderer code
+    fn diagnostic(
+        &self,
+        message: String,
+        span: Option<Span>,
+        code: &ExpandedCode,
+    ) -> wgpu::CompilationMessage {
+        wgpu::CompilationMessage {
+            message,
+            message_type: wgpu::CompilationMessageType::Error,
+            location: span.and_then(|span| self.user_location(span, code)),
+        }
+    }
+
+    /// Translates a message about the combined shader into one about the user code
+    pub fn to_user_code(
+        &self,
+        message: wgpu::CompilationMessage,
+        code: &ExpandedCode,
+    ) -> wgpu::CompilationMessage {
+        let location = message.location.and_then(|location| {
+            let span = Span::new(location.offset, location.offset + location.length);
+            self.user_location(span, code)
+        });
+        wgpu::CompilationMessage {
+            location,
+            ..message
+        }
+    }
+}
+
+/// Adds the standard library in front of the user code, and returns where the user code ended up
+fn push_user_code(source: &mut String, code: &str) -> SourceMap {
+    source.push_str(STDLIB);
+    source.push('\n');
+    let start = source.len();
+    source.push_str(code);
+    SourceMap {
+        user_range: start..source.len(),
+    }
+}
+
+/// Also returns where the user code ended up
+fn replace_render_code(
+    source: &str,
+    sample_object_code: &str,
+    user_hooks: &[&str],
+) -> (String, SourceMap) {
+    let mut source = source.to_string();
+    for name in user_hooks {
+        let start = source.find(&format!("//// START {name}")).unwrap();
+        let end = source.find(&format!("//// END {name}")).unwrap();
+        source.replace_range(start..end, "");
+    }
+
+    let start = source.find("//// START sampleObject").unwrap();
+    let end = source.find("//// END sampleObject").unwrap();
+
+    let mut result = String::new();
+    result.push_str(&source[..start]);
+    let source_map = push_user_code(&mut result, sample_object_code);
+    result.push_str(&source[end..]);
+    (result, source_map)
+}
+
+/// Swaps the placeholder `Parameters` struct for the one that the user code declared
+fn replace_parameters(source: &str, parameters: &str) -> String {
+    let start = source.find("//// START Parameters").unwrap();
+    let end = source.find("//// END Parameters").unwrap();
+
+    let mut result = String::new();
+    result.push_str(&source[..start]);
+    result.push_str(parameters);
+    result.push('\n');
+    result.push_str(&source[end..]);
+    result
+}
+
+/// naga ends the span of a function or struct before its closing brace, so this extends it
+fn braced_span(source: &str, span: Span) -> Span {
+    let Some(range) = span
+        .to_range()
+        .filter(|range| source[range.clone()].contains('{'))
+    else {
+        return span;
+    };
+    let mut depth = 0;
+    let mut offset = range.start;
+    for line in source[range.start..].split_inclusive('\n') {
+        let code = line.split("//").next().unwrap_or_default();
+        for (index, c) in code.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 1 => {
+                    return Span::new(range.start as u32, (offset + index + 1) as u32);
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        offset += line.len();
+    }
+    span
+}
+
+/// Parses a combined shader, and points at the mistake if it is in the user code
+fn parse(
+    source: &str,
+    source_map: &SourceMap,
+    code: &ExpandedCode,
+) -> Result<Module, wgpu::CompilationMessage> {
+    naga::front::wgsl::parse_str(source).map_err(|error| {
+        let span = error
+            .labels()
+            .map(|(span, _)| span)
+            .find(|span| source_map.contains(*span));
+        source_map.diagnostic(error.message().to_string(), span, code)
+    })
+}
+
+/// Parses and validates a combined shader
+fn check(
+    source: &str,
+    source_map: &SourceMap,
+    code: &ExpandedCode,
+) -> Result<(), wgpu::CompilationMessage> {
+    let module = parse(source, source_map, code)?;
+    let mut validator = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    );
+    if let Err(error) = validator.validate(&module) {
+        let mut message = error.as_inner().to_string();
+        let mut source = std::error::Error::source(error.as_inner());
+        while let Some(inner) = source {
+            message.push_str(&format!(": {inner}"));
+            source = inner.source();
+        }
+        let span = error
+            .spans()
+            .map(|(span, _)| *span)
+            .find(|span| source_map.contains(*span));
+        return Err(source_map.diagnostic(message, span, code));
+    }
+    Ok(())
+}
+
+fn find_function(module: &Module, name: &str) -> Option<Handle<naga::Function>> {
+    module
+        .functions
+        .iter()
+        .find(|(_, function)| function.name.as_deref() == Some(name))
+        .map(|(handle, _)| handle)
+}
+
+fn is_vector(module: &Module, ty: Handle<naga::Type>, size: naga::VectorSize) -> bool {
+    matches!(
+        module.types[ty].inner,
+        TypeInner::Vector { size: s, scalar } if s == size && scalar == naga::Scalar::F32
+    )
+}
+
+fn check_signature(
+    module: &Module,
+    name: &str,
+    code: &ExpandedCode,
+    source_map: &SourceMap,
+) -> Result<(), wgpu::CompilationMessage> {
+    let expected = format!("`{name}` must be declared as fn {name}(input: vec2f) -> vec3f");
+    let Some(handle) = find_function(module, name) else {
+        return Err(source_map.diagnostic(expected, None, code));
+    };
+    let function = &module.functions[handle];
+    let has_signature = function.arguments.len() == 1
+        && is_vector(module, function.arguments[0].ty, naga::VectorSize::Bi)
+        && function
+            .result
+            .as_ref()
+            .is_some_and(|result| is_vector(module, result.ty, naga::VectorSize::Tri));
+    if has_signature {
+        Ok(())
+    } else {
+        let span = module.functions.get_span(handle);
+        Err(source_map.diagnostic(expected, Some(span), code))
+    }
+}
+
+fn called_functions(block: &Block, called: &mut Vec<Handle<naga::Function>>) {
+    for statement in block.iter() {
+        match statement {
+            Statement::Call { function, .. } => called.push(*function),
+            Statement::Block(block) => called_functions(block, called),
+            Statement::If { accept, reject, .. } => {
+                called_functions(accept, called);
+                called_functions(reject, called);
+            }
+            Statement::Switch { cases, .. } => {
+                for case in cases {
+                    called_functions(&case.body, called);
+                }
+            }
+            Statement::Loop {
+                body, continuing, ..
+            } => {
+                called_functions(body, called);
+                called_functions(continuing, called);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Replaces the user functions that `sampleObject` does not call with whitespace
+fn blank_unused_functions(module: &Module, source: &str, source_map: &SourceMap) -> String {
+    let mut used = HashSet::new();
+    let mut pending = find_function(module, "sampleObject")
+        .into_iter()
+        .collect::<Vec<_>>();
+    while let Some(handle) = pending.pop() {
+        if used.insert(handle) {
+            called_functions(&module.functions[handle].body, &mut pending);
+        }
+    }
+
+    let mut code = source[source_map.user_range.clone()].to_string();
+    for (handle, _) in module.functions.iter() {
+        let span = braced_span(source, module.functions.get_span(handle));
+        if used.contains(&handle) || !source_map.contains(span) {
+            continue;
+        }
+        let range = span.to_range().unwrap();
+        let start = source_map.user_range.start;
+        let range = range.start - start..range.end - start;
+        let blank = code[range.clone()]
+            .chars()
+            .map(|c| if c == '\n' { '\n' } else { ' ' })
+            .collect::<String>();
+        code.replace_range(range, &blank);
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPHERE: &str =
+        "fn sampleObject(input: vec2f) -> vec3f {\n  return vec3f(input, 0.0);\n}\n";
+
+    fn assemble(code: &str) -> Result<AssembledShader, wgpu::CompilationMessage> {
+        let code = ExpandedCode::new(code, |name| Err(format!("Unknown include {name}")))?;
+        AssembledShader::new(&code, &ShaderParameters::default())
+    }
+
+    #[test]
+    fn finds_hooks_by_declaration() {
+        let code = format!(
+            "// getColor is optional\n{SPHERE}\
+            fn getColor(input: vec2f) -> vec3f {{\n  return material.color_roughness.rgb;\n}}\n"
+        );
+        let shader = assemble(&code).unwrap();
+        assert!(shader.compute_code.contains("sampleObject"));
+        assert!(!shader.compute_code.contains("material"));
+        assert_eq!(shader.compute_code.lines().count(), code.lines().count());
+    }
+
+    #[test]
+    fn maps_locations_to_user_code() {
+        let shader = assemble(SPHERE).unwrap();
+        let (source, source_map) = shader.compute_source(crate::shaders::compute_patches::SOURCE);
+        let message = |offset: usize| wgpu::CompilationMessage {
+            message: "error".into(),
+            message_type: wgpu::CompilationMessageType::Error,
+            location: Some(wgpu::SourceLocation {
+                line_number: 0,
+                line_position: 0,
+                offset: offset as u32,
+                length: 6,
+            }),
+        };
+        let offset = source.find("return vec3f(input").unwrap();
+        let location = source_map
+            .to_user_code(message(offset), &shader.code)
+            .location
+            .unwrap();
+        assert_eq!(location.line_number, 2);
+        assert_eq!(location.line_position, 3);
+        assert_eq!(location.offset, 43);
+
+        let offset = source.find("fn main").unwrap();
+        let message = source_map.to_user_code(message(offset), &shader.code);
+        assert!(message.location.is_none());
+    }
+
+    #[test]
+    fn rejects_wrong_signature() {
+        let code = "fn sampleObject(input: vec3f) -> vec3f {\n  return input;\n}\n";
+        let error = assemble(code).err().unwrap();
+        assert_eq!(error.location.unwrap().line_number, 1);
+    }
+
+    #[test]
+    fn rejects_renderer_names() {
+        let code = format!("const camera = 1.0;\n{SPHERE}");
+        let error = assemble(&code).err().unwrap();
+        assert!(error.message.contains("`camera`"));
+        assert_eq!(error.location.unwrap().line_number, 1);
+    }
+
+    #[test]
+    fn rejects_compute_shader_names() {
+        for declaration in ["fn split_patch() {}", "var<private> seeds: f32;"] {
+            let code = format!("{declaration}\n{SPHERE}");
+            let error = assemble(&code).err().unwrap();
+            assert!(error.message.contains("already declared by the renderer"));
+            assert_eq!(error.location.unwrap().line_number, 1);
+        }
+    }
+
+    #[test]
+    fn provides_stdlib() {
+        let code = "fn sampleObject(input: vec2f) -> vec3f {\n  \
+            return std_rotate_z(std_sphere(input, 1.0), STD_PI);\n}\n";
+        assert!(assemble(code).is_ok());
+
+        let code = format!("fn std_sphere() {{}}\n{SPHERE}");
+        let error = assemble(&code).err().unwrap();
+        assert!(error.message.contains("`std_sphere`"));
+    }
+}
@@ -0,0 +1,219 @@
+//! Cascaded shadow maps for the directional light.
+//! See https://learn.microsoft.com/en-us/windows/win32/dxtecharticles/cascaded-shadow-maps
+
+use glam::{Mat4, UVec2, Vec3};
+
+use crate::{camera::Camera, texture::Texture};
+
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const SHADOW_CASCADE_COUNT: usize = 3;
+/// Shadows are only rendered up to this distance from the camera.
+/// Also used as the distance behind a cascade, where objects can still cast shadows into it.
+const MAX_SHADOW_DISTANCE: f32 = 100.0;
+/// Blends between logarithmic (1.0) and uniform (0.0) cascade splits
+const CASCADE_SPLIT_LAMBDA: f32 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowCascade {
+    pub view: Mat4,
+    pub projection: Mat4,
+    pub eye: Vec3,
+    /// Size of one shadow map texel in world space
+    pub texel_world_size: f32,
+}
+
+impl ShadowCascade {
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection * self.view
+    }
+}
+
+/// Fits one orthographic cascade around each slice of the view frustum.
+pub fn compute_cascades(
+    camera: &Camera,
+    size: UVec2,
+    light_direction: Vec3,
+) -> [ShadowCascade; SHADOW_CASCADE_COUNT] {
+    let light_direction = light_direction.normalize();
+    let splits = cascade_splits(camera);
+    let up = if light_direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    let light_rotation = Mat4::look_to_rh(Vec3::ZERO, light_direction, up);
+
+    std::array::from_fn(|i| {
+        let corners = frustum_slice_corners(camera, size, splits[i], splits[i + 1]);
+        let center = corners.iter().sum::<Vec3>() / (corners.len() as f32);
+        let radius = corners
+            .iter()
+            .map(|corner| corner.distance(center))
+            .fold(0.0f32, f32::max);
+        // A stable radius and texel snapping prevent the shadow edges from shimmering when the camera moves
+        let radius = (radius * 16.0).ceil() / 16.0;
+        let texel_world_size = 2.0 * radius / (SHADOW_MAP_SIZE as f32);
+        let light_space_center = light_rotation.transform_point3(center);
+        let snapped_center = Vec3::new(
+            (light_space_center.x / texel_world_size).floor() * texel_world_size,
+            (light_space_center.y / texel_world_size).floor() * texel_world_size,
+            light_space_center.z,
+        );
+        let center = light_rotation.inverse().transform_point3(snapped_center);
+
+        let eye = center - light_direction * (radius + MAX_SHADOW_DISTANCE);
+        ShadowCascade {
+            view: Mat4::look_to_rh(eye, light_direction, up),
+            // Reverse Z
+            projection: Mat4::orthographic_rh(
+                -radius,
+                radius,
+                -radius,
+                radius,
+                2.0 * radius + MAX_SHADOW_DISTANCE,
+                0.0,
+            ),
+            eye,
+            texel_world_size,
+        }
+    })
+}
+
+/// Distances from the camera, with the practical split scheme
+fn cascade_splits(camera: &Camera) -> [f32; SHADOW_CASCADE_COUNT + 1] {
+    let near = camera.settings.z_near;
+    let far = camera.settings.z_far.min(MAX_SHADOW_DISTANCE);
+    std::array::from_fn(|i| {
+        let t = (i as f32) / (SHADOW_CASCADE_COUNT as f32);
+        let logarithmic = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        CASCADE_SPLIT_LAMBDA * logarithmic + (1.0 - CASCADE_SPLIT_LAMBDA) * uniform
+    })
+}
+
+/// The 8 world space corners of the view frustum between two distances
+fn frustum_slice_corners(camera: &Camera, size: UVec2, near: f32, far: f32) -> [Vec3; 8] {
+    let aspect_ratio = size.x as f32 / size.y as f32;
+    let tan_half_fov = (camera.settings.fov.radians * 0.5).tan();
+    let inverse_view = camera.view_matrix().inverse();
+    std::array::from_fn(|i| {
+        let distance = if i < 4 { near } else { far };
+        let half_height = distance * tan_half_fov;
+        let half_width = half_height * aspect_ratio;
+        let x = if i % 2 == 0 { -half_width } else { half_width };
+        let y = if (i / 2) % 2 == 0 {
+            -half_height
+        } else {
+            half_height
+        };
+        inverse_view.transform_point3(Vec3::new(x, y, -distance))
+    })
+}
+
+pub struct ShadowMaps {
+    /// For sampling all cascades in a shader
+    pub array_view: wgpu::TextureView,
+    /// For rendering into one cascade at a time
+    pub cascade_views: Vec<wgpu::TextureView>,
+    pub sampler: wgpu::Sampler,
+    /// Bound while rendering the shadow maps themselves, since a texture cannot be sampled and rendered to at once
+    pub dummy_view: wgpu::TextureView,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture = create_depth_array_texture(
+            device,
+            "Shadow Maps",
+            SHADOW_MAP_SIZE,
+            SHADOW_CASCADE_COUNT as u32,
+        );
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Maps View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..SHADOW_CASCADE_COUNT as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(&format!("Shadow Cascade {layer}")),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            // Reverse Z
+            compare: Some(wgpu::CompareFunction::GreaterEqual),
+            ..Default::default()
+        });
+        let dummy_view = create_depth_array_texture(device, "Shadow Maps Dummy", 1, 1).create_view(
+            &wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            },
+        );
+
+        Self {
+            array_view,
+            cascade_views,
+            sampler,
+            dummy_view,
+        }
+    }
+}
+
+fn create_depth_array_texture(
+    device: &wgpu::Device,
+    label: &str,
+    size: u32,
+    layers: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: Texture::DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+
+    #[test]
+    fn cascades_contain_their_frustum_slice() {
+        let camera = Camera::new(CameraSettings::default());
+        let size = UVec2::new(1920, 1080);
+        let splits = cascade_splits(&camera);
+        let cascades = compute_cascades(&camera, size, Vec3::new(1.0, -4.0, 1.0));
+        for (i, cascade) in cascades.iter().enumerate() {
+            for corner in frustum_slice_corners(&camera, size, splits[i], splits[i + 1]) {
+                let ndc = cascade.view_projection().project_point3(corner);
+                assert!(
+                    ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0,
+                    "{ndc} in cascade {i}"
+                );
+                assert!((0.0..=1.0).contains(&ndc.z), "{ndc} in cascade {i}");
+            }
+        }
+    }
+}
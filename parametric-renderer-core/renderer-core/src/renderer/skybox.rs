@@ -1,17 +1,31 @@
 use glam::Vec3;
-use reactive_graph::{signal::RwSignal, traits::Read};
+use reactive_graph::{
+    computed::Memo,
+    owner::StoredValue,
+    prelude::*,
+    signal::{ReadSignal, RwSignal},
+};
 
-use crate::{buffer::DeviceBufferExt, mesh::Mesh, shaders, texture::Texture};
+use crate::{
+    buffer::DeviceBufferExt,
+    mesh::Mesh,
+    reactive::{IntoValueView, MemoComputed, RenderCommand, RenderComponent},
+    shaders,
+    texture::Texture,
+};
 
-use super::{FrameData, get_context, wgpu_context::SurfaceOrFallback};
+use super::{
+    FrameData, get_context, main_pass_targets, scene::SceneData, wgpu_context::SurfaceOrFallback,
+};
 
 pub fn skybox_component(
     surface: RwSignal<SurfaceOrFallback>,
-) -> impl Fn(&FrameData, &mut wgpu_profiler::OwningScope<'_, wgpu::RenderPass<'_>>) {
+    scene_data: StoredValue<SceneData>,
+    sample_count: ReadSignal<u32>,
+) -> RenderComponent<FrameData> {
     let context = &get_context();
     let skybox_mesh = Mesh::cubemap_cube(&context.device, Vec3::NEG_ONE, Vec3::ONE);
 
-    let context = &get_context();
     let shader = context
         .device
         .create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -19,87 +33,85 @@ pub fn skybox_component(
             source: wgpu::ShaderSource::Wgsl(shaders::skybox::SOURCE.into()),
         });
 
-    let pipeline = context
-        .device
-        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Skybox"),
-            layout: Some(&shaders::skybox::create_pipeline_layout(&context.device)),
-            vertex: shaders::skybox::vertex_state(
-                &shader,
-                &shaders::skybox::vs_main_entry(wgpu::VertexStepMode::Vertex),
-            ),
-            fragment: Some(shaders::skybox::fragment_state(
-                &shader,
-                &shaders::skybox::fs_main_entry([
-                    Some(wgpu::ColorTargetState {
-                        format: context.view_format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    }),
-                    Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::R32Uint,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::empty(),
-                    }),
-                ]),
-            )),
-            primitive: wgpu::PrimitiveState {
-                cull_mode: Some(wgpu::Face::Back),
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: false,
-                // Reverse-Z range: [1.0, 0.0]
-                // Depth buffer starts at far-away (0.0)
-                // Skybox is at 0.0 (faaar away), and we want to replace the depth buffer values
-                depth_compare: wgpu::CompareFunction::GreaterEqual,
-                stencil: Default::default(),
-                bias: Default::default(),
-            }),
-            multisample: Default::default(),
-            multiview: None,
-            cache: Default::default(),
-        });
+    let pipeline = Memo::new_computed(move |_| {
+        let context = &get_context();
+        let sample_count = sample_count.get();
+        context
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Skybox"),
+                layout: Some(&shaders::skybox::create_pipeline_layout(&context.device)),
+                vertex: shaders::skybox::vertex_state(
+                    &shader,
+                    &shaders::skybox::vs_main_entry(wgpu::VertexStepMode::Vertex),
+                ),
+                fragment: Some(shaders::skybox::fragment_state(
+                    &shader,
+                    &shaders::skybox::fs_main_entry(main_pass_targets(Some(
+                        wgpu::BlendState::REPLACE,
+                    ))),
+                )),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    // Reverse-Z range: [1.0, 0.0]
+                    // Depth buffer starts at far-away (0.0)
+                    // Skybox is at 0.0 (faaar away), and we want to replace the depth buffer values
+                    depth_compare: wgpu::CompareFunction::GreaterEqual,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: Default::default(),
+            })
+    });
 
     let uniforms = context.device.uniform_buffer(
         "Skybox Uniforms",
         &shaders::skybox::Uniforms {
             view_projection_matrix: Default::default(),
-            background_color: Default::default(),
-            sun_direction: Default::default(),
         },
         wgpu::BufferUsages::COPY_DST,
     );
 
-    let bind_group_0 = shaders::skybox::bind_groups::BindGroup0::from_bindings(
-        &context.device,
-        shaders::skybox::bind_groups::BindGroupLayout0 {
-            uniforms: uniforms.as_entire_buffer_binding(),
-        },
-    );
+    // The environment maps are baked in place, so the bind group never needs to be recreated
+    let bind_group_0 = scene_data.with_value(|scene_data| {
+        shaders::skybox::bind_groups::BindGroup0::from_bindings(
+            &context.device,
+            shaders::skybox::bind_groups::BindGroupLayout0 {
+                uniforms: uniforms.as_entire_buffer_binding(),
+                environment: &scene_data.environment.environment_view,
+                environment_sampler: &scene_data.environment.sampler,
+            },
+        )
+    });
 
-    move |render_data: &FrameData, render_pass| {
+    let mut commands = vec![
+        RenderCommand::SetPipeline(pipeline.into_value_view()),
+        RenderCommand::action(move |render_pass: &mut wgpu::RenderPass<'_>| {
+            bind_group_0.set(render_pass)
+        }),
+    ];
+    commands.extend(skybox_mesh.draw_commands());
+
+    RenderComponent::new(commands).on_update(move |render_data: &FrameData| {
         let context = &get_context();
         let view_matrix =
             glam::Mat4::from_mat3(glam::Mat3::from_mat4(render_data.camera.view_matrix()));
         uniforms.write_buffer(
             &context.queue,
             &shaders::skybox::Uniforms {
-                view_projection_matrix: render_data.camera.projection_matrix(surface.read().size())
+                view_projection_matrix: render_data.projection_matrix(surface.read().size())
                     * view_matrix,
-                background_color: (Vec3::new(0.09, 0.59, 0.85) * 0.8).extend(1.0),
-                sun_direction: Vec3::new(1., 1., 1.).normalize().extend(1.0),
             },
         );
-
-        render_pass.set_pipeline(&pipeline);
-        render_pass.set_vertex_buffer(0, skybox_mesh.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(
-            skybox_mesh.index_buffer.slice(..),
-            wgpu::IndexFormat::Uint16,
-        );
-        bind_group_0.set(&mut render_pass.recorder);
-        render_pass.draw_indexed(0..skybox_mesh.num_indices, 0, 0..1);
-    }
+    })
 }
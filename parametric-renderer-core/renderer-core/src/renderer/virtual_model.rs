@@ -4,24 +4,116 @@ use crate::{
     buffer::TypedBuffer,
     game::{MaterialInfo, TextureData, TextureInfo},
     mesh::Mesh,
-    shaders::{compute_patches, copy_patches, shader},
-    texture::Texture,
+    shader_includes::{ExpandedCode, include_names},
+    shader_parameters::{PARAMETERS_BUFFER_SIZE, ShaderParameters},
+    shaders::{compute_patches, copy_patches, persistent_patches, shader},
+    texture::{MipmapGenerator, SampledTexture, Texture},
 };
 
-use super::{MAX_PATCH_COUNT, PATCH_SIZES, wgpu_context::WgpuContext};
-use std::sync::Arc;
+use super::{
+    LOD_TREE_CAPACITY, MAX_PATCH_COUNT, PATCH_SIZES, main_pass_targets,
+    pipeline_cache::PipelineCache,
+    shader_assembly::{AssembledShader, SourceMap},
+    wgpu_context::WgpuContext,
+};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::{Arc, Mutex},
+};
 
 use glam::{Vec2, Vec3, Vec4};
+use log::error;
+use reactive_graph::{
+    signal::ArcTrigger,
+    traits::{Notify, Track},
+};
 use uuid::Uuid;
 use wgpu::ShaderModule;
 
+/// Error scopes are a stack that the whole device shares, so only one thread at a time may use them
+static ERROR_SCOPE: Mutex<()> = Mutex::new(());
+
 pub struct ShaderPipelines {
     /// Pipeline per model, for different parametric functions.
     pub compute_patches: wgpu::ComputePipeline,
+    /// Work queue variant of the compute pipeline, for the persistent LOD kernel.
+    pub persistent_patches: wgpu::ComputePipeline,
+    /// Depth-only variant of the render pipeline, for the shadow maps.
+    pub shadow: wgpu::RenderPipeline,
+    /// Render pipelines per sample count. `None` while they are being created.
+    render: Mutex<HashMap<u32, Option<Arc<RenderPipelines>>>>,
+    /// Notified when render pipelines for another sample count are ready
+    render_ready: ArcTrigger,
+    label: String,
+    /// The compute, render and persistent compute shader
+    pub shaders: [ShaderModule; 3],
+    /// Where the user code is in each of the `shaders`
+    source_maps: [SourceMap; 3],
+    /// The user code, which the compilation messages refer to
+    code: ExpandedCode,
+    /// Declared with `// @param` annotations in the code
+    pub parameters: ShaderParameters,
+    /// Also used for the render pipelines that get created later
+    cache: Option<PipelineCache>,
+    pub id: Uuid,
+}
+
+/// The pipelines of the main render pass, which depend on the sample count.
+pub struct RenderPipelines {
     /// Pipeline per model, for different parametric functions.
     pub render: wgpu::RenderPipeline,
-    pub shaders: [ShaderModule; 2],
-    pub id: Uuid,
+    /// Line variant of the render pipeline, if the device supports `POLYGON_MODE_LINE`.
+    pub wireframe: Option<wgpu::RenderPipeline>,
+    /// Alpha blended variant of the render pipeline, which does not write to the depth buffer.
+    pub transparent: wgpu::RenderPipeline,
+    pub sample_count: u32,
+}
+
+/// The user code of a shader, with a snapshot of the files that it includes.
+/// Owns everything, so that the shader can be assembled on another thread.
+pub struct ShaderSource {
+    pub label: String,
+    /// Identifies the shader in the pipeline cache, across versions of the shader
+    pub cache_key: String,
+    pub code: String,
+    includes: HashMap<String, Result<String, String>>,
+}
+
+impl ShaderSource {
+    /// `resolve_include` looks up the code of the `////#include` statements.
+    pub fn new(
+        label: String,
+        cache_key: String,
+        code: String,
+        resolve_include: &dyn Fn(&str) -> Result<String, String>,
+    ) -> Self {
+        let includes = include_names(&code, resolve_include)
+            .into_iter()
+            .map(|name| {
+                let include = resolve_include(&name);
+                (name, include)
+            })
+            .collect();
+        Self {
+            label,
+            cache_key,
+            code,
+            includes,
+        }
+    }
+
+    /// Mistakes in the user code are reported before any pipeline gets created
+    fn assemble(&self) -> Result<(AssembledShader, ShaderParameters), wgpu::CompilationMessage> {
+        let parameters = ShaderParameters::parse(&self.code)?;
+        let code = ExpandedCode::new(&self.code, |name| {
+            self.includes
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| Err(format!("Unknown include {name}")))
+        })?;
+        let shader = AssembledShader::new(&code, &parameters)?;
+        Ok((shader, parameters))
+    }
 }
 
 impl PartialEq for ShaderPipelines {
@@ -32,53 +124,329 @@ impl PartialEq for ShaderPipelines {
 
 impl Eq for ShaderPipelines {}
 
+/// Native drivers compile the shaders while the pipelines are created, so that happens on another thread.
+/// Browsers already compile them in the background.
+async fn in_background<T: wgpu::WasmNotSend + 'static>(
+    work: impl Future<Output = T> + wgpu::WasmNotSend + 'static,
+) -> T {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(futures::executor::block_on(work));
+        });
+        receiver
+            .await
+            .expect("the thread that creates the pipelines panicked")
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        work.await
+    }
+}
+
 impl ShaderPipelines {
-    pub fn new(label: &str, code: &str, context: &WgpuContext) -> Self {
-        let (compute_patches, shader_a) =
-            create_compute_patches_pipeline(label, &context.device, code);
-        let (render, shader_b) = create_render_pipeline(label, context, code);
+    /// `cache_key` identifies the shader in the pipeline cache, across versions of the shader.
+    /// The render pipelines for `sample_count` are created right away.
+    pub fn new(
+        label: &str,
+        cache_key: &str,
+        shader: &AssembledShader,
+        parameters: ShaderParameters,
+        sample_count: u32,
+        context: &WgpuContext,
+    ) -> Self {
+        let cache = PipelineCache::new(
+            context,
+            cache_key,
+            &[
+                &shader.render_source,
+                compute_patches::SOURCE,
+                persistent_patches::SOURCE,
+            ],
+        );
+        let cache_ref = cache.as_ref().map(PipelineCache::get);
+        let (compute_patches, shader_a, source_map_a) =
+            create_compute_patches_pipeline(label, &context.device, shader, cache_ref);
+        let (persistent_patches, shader_c, source_map_c) =
+            create_persistent_patches_pipeline(label, &context.device, shader, cache_ref);
+        let shader_b = create_render_shader(label, &context.device, shader);
+        let shadow = create_shadow_pipeline(label, &context.device, &shader_b, cache_ref);
+        let render = RenderPipelines::new(label, context, &shader_b, sample_count, cache_ref);
+        if let Some(cache) = &cache {
+            cache.save();
+        }
 
         Self {
             compute_patches,
-            render,
-            shaders: [shader_a, shader_b],
+            persistent_patches,
+            shadow,
+            render: Mutex::new(HashMap::from([(sample_count, Some(Arc::new(render)))])),
+            render_ready: ArcTrigger::new(),
+            label: label.to_string(),
+            shaders: [shader_a, shader_b, shader_c],
+            source_maps: [source_map_a, shader.render_map.clone(), source_map_c],
+            code: shader.code.clone(),
+            parameters,
+            cache,
             id: Uuid::new_v4(),
         }
     }
 
+    /// Assembles the shader and creates the pipelines without blocking the render thread.
+    /// Fails when the user code cannot be assembled.
+    pub async fn compile(
+        source: ShaderSource,
+        sample_count: u32,
+        context: Arc<WgpuContext>,
+    ) -> Result<(Self, Vec<wgpu::CompilationMessage>), wgpu::CompilationMessage> {
+        in_background(async move {
+            let (shader, parameters) = source.assemble()?;
+            Ok(Self::compile_in_error_scope(
+                &source.label,
+                &source.cache_key,
+                &shader,
+                parameters,
+                sample_count,
+                &context,
+            )
+            .await)
+        })
+        .await
+    }
+
+    /// Creates the pipelines in a validation error scope, which is popped asynchronously.
+    /// Errors that slip past the checks of the shader assembly end up in the returned messages,
+    /// instead of being uncaught device errors.
+    async fn compile_in_error_scope(
+        label: &str,
+        cache_key: &str,
+        shader: &AssembledShader,
+        parameters: ShaderParameters,
+        sample_count: u32,
+        context: &WgpuContext,
+    ) -> (Self, Vec<wgpu::CompilationMessage>) {
+        let (pipelines, error) = {
+            let _error_scope = ERROR_SCOPE.lock().unwrap();
+            context
+                .device
+                .push_error_scope(wgpu::ErrorFilter::Validation);
+            let pipelines = Self::new(label, cache_key, shader, parameters, sample_count, context);
+            (pipelines, context.device.pop_error_scope())
+        };
+        let error = error.await;
+        let mut messages = pipelines.get_compilation_info().await;
+        let has_error = messages
+            .iter()
+            .any(|v| v.message_type == wgpu::CompilationMessageType::Error);
+        if let Some(error) = error
+            && !has_error
+        {
+            messages.push(wgpu::CompilationMessage {
+                message: error.to_string(),
+                message_type: wgpu::CompilationMessageType::Error,
+                location: None,
+            });
+        }
+        (pipelines, messages)
+    }
+
+    /// The render pipelines for a sample count, if they are ready.
+    /// Reruns the caller once pipelines from [`Self::prepare_render_pipelines`] are ready.
+    pub fn render_pipelines(&self, sample_count: u32) -> Option<Arc<RenderPipelines>> {
+        self.render_ready.track();
+        self.render
+            .lock()
+            .unwrap()
+            .get(&sample_count)
+            .cloned()
+            .flatten()
+    }
+
+    /// Creates the render pipelines for a sample count without blocking the render thread.
+    /// Finishes right away when they exist or are already being created.
+    pub fn prepare_render_pipelines(
+        self: &Arc<Self>,
+        sample_count: u32,
+        context: Arc<WgpuContext>,
+    ) -> impl Future<Output = ()> + use<> {
+        let is_new = match self.render.lock().unwrap().entry(sample_count) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(None);
+                true
+            }
+        };
+        let shaders = self.clone();
+        async move {
+            if !is_new {
+                return;
+            }
+            let pipelines = in_background({
+                let shaders = shaders.clone();
+                async move {
+                    shaders
+                        .create_render_pipelines(sample_count, &context)
+                        .await
+                }
+            })
+            .await;
+            shaders
+                .render
+                .lock()
+                .unwrap()
+                .insert(sample_count, Some(Arc::new(pipelines)));
+            shaders.render_ready.notify();
+        }
+    }
+
+    /// Errors are logged, since they would otherwise be uncaught device errors.
+    async fn create_render_pipelines(
+        &self,
+        sample_count: u32,
+        context: &WgpuContext,
+    ) -> RenderPipelines {
+        let (pipelines, error) = {
+            let _error_scope = ERROR_SCOPE.lock().unwrap();
+            context
+                .device
+                .push_error_scope(wgpu::ErrorFilter::Validation);
+            let pipelines = RenderPipelines::new(
+                &self.label,
+                context,
+                &self.shaders[1],
+                sample_count,
+                self.cache.as_ref().map(PipelineCache::get),
+            );
+            (pipelines, context.device.pop_error_scope())
+        };
+        if let Some(error) = error.await {
+            error!(
+                "Failed to create the {sample_count}x render pipelines of {}: {error}",
+                self.label
+            );
+        }
+        if let Some(cache) = &self.cache {
+            cache.save();
+        }
+        pipelines
+    }
+
+    /// The messages of all shaders, with locations in the user code.
+    /// The user code is in every shader, so the same message can appear more than once.
     pub async fn get_compilation_info(&self) -> Vec<wgpu::CompilationMessage> {
-        let mut messages = self.shaders[0].get_compilation_info().await.messages;
-        messages.extend(self.shaders[1].get_compilation_info().await.messages);
+        let mut messages: Vec<wgpu::CompilationMessage> = Vec::new();
+        for (shader, source_map) in self.shaders.iter().zip(&self.source_maps) {
+            for message in shader.get_compilation_info().await.messages {
+                let message = source_map.to_user_code(message, &self.code);
+                let is_duplicate = messages.iter().any(|v| {
+                    v.message_type == message.message_type
+                        && match (v.location, message.location) {
+                            (Some(a), Some(b)) => a == b,
+                            (None, None) => v.message == message.message,
+                            _ => false,
+                        }
+                });
+                if !is_duplicate {
+                    messages.push(message);
+                }
+            }
+        }
         messages
     }
 }
 
+impl RenderPipelines {
+    fn new(
+        label: &str,
+        context: &WgpuContext,
+        shader: &ShaderModule,
+        sample_count: u32,
+        cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let render = create_render_pipeline(
+            label,
+            context,
+            shader,
+            wgpu::PolygonMode::Fill,
+            false,
+            sample_count,
+            cache,
+        );
+        let transparent = create_render_pipeline(
+            &format!("{label} Transparent"),
+            context,
+            shader,
+            wgpu::PolygonMode::Fill,
+            true,
+            sample_count,
+            cache,
+        );
+        let wireframe = context
+            .device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| {
+                create_render_pipeline(
+                    &format!("{label} Wireframe"),
+                    context,
+                    shader,
+                    wgpu::PolygonMode::Line,
+                    false,
+                    sample_count,
+                    cache,
+                )
+            });
+        Self {
+            render,
+            wireframe,
+            transparent,
+            sample_count,
+        }
+    }
+}
+
 const MISSING_SHADER: &str = include_str!("../../../shaders/DefaultParametric.wgsl");
 
 pub fn make_missing_shader(context: &WgpuContext) -> Arc<ShaderPipelines> {
+    let parameters = ShaderParameters::default();
+    let code = ExpandedCode::new(MISSING_SHADER, |name| {
+        Err(format!("Unknown include {name}"))
+    })
+    .unwrap();
+    let shader = AssembledShader::new(&code, &parameters).unwrap();
     Arc::new(ShaderPipelines::new(
         "Missing Shader",
-        MISSING_SHADER,
+        "Missing Shader",
+        &shader,
+        parameters,
+        1,
         context,
     ))
 }
 
-pub fn make_empty_texture(context: &WgpuContext) -> Arc<Texture> {
-    Arc::new(Texture::new_rgba(
+pub fn make_empty_texture(context: &WgpuContext, mipmaps: &MipmapGenerator) -> Arc<SampledTexture> {
+    Arc::new(SampledTexture::new(
         &context.device,
         &context.queue,
         &TextureInfo {
             width: 1,
             height: 1,
             data: TextureData::Bytes(vec![u8::MAX, u8::MAX, u8::MAX, u8::MAX]),
+            sampler: Default::default(),
         },
+        mipmaps,
     ))
 }
 
 // Minimal amount of info to pass from patches stage to render stage
 pub struct VirtualModel {
     pub render_buffer: Vec<TypedBuffer<compute_patches::RenderBuffer>>,
+    /// How the patches were subdivided, for stitching them together
+    pub lod_tree: TypedBuffer<compute_patches::LodTree>,
     pub indirect_draw: TypedBuffer<Vec<copy_patches::DrawIndexedIndirectArgs>>,
+    /// Values of the `// @param` annotations of the shader
+    pub parameters: wgpu::Buffer,
 }
 
 impl VirtualModel {
@@ -101,6 +469,17 @@ impl VirtualModel {
             })
             .collect::<Vec<_>>();
 
+        let lod_tree = TypedBuffer::new_storage_with_runtime_array(
+            &context.device,
+            &format!("{id} LOD Tree"),
+            &compute_patches::LodTree {
+                overflowed: 0,
+                nodes: vec![],
+            },
+            LOD_TREE_CAPACITY as u64,
+            wgpu::BufferUsages::COPY_DST,
+        );
+
         let indirect_draw_data = copy_patches::DrawIndexedIndirectArgs {
             index_count: 0,
             instance_count: 0, // Our shader sets this
@@ -122,9 +501,18 @@ impl VirtualModel {
             wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_SRC,
         );
 
+        let parameters = context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{id} Parameters")),
+            size: PARAMETERS_BUFFER_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         Self {
             render_buffer,
+            lod_tree,
             indirect_draw,
+            parameters,
         }
     }
 }
@@ -136,6 +524,24 @@ impl MaterialInfo {
             emissive_metallic: self.emissive.extend(self.metallic),
             has_texture: if self.diffuse_texture.is_some() { 1 } else { 0 },
             texture_scale: self.texture_scale,
+            opacity: self.opacity,
+            alpha_from_texture: if self.alpha_from_texture { 1 } else { 0 },
+            has_normal_texture: if self.normal_texture.is_some() { 1 } else { 0 },
+            has_metallic_roughness_texture: if self.metallic_roughness_texture.is_some() {
+                1
+            } else {
+                0
+            },
+            has_occlusion_texture: if self.occlusion_texture.is_some() {
+                1
+            } else {
+                0
+            },
+            has_emissive_texture: if self.emissive_texture.is_some() {
+                1
+            } else {
+                0
+            },
         }
     }
 
@@ -146,7 +552,13 @@ impl MaterialInfo {
             roughness: 0.7,
             metallic: 0.0,
             diffuse_texture: None,
+            normal_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
             texture_scale: Vec2::ONE,
+            opacity: 1.0,
+            alpha_from_texture: false,
         }
     }
 }
@@ -158,130 +570,195 @@ impl Default for MaterialInfo {
             roughness: 0.0,
             metallic: 0.0,
             diffuse_texture: None,
+            normal_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
             texture_scale: Vec2::ONE,
+            opacity: 1.0,
+            alpha_from_texture: false,
         }
     }
 }
 
+fn create_render_shader(
+    label: &str,
+    device: &wgpu::Device,
+    shader: &AssembledShader,
+) -> ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(&format!("Render Shader {}", label)),
+        source: wgpu::ShaderSource::Wgsl(shader.render_source.as_str().into()),
+    })
+}
+
 fn create_render_pipeline(
     label: &str,
     context: &WgpuContext,
-    code: &str,
-) -> (wgpu::RenderPipeline, ShaderModule) {
+    shader: &ShaderModule,
+    polygon_mode: wgpu::PolygonMode,
+    transparent: bool,
+    sample_count: u32,
+    cache: Option<&wgpu::PipelineCache>,
+) -> wgpu::RenderPipeline {
     let device = &context.device;
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("Render Pipeline {}", label)),
+        layout: Some(&shader::create_pipeline_layout(device)),
+        vertex: shader::vertex_state(shader, &shader::vs_main_entry(wgpu::VertexStepMode::Vertex)),
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(shader::ENTRY_FS_MAIN),
+            targets: &main_pass_targets(Some(if transparent {
+                wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
+            } else {
+                wgpu::BlendState::REPLACE
+            })),
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            // Setting this to Line requires Features::POLYGON_MODE_LINE
+            polygon_mode,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            // Transparent surfaces behind each other should all be visible
+            depth_write_enabled: !transparent,
+            depth_compare: wgpu::CompareFunction::Greater, // Reverse Z
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache,
+    })
+}
+
+fn create_shadow_pipeline(
+    label: &str,
+    device: &wgpu::Device,
+    shader: &ShaderModule,
+    cache: Option<&wgpu::PipelineCache>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("Shadow Pipeline {}", label)),
+        layout: Some(&shader::create_pipeline_layout(device)),
+        vertex: shader::vertex_state(shader, &shader::vs_main_entry(wgpu::VertexStepMode::Vertex)),
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Greater, // Reverse Z
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: Default::default(),
+        multiview: None,
+        cache,
+    })
+}
+
+pub fn create_compute_patches_pipeline(
+    label: &str,
+    device: &wgpu::Device,
+    shader: &AssembledShader,
+    cache: Option<&wgpu::PipelineCache>,
+) -> (wgpu::ComputePipeline, ShaderModule, SourceMap) {
+    let (source, source_map) = shader.compute_source(compute_patches::SOURCE);
     let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: Some(&format!("Render Shader {}", label)),
-        source: wgpu::ShaderSource::Wgsl(replace_render_code(shader::SOURCE, code).into()),
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(source.as_ref())),
     });
     (
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some(&format!("Render Pipeline {}", label)),
-            layout: Some(&shader::create_pipeline_layout(device)),
-            vertex: shader::vertex_state(
-                &shader,
-                &shader::vs_main_entry(wgpu::VertexStepMode::Vertex),
-            ),
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some(shader::ENTRY_FS_MAIN),
-                targets: &[
-                    Some(wgpu::ColorTargetState {
-                        format: context.view_format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    }),
-                    Some(wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::R32Uint,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::empty(),
-                    }),
-                ],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill, // Wireframe mode can be toggled here on the desktop backend
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Greater, // Reverse Z
-                stencil: Default::default(),
-                bias: Default::default(),
-            }),
-            multisample: Default::default(),
-            multiview: None,
-            cache: Default::default(),
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&format!("Compute Patches {}", label)),
+            layout: Some(&compute_patches::create_pipeline_layout(device)),
+            module: &shader,
+            entry_point: Some(compute_patches::ENTRY_MAIN),
+            compilation_options: Default::default(),
+            cache,
         }),
         shader,
+        source_map,
     )
 }
 
-pub fn create_compute_patches_pipeline(
+pub fn create_persistent_patches_pipeline(
     label: &str,
     device: &wgpu::Device,
-    code: &str,
-) -> (wgpu::ComputePipeline, ShaderModule) {
-    let source = replace_compute_code(compute_patches::SOURCE, code);
+    shader: &AssembledShader,
+    cache: Option<&wgpu::PipelineCache>,
+) -> (wgpu::ComputePipeline, ShaderModule, SourceMap) {
+    let (source, source_map) = shader.compute_source(persistent_patches::SOURCE);
     let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: None,
         source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(source.as_ref())),
     });
     (
         device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some(&format!("Compute Patches {}", label)),
-            layout: Some(&compute_patches::create_pipeline_layout(device)),
+            label: Some(&format!("Persistent Patches {}", label)),
+            layout: Some(&persistent_patches::create_pipeline_layout(device)),
             module: &shader,
-            entry_point: Some(compute_patches::ENTRY_MAIN),
+            entry_point: Some(persistent_patches::ENTRY_MAIN),
             compilation_options: Default::default(),
-            cache: Default::default(),
+            cache,
         }),
         shader,
+        source_map,
     )
 }
 
-fn replace_render_code<'a>(source: &'a str, sample_object_code: &str) -> String {
-    // TODO: use wgsl-parser instead of this
-    let start_1 = source.find("//// START sampleObject").unwrap();
-    let end_1 = source.find("//// END sampleObject").unwrap();
-    let start_2 = source.find("//// START getColor").unwrap();
-    let end_2 = source.find("//// END getColor").unwrap();
-
-    let mut result = String::new();
-    result.push_str(&source[..start_1]);
-    result.push_str(sample_object_code);
-    result.push_str(&source[end_1..start_2]);
-
-    if sample_object_code.contains("fn getColor") {
-        result.push_str(&source[end_2..]);
-    } else {
-        result.push_str(&source[start_2..]);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    result
-}
+    #[test]
+    fn compute_pipelines_fit_default_limits() {
+        let limits = wgpu::Limits::default();
+        for source in [compute_patches::SOURCE, persistent_patches::SOURCE] {
+            let module = naga::front::wgsl::parse_str(source).unwrap();
+            let storage_buffers = module
+                .global_variables
+                .iter()
+                .filter(|(_, global)| matches!(global.space, naga::AddressSpace::Storage { .. }))
+                .count() as u32;
+            assert!(storage_buffers <= limits.max_storage_buffers_per_shader_stage);
+        }
 
-fn replace_compute_code<'a>(source: &'a str, sample_object_code: &str) -> String {
-    // TODO: use wgsl-parser instead of this
-    let start_1 = source.find("//// START sampleObject").unwrap();
-    let end_1 = source.find("//// END sampleObject").unwrap();
-
-    let mut result = String::new();
-    result.push_str(&source[..start_1]);
-    if sample_object_code.contains("fn getColor") {
-        let get_color = sample_object_code.find("fn getColor").unwrap();
-        result.push_str(&sample_object_code[0..get_color]);
-    } else {
-        result.push_str(sample_object_code);
+        // Creating the pipelines checks the remaining limits, but needs an adapter
+        let instance = wgpu::Instance::default();
+        let Some(adapter) = pollster::block_on(instance.request_adapter(&Default::default()))
+        else {
+            return;
+        };
+        let (device, _queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                required_limits: limits,
+                ..Default::default()
+            },
+            None,
+        ))
+        .unwrap();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        compute_patches::compute::create_main_pipeline(&device);
+        persistent_patches::compute::create_main_pipeline(&device);
+        let error = pollster::block_on(device.pop_error_scope());
+        assert!(error.is_none(), "{error:?}");
     }
-    result.push_str(&source[end_1..]);
-    result
 }
@@ -5,13 +5,19 @@ use log::info;
 use wgpu_profiler::{GpuProfiler, GpuProfilerSettings};
 use winit::window::Window;
 
-use super::WindowOrFallback;
+use crate::texture::Texture;
+
+use super::{HDR_FORMAT, WindowOrFallback};
 
 pub struct WgpuContext {
     pub instance: wgpu::Instance,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub view_format: wgpu::TextureFormat,
+    /// Sample counts that can be used for multisampling the main render pass, in ascending order
+    pub supported_sample_counts: Vec<u32>,
+    /// Identifies the adapter and driver for the pipeline cache. `None` if pipelines cannot be cached.
+    pub pipeline_cache_key: Option<String>,
 }
 
 impl WgpuContext {
@@ -43,7 +49,10 @@ impl WgpuContext {
                 &wgpu::DeviceDescriptor {
                     required_features: wgpu::Features::default()
                         | (adapter.features() & GpuProfiler::ALL_WGPU_TIMER_FEATURES)
-                        | (adapter.features() & wgpu::Features::POLYGON_MODE_LINE),
+                        | (adapter.features() & wgpu::Features::POLYGON_MODE_LINE)
+                        | (adapter.features()
+                            & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                        | (adapter.features() & wgpu::Features::PIPELINE_CACHE),
                     required_limits: wgpu::Limits::default(),
                     label: None,
                     memory_hints: Default::default(),
@@ -74,6 +83,13 @@ impl WgpuContext {
             ),
         };
 
+        let supported_sample_counts = supported_sample_counts(&adapter, &device, HDR_FORMAT);
+        let pipeline_cache_key = device
+            .features()
+            .contains(wgpu::Features::PIPELINE_CACHE)
+            .then(|| wgpu::util::pipeline_cache_key(&adapter.get_info()))
+            .flatten();
+
         let surface_or_fallback = match surface {
             Some(surface) => {
                 let config = wgpu::SurfaceConfiguration {
@@ -106,6 +122,8 @@ impl WgpuContext {
                 device,
                 queue,
                 view_format,
+                supported_sample_counts,
+                pipeline_cache_key,
             },
             surface_or_fallback,
         ))
@@ -119,6 +137,37 @@ impl WgpuContext {
     }
 }
 
+fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    color_format: wgpu::TextureFormat,
+) -> Vec<u32> {
+    // Without this feature, only the guaranteed format features may be used
+    let format_features = |format: wgpu::TextureFormat| {
+        if device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(device.features())
+        }
+    };
+    let color = format_features(color_format);
+    let depth = format_features(Texture::DEPTH_FORMAT);
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (color.flags.sample_count_supported(count)
+                    && color
+                        .flags
+                        .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                    && depth.flags.sample_count_supported(count))
+        })
+        .collect()
+}
+
 pub enum SurfaceTexture {
     Surface(wgpu::SurfaceTexture, wgpu::TextureView, Arc<Window>),
     Fallback(wgpu::TextureView),
@@ -157,6 +206,14 @@ pub enum SurfaceOrFallback {
 }
 
 impl SurfaceOrFallback {
+    /// An offscreen texture that can be copied from
+    pub fn new_fallback(context: &WgpuContext, size: UVec2) -> Self {
+        SurfaceOrFallback::Fallback {
+            texture: create_fallback_texture(&context.device, size, context.view_format),
+            size,
+        }
+    }
+
     pub fn size(&self) -> UVec2 {
         match self {
             SurfaceOrFallback::Surface { size, .. } => *size,
@@ -239,7 +296,7 @@ fn create_fallback_texture(
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format,
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
         view_formats: &[],
     })
 }
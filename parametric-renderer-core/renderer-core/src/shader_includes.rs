@@ -0,0 +1,231 @@
+//! `////#include "name"` statements in user shaders, like in the shaders of the renderer.
+//! Instead of paths, the names refer to other shaders or to virtual files, which are looked up by the caller.
+//! ```wgsl
+//! ////#include "NoiseFunction.wgsl"
+//! fn sampleObject(input: vec2f) -> vec3f {
+//!   return vec3f(input, cnoise(input));
+//! }
+//! ```
+
+use std::{collections::HashSet, ops::Range};
+
+const INCLUDE_PREFIX: &str = "////#include";
+
+/// User code where every include statement has been replaced with the included code
+#[derive(Debug, Clone)]
+pub struct ExpandedCode {
+    /// The code as the user wrote it, which the compilation messages refer to
+    pub original: String,
+    pub expanded: String,
+    /// In the order of the code
+    inclusions: Vec<Inclusion>,
+}
+
+#[derive(Debug, Clone)]
+struct Inclusion {
+    /// The include statement in the original code, including the line break
+    statement: Range<usize>,
+    /// The included code in the expanded code
+    expanded: Range<usize>,
+}
+
+impl ExpandedCode {
+    /// Resolves the includes recursively, and includes every name only once.
+    /// Fails when a name cannot be resolved, and for cycles.
+    pub fn new(
+        code: &str,
+        resolve: impl Fn(&str) -> Result<String, String>,
+    ) -> Result<Self, wgpu::CompilationMessage> {
+        let mut expanded = String::new();
+        let mut inclusions = Vec::new();
+        let mut included = HashSet::new();
+        let mut line_offset = 0;
+        for line in code.split_inclusive('\n') {
+            let line_start = line_offset;
+            line_offset += line.len();
+            let Some(name) = parse_include(line) else {
+                expanded.push_str(line);
+                continue;
+            };
+            let error = |message: String| {
+                let location = naga::Span::new(
+                    line_start as u32,
+                    (line_start + line.trim_end().len()) as u32,
+                )
+                .location(code);
+                wgpu::CompilationMessage {
+                    message,
+                    message_type: wgpu::CompilationMessageType::Error,
+                    location: Some(wgpu::SourceLocation {
+                        line_number: location.line_number,
+                        line_position: location.line_position,
+                        offset: location.offset,
+                        length: location.length,
+                    }),
+                }
+            };
+            let included = name
+                .and_then(|name| include(name, &resolve, &mut Vec::new(), &mut included))
+                .map_err(error)?;
+            let start = expanded.len();
+            expanded.push_str(&included);
+            inclusions.push(Inclusion {
+                statement: line_start..line_offset,
+                expanded: start..expanded.len(),
+            });
+        }
+        Ok(Self {
+            original: code.to_string(),
+            expanded,
+            inclusions,
+        })
+    }
+
+    /// Maps a range of the expanded code to the original code.
+    /// Included code maps to its include statement.
+    pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        let mut original_start = range.start;
+        let mut original_end = range.end;
+        for inclusion in &self.inclusions {
+            if range.start < inclusion.expanded.start {
+                break;
+            }
+            if range.start < inclusion.expanded.end {
+                let statement = &self.original[inclusion.statement.clone()];
+                return inclusion.statement.start
+                    ..inclusion.statement.start + statement.trim_end().len();
+            }
+            // The included code can be shorter or longer than the statement
+            original_start = original_start + inclusion.statement.len() - inclusion.expanded.len();
+            original_end = original_end + inclusion.statement.len() - inclusion.expanded.len();
+        }
+        original_start..original_end.max(original_start)
+    }
+}
+
+/// Names of the files that the code includes, directly or indirectly.
+/// Unlike [`ExpandedCode::new`], this also works for code with unknown includes or cycles.
+pub fn include_names(
+    code: &str,
+    resolve: impl Fn(&str) -> Result<String, String>,
+) -> HashSet<String> {
+    let mut names = HashSet::new();
+    let mut pending = vec![code.to_string()];
+    while let Some(code) = pending.pop() {
+        for line in code.lines() {
+            let Some(Ok(name)) = parse_include(line) else {
+                continue;
+            };
+            if names.insert(name.to_string()) {
+                pending.extend(resolve(name).ok());
+            }
+        }
+    }
+    names
+}
+
+/// The included code, with its own includes already replaced.
+/// `stack` are the files that are currently being included, for finding cycles.
+/// `included` are the files that were already included, which are left out when they come up again.
+fn include(
+    name: &str,
+    resolve: &impl Fn(&str) -> Result<String, String>,
+    stack: &mut Vec<String>,
+    included: &mut HashSet<String>,
+) -> Result<String, String> {
+    if stack.iter().any(|v| v == name) {
+        return Err(format!("Include cycle {} -> {}", stack.join(" -> "), name));
+    }
+    if !included.insert(name.to_string()) {
+        return Ok(String::new());
+    }
+    let code = resolve(name)?;
+    stack.push(name.to_string());
+    let mut result = String::new();
+    for line in code.split_inclusive('\n') {
+        match parse_include(line) {
+            Some(nested) => result.push_str(&include(nested?, resolve, stack, included)?),
+            None => result.push_str(line),
+        }
+    }
+    stack.pop();
+    // Keeps the code after the include statement on its own line
+    if !result.ends_with('\n') {
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// `None` if the line is not an include statement
+fn parse_include(line: &str) -> Option<Result<&str, String>> {
+    let path = line.trim().strip_prefix(INCLUDE_PREFIX)?.trim();
+    Some(
+        path.strip_prefix('"')
+            .and_then(|path| path.strip_suffix('"'))
+            .ok_or_else(|| format!("Expected {INCLUDE_PREFIX} \"name\"")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(name: &str) -> Result<String, String> {
+        match name {
+            "Noise.wgsl" => Ok("////#include \"Math.wgsl\"\nfn noise() {}\n".into()),
+            "Color.wgsl" => Ok("////#include \"Math.wgsl\"\nfn color() {}\n".into()),
+            "Math.wgsl" => Ok("const PI = 3.14;".into()),
+            "Loop.wgsl" => Ok("////#include \"Loop.wgsl\"\n".into()),
+            _ => Err(format!("Unknown include {name}")),
+        }
+    }
+
+    #[test]
+    fn expands_nested_includes() {
+        let code = "////#include \"Noise.wgsl\"\nfn sampleObject() {}\n";
+        let expanded = ExpandedCode::new(code, files).unwrap();
+        assert_eq!(
+            expanded.expanded,
+            "const PI = 3.14;\nfn noise() {}\nfn sampleObject() {}\n"
+        );
+        let offset = expanded.expanded.find("fn sampleObject").unwrap();
+        let start = code.find("fn sampleObject").unwrap();
+        assert_eq!(
+            expanded.original_range(offset..offset + 2),
+            start..start + 2
+        );
+        assert_eq!(expanded.original_range(3..5), 0..code.find('\n').unwrap());
+    }
+
+    #[test]
+    fn includes_shared_files_once() {
+        let code = "////#include \"Noise.wgsl\"\n////#include \"Color.wgsl\"\n\
+            ////#include \"Math.wgsl\"\nfn sampleObject() {}\n";
+        let expanded = ExpandedCode::new(code, files).unwrap();
+        assert_eq!(
+            expanded.expanded,
+            "const PI = 3.14;\nfn noise() {}\nfn color() {}\nfn sampleObject() {}\n"
+        );
+        let offset = expanded.expanded.find("fn sampleObject").unwrap();
+        let start = code.find("fn sampleObject").unwrap();
+        assert_eq!(
+            expanded.original_range(offset..offset + 2),
+            start..start + 2
+        );
+    }
+
+    #[test]
+    fn reports_cycles_and_unknown_includes() {
+        let code = "fn a() {}\n////#include \"Loop.wgsl\"\n";
+        let error = ExpandedCode::new(code, files).unwrap_err();
+        assert_eq!(error.location.unwrap().line_number, 2);
+        assert!(error.message.contains("cycle"));
+
+        let code = "////#include \"Missing.wgsl\"\n";
+        assert!(ExpandedCode::new(code, files).is_err());
+        assert_eq!(
+            include_names(code, files),
+            HashSet::from(["Missing.wgsl".to_string()])
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! Named, typed uniforms that a parametric shader declares with `// @param` comments, like
+//! ```wgsl
+//! // @param radius: f32 = 1.0 [0, 5]
+//! // @param offset: vec3f = vec3f(0.0, 1.0, 0.0)
+//! ```
+//! The shader then reads them with `parameters.radius` and `parameters.offset`.
+
+use std::collections::HashMap;
+
+/// Size of the uniform buffer of each model, which limits how many parameters a shader can have
+pub const PARAMETERS_BUFFER_SIZE: u64 = 256;
+
+/// Values of the parameters of one model, by name. Missing values fall back to the defaults of the shader.
+pub type ParameterValues = HashMap<String, Vec<f32>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterType {
+    F32,
+    I32,
+    U32,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl ParameterType {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "f32" => Some(Self::F32),
+            "i32" => Some(Self::I32),
+            "u32" => Some(Self::U32),
+            "vec2f" | "vec2<f32>" => Some(Self::Vec2),
+            "vec3f" | "vec3<f32>" => Some(Self::Vec3),
+            "vec4f" | "vec4<f32>" => Some(Self::Vec4),
+            _ => None,
+        }
+    }
+
+    pub fn wgsl_name(self) -> &'static str {
+        match self {
+            Self::F32 => "f32",
+            Self::I32 => "i32",
+            Self::U32 => "u32",
+            Self::Vec2 => "vec2f",
+            Self::Vec3 => "vec3f",
+            Self::Vec4 => "vec4f",
+        }
+    }
+
+    pub fn components(self) -> usize {
+        match self {
+            Self::F32 | Self::I32 | Self::U32 => 1,
+            Self::Vec2 => 2,
+            Self::Vec3 => 3,
+            Self::Vec4 => 4,
+        }
+    }
+
+    /// Alignment in bytes, following the WGSL memory layout rules
+    fn align(self) -> u64 {
+        match self {
+            Self::F32 | Self::I32 | Self::U32 => 4,
+            Self::Vec2 => 8,
+            Self::Vec3 | Self::Vec4 => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderParameter {
+    pub name: String,
+    pub ty: ParameterType,
+    /// One value per component. Integers are stored as floats as well.
+    pub default: Vec<f32>,
+    /// Inclusive slider range, if the annotation has one
+    pub range: Option<(f32, f32)>,
+    /// Byte offset in the uniform buffer
+    offset: u64,
+}
+
+/// The parameters of a shader, in the order in which they were declared
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShaderParameters {
+    pub parameters: Vec<ShaderParameter>,
+}
+
+/// An annotation that could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterError {
+    /// 1-based, like in the compiler messages
+    pub line_number: u32,
+    /// Byte offset of the line in the shader code
+    pub offset: u32,
+    pub length: u32,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line_number, self.message)
+    }
+}
+
+impl std::error::Error for ParameterError {}
+
+impl From<ParameterError> for wgpu::CompilationMessage {
+    fn from(error: ParameterError) -> Self {
+        wgpu::CompilationMessage {
+            message: error.message,
+            message_type: wgpu::CompilationMessageType::Error,
+            location: Some(wgpu::SourceLocation {
+                line_number: error.line_number,
+                line_position: 1,
+                offset: error.offset,
+                length: error.length,
+            }),
+        }
+    }
+}
+
+const ANNOTATION: &str = "// @param";
+
+impl ShaderParameters {
+    /// Finds every `// @param name: type = default [min, max]` line. The default and the range are optional.
+    pub fn parse(code: &str) -> Result<Self, ParameterError> {
+        let mut parameters: Vec<ShaderParameter> = Vec::new();
+        let mut size = 0u64;
+        let mut line_offset = 0;
+        for (index, line) in code.split_inclusive('\n').enumerate() {
+            let line_start = line_offset;
+            line_offset += line.len();
+            let error = |message: String| ParameterError {
+                line_number: index as u32 + 1,
+                offset: line_start as u32,
+                length: line.trim_end().len() as u32,
+                message,
+            };
+            let Some(annotation) = line.trim().strip_prefix(ANNOTATION) else {
+                continue;
+            };
+            if is_documentation(annotation) {
+                continue;
+            }
+            let mut parameter = parse_annotation(annotation).map_err(error)?;
+            if parameters.iter().any(|v| v.name == parameter.name) {
+                return Err(error(format!("Duplicate parameter {}", parameter.name)));
+            }
+            parameter.offset = size.next_multiple_of(parameter.ty.align());
+            size = parameter.offset + 4 * parameter.ty.components() as u64;
+            if size > PARAMETERS_BUFFER_SIZE {
+                return Err(error(format!(
+                    "Parameters can use at most {PARAMETERS_BUFFER_SIZE} bytes"
+                )));
+            }
+            parameters.push(parameter);
+        }
+        Ok(Self { parameters })
+    }
+
+    /// The `Parameters` struct that gets injected into the shader
+    pub fn to_wgsl(&self) -> String {
+        let mut result = String::from("struct Parameters {\n");
+        for parameter in &self.parameters {
+            result.push_str(&format!(
+                "  {}: {},\n",
+                parameter.name,
+                parameter.ty.wgsl_name()
+            ));
+        }
+        if self.parameters.is_empty() {
+            // WGSL does not allow empty structs
+            result.push_str("  unused: u32,\n");
+        }
+        result.push('}');
+        result
+    }
+
+    /// Contents of the uniform buffer. Values are clamped to the range of their parameter.
+    pub fn to_bytes(&self, values: &ParameterValues) -> Vec<u8> {
+        let mut bytes = vec![0; PARAMETERS_BUFFER_SIZE as usize];
+        for parameter in &self.parameters {
+            let value = values
+                .get(&parameter.name)
+                .filter(|v| v.len() == parameter.ty.components())
+                .unwrap_or(&parameter.default);
+            for (i, &component) in value.iter().enumerate() {
+                let component = match parameter.range {
+                    Some((min, max)) => component.clamp(min, max),
+                    None => component,
+                };
+                let component_bytes = match parameter.ty {
+                    ParameterType::I32 => (component as i32).to_le_bytes(),
+                    ParameterType::U32 => (component as u32).to_le_bytes(),
+                    _ => component.to_le_bytes(),
+                };
+                let start = parameter.offset as usize + 4 * i;
+                bytes[start..start + 4].copy_from_slice(&component_bytes);
+            }
+        }
+        bytes
+    }
+}
+
+/// Doc comments also use `@param name description`, without a type.
+/// A description that starts like a type is a forgotten colon, and gets reported instead.
+fn is_documentation(annotation: &str) -> bool {
+    let Some((name, description)) = annotation.trim().split_once(char::is_whitespace) else {
+        return false;
+    };
+    let description = description.trim();
+    let first_word = description
+        .split(|c: char| c.is_whitespace() || c == '=' || c == '[')
+        .next()
+        .unwrap_or_default();
+    is_identifier(name)
+        && !description.is_empty()
+        && !description.starts_with(':')
+        && ParameterType::parse(first_word).is_none()
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_annotation(annotation: &str) -> Result<ShaderParameter, String> {
+    let (declaration, range) = match annotation.split_once('[') {
+        Some((declaration, range)) => {
+            let range = range
+                .trim()
+                .strip_suffix(']')
+                .ok_or_else(|| "Missing ] after the range".to_string())?;
+            (declaration, Some(parse_range(range)?))
+        }
+        None => (annotation, None),
+    };
+    let (declaration, default) = match declaration.split_once('=') {
+        Some((declaration, default)) => (declaration, Some(default.trim())),
+        None => (declaration, None),
+    };
+    let (name, ty) = declaration
+        .split_once(':')
+        .ok_or_else(|| "Expected name: type".to_string())?;
+    let name = name.trim();
+    if !is_identifier(name) {
+        return Err(format!("Invalid parameter name {name}"));
+    }
+    let ty = ParameterType::parse(ty.trim())
+        .ok_or_else(|| format!("Unsupported parameter type {}", ty.trim()))?;
+    let default = match default {
+        Some(default) => parse_default(default, ty)?,
+        None => vec![0.0; ty.components()],
+    };
+    Ok(ShaderParameter {
+        name: name.to_string(),
+        ty,
+        default,
+        range,
+        offset: 0,
+    })
+}
+
+fn parse_number(text: &str) -> Result<f32, String> {
+    // Allows WGSL suffixes, like 1.0f or 2u
+    let text = text.trim().trim_end_matches(['f', 'i', 'u']);
+    text.parse::<f32>()
+        .map_err(|_| format!("Invalid number {}", text))
+}
+
+fn parse_range(range: &str) -> Result<(f32, f32), String> {
+    let (min, max) = range
+        .split_once(',')
+        .ok_or_else(|| "Expected [min, max]".to_string())?;
+    let (min, max) = (parse_number(min)?, parse_number(max)?);
+    if min > max {
+        return Err(format!(
+            "The range minimum {min} is larger than the maximum {max}"
+        ));
+    }
+    Ok((min, max))
+}
+
+/// Either a number, or a vector constructor like `vec3f(1.0, 0.0, 0.0)` or `vec3(1.0)`
+fn parse_default(default: &str, ty: ParameterType) -> Result<Vec<f32>, String> {
+    let arguments = match default.split_once('(') {
+        Some((_, arguments)) => arguments
+            .strip_suffix(')')
+            .ok_or_else(|| "Missing ) after the default value".to_string())?,
+        None => default,
+    };
+    let values = arguments
+        .split(',')
+        .map(parse_number)
+        .collect::<Result<Vec<_>, _>>()?;
+    match values.len() {
+        1 => Ok(vec![values[0]; ty.components()]),
+        n if n == ty.components() => Ok(values),
+        n => Err(format!(
+            "Expected {} values for {}, but found {}",
+            ty.components(),
+            ty.wgsl_name(),
+            n
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_annotations() {
+        let code = "// @param radius: f32 = 1.0 [0, 5]\n\
+            fn sampleObject(input: vec2f) -> vec3f {\n\
+            // @param offset: vec3f = vec3f(0.0, 1.0, 0.0)\n\
+            // @param count: u32\n";
+        let parameters = ShaderParameters::parse(code).unwrap().parameters;
+        assert_eq!(parameters.len(), 3);
+        assert_eq!(parameters[0].name, "radius");
+        assert_eq!(parameters[0].default, vec![1.0]);
+        assert_eq!(parameters[0].range, Some((0.0, 5.0)));
+        assert_eq!(parameters[1].ty, ParameterType::Vec3);
+        assert_eq!(parameters[1].default, vec![0.0, 1.0, 0.0]);
+        assert_eq!(parameters[1].offset, 16);
+        assert_eq!(parameters[2].offset, 28);
+    }
+
+    #[test]
+    fn reports_line_of_invalid_annotation() {
+        let code = "fn a() {}\n// @param radius: f64 = 1.0\n";
+        let error = ShaderParameters::parse(code).unwrap_err();
+        assert_eq!(error.line_number, 2);
+        assert_eq!(error.offset, 10);
+    }
+
+    #[test]
+    fn skips_doc_comments() {
+        let code = "// @param alphaDeg      angle of tangent in degrees\n\
+            // @param a             radius: of ellipse\n";
+        let parameters = ShaderParameters::parse(code).unwrap().parameters;
+        assert!(parameters.is_empty());
+    }
+
+    #[test]
+    fn reports_malformed_annotations() {
+        for code in [
+            "// @param radius\n",
+            "// @param radius f32 = 1.0\n",
+            "// @param radius : f64\n",
+            "// @param 2radius: f32\n",
+        ] {
+            assert!(ShaderParameters::parse(code).is_err(), "{code}");
+        }
+    }
+}
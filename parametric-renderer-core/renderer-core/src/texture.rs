@@ -1,16 +1,75 @@
 use glam::UVec2;
 
-use crate::game::TextureInfo;
+use crate::{
+    game::{AddressMode, FilterMode, SamplerInfo, TextureInfo},
+    shaders::mipmap,
+};
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
 }
 
+/// A texture for a material, together with the sampler that it should be sampled with
+pub struct SampledTexture {
+    pub texture: Texture,
+    /// The raw values, for textures that store data instead of colors. Like normal maps.
+    /// It has its own mip chain, since averaging in linear space would skew the data.
+    pub data: Texture,
+    pub sampler: wgpu::Sampler,
+}
+
+impl SampledTexture {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        info: &TextureInfo,
+        mipmaps: &MipmapGenerator,
+    ) -> Self {
+        let texture = Texture::new_rgba(device, queue, info, mipmaps);
+        let data = Texture::new_data_copy(device, queue, &texture.texture, mipmaps);
+        Self {
+            texture,
+            data,
+            sampler: create_sampler(device, &info.sampler),
+        }
+    }
+}
+
+fn create_sampler(device: &wgpu::Device, info: &SamplerInfo) -> wgpu::Sampler {
+    let address_mode = match info.address_mode {
+        AddressMode::Repeat => wgpu::AddressMode::Repeat,
+        AddressMode::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+        AddressMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+    };
+    let (filter, anisotropy_clamp) = match info.filter {
+        // wgpu only allows anisotropic filtering when everything is linearly filtered
+        FilterMode::Nearest => (wgpu::FilterMode::Nearest, 1),
+        FilterMode::Linear => (wgpu::FilterMode::Linear, info.anisotropy.clamp(1, 16)),
+    };
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Texture Sampler"),
+        address_mode_u: address_mode,
+        address_mode_v: address_mode,
+        address_mode_w: address_mode,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: filter,
+        anisotropy_clamp,
+        ..Default::default()
+    })
+}
+
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn new_rgba(device: &wgpu::Device, queue: &wgpu::Queue, info: &TextureInfo) -> Self {
+    /// Uploads the texture and generates its mip chain
+    pub fn new_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        info: &TextureInfo,
+        mipmaps: &MipmapGenerator,
+    ) -> Self {
         let size = wgpu::Extent3d {
             width: info.width,
             height: info.height,
@@ -19,11 +78,12 @@ impl Texture {
         let desc = wgpu::TextureDescriptor {
             label: None,
             size,
-            mip_level_count: 1,
+            mip_level_count: size.max_mips(wgpu::TextureDimension::D2),
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: MipmapGenerator::FORMAT,
             usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
                 | wgpu::TextureUsages::COPY_DST
                 | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
@@ -61,17 +121,56 @@ impl Texture {
                 );
             }
         }
+        mipmaps.generate(device, queue, &texture);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         Self { texture, view }
     }
 
-    pub fn create_depth_texture(device: &wgpu::Device, size: UVec2, label: &str) -> Self {
+    /// Copies the first mip level of a color texture without decoding it, and generates a mip chain from the raw values
+    pub fn new_data_copy(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: &wgpu::Texture,
+        mipmaps: &MipmapGenerator,
+    ) -> Self {
+        let size = color.size();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: color.mip_level_count(),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: MipmapGenerator::DATA_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        // Formats that only differ in their sRGB suffix can be copied between
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Data Texture Copy"),
+        });
+        encoder.copy_texture_to_texture(color.as_image_copy(), texture.as_image_copy(), size);
+        queue.submit(std::iter::once(encoder.finish()));
+        mipmaps.generate(device, queue, &texture);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+
+    pub fn create_depth_texture(
+        device: &wgpu::Device,
+        size: UVec2,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size: size.to_extent(),
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -83,12 +182,17 @@ impl Texture {
         Self { texture, view }
     }
 
-    pub fn create_object_id_texture(device: &wgpu::Device, size: UVec2, label: &str) -> Self {
+    pub fn create_object_id_texture(
+        device: &wgpu::Device,
+        size: UVec2,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size: size.to_extent(),
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::R32Uint,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -99,6 +203,153 @@ impl Texture {
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         Self { texture, view }
     }
+
+    /// A texture that can be rendered to, sampled and copied. Like the HDR image for post processing.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        size: UVec2,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
+        label: &str,
+    ) -> Self {
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size: size.to_extent(),
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+
+    /// A render target that gets resolved into a single sampled texture
+    pub fn create_multisampled_texture(
+        device: &wgpu::Device,
+        size: UVec2,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size: size.to_extent(),
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+}
+
+/// Fills in the mip levels of a texture by repeatedly downsampling the previous level
+pub struct MipmapGenerator {
+    pipeline: wgpu::RenderPipeline,
+    data_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl MipmapGenerator {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    /// For textures that store data, which gets averaged without any decoding
+    pub const DATA_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = mipmap::create_shader_module(device);
+        let create_pipeline = |format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Mipmap Generator"),
+                layout: Some(&mipmap::create_pipeline_layout(device)),
+                vertex: mipmap::vertex_state(&shader, &mipmap::vs_main_entry()),
+                fragment: Some(mipmap::fragment_state(
+                    &shader,
+                    &mipmap::fs_main_entry([Some(format.into())]),
+                )),
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: Default::default(),
+            })
+        };
+        let pipeline = create_pipeline(Self::FORMAT);
+        let data_pipeline = create_pipeline(Self::DATA_FORMAT);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            pipeline,
+            data_pipeline,
+            sampler,
+        }
+    }
+
+    /// Expects the first mip level to be filled in already. The texture has to be in [`Self::FORMAT`] or [`Self::DATA_FORMAT`].
+    pub fn generate(&self, device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        if texture.mip_level_count() <= 1 {
+            return;
+        }
+        let views = (0..texture.mip_level_count())
+            .map(|mip_level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mipmap View"),
+                    base_mip_level: mip_level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let pipeline = if texture.format() == Self::DATA_FORMAT {
+            &self.data_pipeline
+        } else {
+            &self.pipeline
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for levels in views.windows(2) {
+            let bind_group = mipmap::bind_groups::BindGroup0::from_bindings(
+                device,
+                mipmap::bind_groups::BindGroupLayout0 {
+                    source: &levels[0],
+                    source_sampler: &self.sampler,
+                },
+            );
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &levels[1],
+                    resolve_target: None,
+                    ops: Default::default(),
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            bind_group.set(&mut render_pass);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
 }
 
 trait ToExtent3d {
@@ -1,4 +1,4 @@
 mod time_counters;
 pub use time_counters::{TimeCounters, TimeStats};
 mod frame_counter;
-pub use frame_counter::{FrameCounter, FrameTime, Seconds};
+pub use frame_counter::{ClockMode, FrameCounter, FrameTime, Seconds};
@@ -1,41 +1,96 @@
 use web_time::Instant;
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Seconds(pub f32);
 
 pub struct FrameTime {
     pub frame: u64,
     pub delta: Seconds,
     pub elapsed: Seconds,
+    /// Wall-clock time since the last frame, regardless of the clock mode
+    pub real_delta: Seconds,
+}
+
+/// How the elapsed time advances from one frame to the next
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ClockMode {
+    #[default]
+    RealTime,
+    /// Every frame advances by the same amount, for reproducible renders
+    FixedStep(Seconds),
+    Paused,
 }
 
 pub struct FrameCounter {
     pub frame: u64,
-    pub first_render_instant: Option<Instant>,
+    pub mode: ClockMode,
+    pub elapsed: Seconds,
+    /// Set when jumping to a point in time
+    next_elapsed: Option<Seconds>,
     pub render_instant: Option<Instant>,
 }
 impl FrameCounter {
     pub fn new() -> Self {
         Self {
             frame: 0,
-            first_render_instant: None,
+            mode: ClockMode::RealTime,
+            elapsed: Seconds(0.0),
+            next_elapsed: None,
             render_instant: None,
         }
     }
 
+    pub fn set_mode(&mut self, mode: ClockMode) {
+        self.mode = mode;
+    }
+
+    /// Jumps to a point in time, which the next frame will use
+    pub fn set_elapsed(&mut self, elapsed: Seconds) {
+        self.next_elapsed = Some(elapsed);
+    }
+
     pub fn new_frame(&mut self) -> FrameTime {
         let frame = self.frame;
         let now = Instant::now();
-        let first_render_instant = *self.first_render_instant.get_or_insert(now);
         let previous_render_instant = *self.render_instant.get_or_insert(now);
-        let delta = Seconds((now - previous_render_instant).as_secs_f32());
-        let elapsed = Seconds((now - first_render_instant).as_secs_f32());
+        let real_delta = Seconds((now - previous_render_instant).as_secs_f32());
+        let delta = match self.mode {
+            ClockMode::RealTime => real_delta,
+            ClockMode::FixedStep(step) => step,
+            ClockMode::Paused => Seconds(0.0),
+        };
+        self.elapsed = self
+            .next_elapsed
+            .take()
+            .unwrap_or(Seconds(self.elapsed.0 + delta.0));
         self.render_instant = Some(now);
         self.frame += 1;
         FrameTime {
             frame,
             delta,
-            elapsed,
+            elapsed: self.elapsed,
+            real_delta,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_step_and_paused_clocks() {
+        let mut counter = FrameCounter::new();
+        counter.set_mode(ClockMode::FixedStep(Seconds(0.5)));
+        counter.set_elapsed(Seconds(2.0));
+        let elapsed = (0..3)
+            .map(|_| counter.new_frame().elapsed)
+            .collect::<Vec<_>>();
+        assert_eq!(elapsed, vec![Seconds(2.0), Seconds(2.5), Seconds(3.0)]);
+
+        counter.set_mode(ClockMode::Paused);
+        counter.set_elapsed(Seconds(10.0));
+        assert_eq!(counter.new_frame().elapsed, Seconds(10.0));
+        assert_eq!(counter.new_frame().elapsed, Seconds(10.0));
+    }
+}
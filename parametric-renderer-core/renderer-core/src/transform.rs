@@ -1,19 +1,20 @@
-use glam::{Mat4, Vec3};
+use glam::{Affine3A, Mat3, Mat4, Vec3};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Transform {
     pub position: Vec3,
     pub rotation: glam::Quat,
-    pub scale: f32,
+    pub scale: Vec3,
+    /// Replaces the position, rotation and scale, for shears or matrices from other tools
+    pub matrix: Option<Affine3A>,
 }
 
 impl Transform {
     pub fn to_matrix(&self) -> Mat4 {
-        Mat4::from_scale_rotation_translation(
-            Vec3::new(self.scale, self.scale, self.scale),
-            self.rotation,
-            self.position,
-        )
+        match self.matrix {
+            Some(matrix) => Mat4::from(matrix),
+            None => Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.position),
+        }
     }
 }
 
@@ -22,7 +23,61 @@ impl Default for Transform {
         Self {
             position: Vec3::new(0.0, 0.0, 0.0),
             rotation: glam::Quat::IDENTITY,
-            scale: 1.0,
+            scale: Vec3::ONE,
+            matrix: None,
         }
     }
 }
+
+/// The inverse transpose, which keeps normals perpendicular to the surface under non-uniform scaling.
+/// Normals have to be normalized afterwards.
+///
+/// Uses the cofactor matrix, which is the inverse transpose times the determinant.
+/// It needs no division, so tiny models work, and models that are squashed flat still get a normal.
+pub fn normal_matrix(model_matrix: Mat4) -> Mat3 {
+    let linear = Mat3::from_mat4(model_matrix);
+    let cofactor = Mat3::from_cols(
+        linear.y_axis.cross(linear.z_axis),
+        linear.z_axis.cross(linear.x_axis),
+        linear.x_axis.cross(linear.y_axis),
+    );
+    // Mirrored models would otherwise get flipped normals
+    cofactor * linear.determinant().signum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let transform = Transform {
+            scale: Vec3::new(4.0, 1.0, 1.0),
+            rotation: glam::Quat::from_rotation_z(0.5),
+            ..Default::default()
+        };
+        let matrix = transform.to_matrix();
+        // A diagonal surface, with its normal
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let normal = Vec3::new(1.0, 1.0, 0.0);
+        let world_tangent = matrix.transform_vector3(tangent);
+        let world_normal = normal_matrix(matrix) * normal;
+        assert!(world_tangent.dot(world_normal).abs() < 1e-5);
+        assert!(matrix.transform_vector3(normal).dot(world_tangent).abs() > 1.0);
+    }
+
+    #[test]
+    fn normals_of_tiny_models() {
+        let transform = Transform {
+            scale: Vec3::new(0.001, 0.004, 0.002),
+            ..Default::default()
+        };
+        let matrix = transform.to_matrix();
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let normal = Vec3::new(1.0, 1.0, 0.0);
+        let world_tangent = matrix.transform_vector3(tangent).normalize();
+        let world_normal = (normal_matrix(matrix) * normal).normalize();
+        assert!(world_tangent.dot(world_normal).abs() < 1e-5);
+        assert!(world_normal.dot(normal) > 0.0);
+    }
+}
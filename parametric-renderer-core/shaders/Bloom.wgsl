@@ -0,0 +1,70 @@
+// Bloom with a chain of downsampled images, which are upsampled and added back together.
+// Inspired by https://learnopengl.com/Guest-Articles/2022/Phys.-Based-Bloom
+
+struct BloomParams {
+  // Brightness where the bloom starts
+  threshold: f32,
+  // Width of the smooth transition around the threshold
+  knee: f32,
+}
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> params: BloomParams;
+
+struct VertexOutput {
+  @builtin(position) position: vec4f,
+  @location(0) uv: vec2f,
+}
+
+// A single triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+  let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+  var out: VertexOutput;
+  out.position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+  out.uv = uv;
+  return out;
+}
+
+/// Averages 16 texels with 4 bilinear samples
+fn downsample(uv: vec2f) -> vec3f {
+  let texel = 1.0 / vec2f(textureDimensions(source));
+  var color = textureSample(source, source_sampler, uv + texel * vec2f(-1.0, -1.0)).rgb;
+  color += textureSample(source, source_sampler, uv + texel * vec2f(1.0, -1.0)).rgb;
+  color += textureSample(source, source_sampler, uv + texel * vec2f(-1.0, 1.0)).rgb;
+  color += textureSample(source, source_sampler, uv + texel * vec2f(1.0, 1.0)).rgb;
+  return color * 0.25;
+}
+
+/// Keeps only the bright parts of the image, with a soft transition
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4f {
+  let color = downsample(in.uv);
+  let brightness = max(color.r, max(color.g, color.b));
+  var soft = clamp(brightness - params.threshold + params.knee, 0.0, 2.0 * params.knee);
+  soft = soft * soft / (4.0 * params.knee + 1e-4);
+  let contribution = max(soft, brightness - params.threshold) / max(brightness, 1e-4);
+  return vec4f(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4f {
+  return vec4f(downsample(in.uv), 1.0);
+}
+
+/// 3x3 tent filter. The result gets added to the larger image with blending.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4f {
+  let texel = 1.0 / vec2f(textureDimensions(source));
+  var color = textureSample(source, source_sampler, in.uv).rgb * 4.0;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(-1.0, 0.0)).rgb * 2.0;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(1.0, 0.0)).rgb * 2.0;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(0.0, -1.0)).rgb * 2.0;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(0.0, 1.0)).rgb * 2.0;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(-1.0, -1.0)).rgb;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(1.0, -1.0)).rgb;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(-1.0, 1.0)).rgb;
+  color += textureSample(source, source_sampler, in.uv + texel * vec2f(1.0, 1.0)).rgb;
+  return vec4f(color / 16.0, 1.0);
+}
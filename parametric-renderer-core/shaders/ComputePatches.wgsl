@@ -8,9 +8,10 @@ fn sampleObject(input: vec2f) -> vec3f {
 }
 //// END sampleObject
 var<private> instance_id: u32;
+var<private> instance: Instance;
 
 ////#include "./Common.wgsl"
-//// AUTOGEN 6de14edf9918265eb2e1232f93b94c84430d0069898214379635e51c3d4c9550
+//// AUTOGEN d5bc2f7e981d9972b3c852bb1248465b910ae2d693454f5ffbc068cff688e21f
 struct EncodedPatch {
   u: u32,
   v: u32,
@@ -22,11 +23,19 @@ struct Patch {
   instance: u32
 };
 struct Patches {
+  // Indirect dispatch arguments for the next round, one workgroup per patch.
+  // They live in the same buffer to save a storage buffer binding.
+  dispatch_x: atomic<u32>,
+  dispatch_y: u32,
+  dispatch_z: u32,
   patches_length: atomic<u32>,
   patches_capacity: u32,
   patches : array<EncodedPatch>,
 };
 struct PatchesRead { // Is currently needed, see https://github.com/gpuweb/gpuweb/discussions/4438
+  dispatch_x: u32,
+  dispatch_y: u32,
+  dispatch_z: u32,
   patches_length: u32, // Same size and alignment as atomic<u32>. Should be legal, right?
   patches_capacity: u32,
   patches : array<EncodedPatch>,
@@ -41,11 +50,84 @@ struct RenderBufferRead {
   patches_capacity: u32,
   patches: array<EncodedPatch>,
 };
-struct DispatchIndirectArgs { // From https://docs.rs/wgpu/latest/wgpu/util/struct.DispatchIndirectArgs.html
-  x: atomic<u32>,
-  y: u32,
-  z: u32,
+// The data of one instance of a model, indexed by the instance of a patch
+struct Instance {
+  // Applied after sampleObject, relative to the model
+  transform: mat4x4<f32>,
+  normal_matrix: mat3x3<f32>,
+  // Multiplies the result of getColor
+  tint: vec3<f32>,
+  // Free for the user code
+  custom: vec4<f32>,
 };
+// Instances are stored in a rgba32uint texture, since the LOD stage has no storage buffer binding left.
+// Every instance takes up INSTANCE_TEXELS texels in the layout of a storage buffer, and a row has INSTANCES_PER_ROW of them.
+const INSTANCE_TEXELS: u32 = 9u;
+const INSTANCES_PER_ROW: u32 = 512u;
+fn instance_texel(instances: texture_2d<u32>, id: u32, texel: u32) -> vec4f {
+  let x = (id % INSTANCES_PER_ROW) * INSTANCE_TEXELS + texel;
+  return bitcast<vec4f>(textureLoad(instances, vec2(x, id / INSTANCES_PER_ROW), 0));
+}
+fn instance_load(instances: texture_2d<u32>, id: u32) -> Instance {
+  return Instance(
+    mat4x4(
+      instance_texel(instances, id, 0u),
+      instance_texel(instances, id, 1u),
+      instance_texel(instances, id, 2u),
+      instance_texel(instances, id, 3u)
+    ),
+    mat3x3(
+      instance_texel(instances, id, 4u).xyz,
+      instance_texel(instances, id, 5u).xyz,
+      instance_texel(instances, id, 6u).xyz
+    ),
+    instance_texel(instances, id, 7u).xyz,
+    instance_texel(instances, id, 8u)
+  );
+}
+// A hash map of the subdivision tree, so that the render stage can find the neighbours of a patch.
+// Every split, every rendered patch and every culled patch gets a node.
+struct LodNode {
+  // Set to 1 by whoever claims the slot
+  occupied: atomic<u32>,
+  key: EncodedPatch,
+  // LOD_SPLIT_U and LOD_SPLIT_V flags, 0 for a patch that got rendered
+  splits: u32,
+  // How many quads a rendered patch has along one side, 0 for a culled patch
+  quads_per_side: u32,
+};
+struct LodTree {
+  // Set when a node did not fit, which means that the tree is incomplete
+  overflowed: atomic<u32>,
+  nodes: array<LodNode>,
+};
+struct LodNodeRead {
+  occupied: u32,
+  key: EncodedPatch,
+  splits: u32,
+  quads_per_side: u32,
+};
+struct LodTreeRead {
+  overflowed: u32,
+  nodes: array<LodNodeRead>,
+};
+const LOD_SPLIT_U: u32 = 1u;
+const LOD_SPLIT_V: u32 = 2u;
+// Linear probing gives up after this many slots
+const LOD_TREE_MAX_PROBES: u32 = 64u;
+fn lod_node_hash(key: EncodedPatch) -> u32 {
+  // Integer hash from https://nullprogram.com/blog/2018/07/31/
+  var hash = (key.u * 0x9e3779b1u) ^ (key.v * 0x85ebca77u) ^ (key.instance * 0xc2b2ae3du);
+  hash ^= hash >> 16u;
+  hash *= 0x7feb352du;
+  hash ^= hash >> 15u;
+  hash *= 0x846ca68bu;
+  hash ^= hash >> 16u;
+  return hash;
+}
+fn lod_node_is(node_key: EncodedPatch, key: EncodedPatch) -> bool {
+  return node_key.u == key.u && node_key.v == key.v && node_key.instance == key.instance;
+}
 fn ceil_div(a: u32, b: u32) -> u32 { return (a + b - 1u) / b; }
 // Inspired from https://onrendering.com/data/papers/isubd/isubd.pdf
 fn patch_u_child(u: u32, child_bit: u32) -> u32 {
@@ -144,16 +226,13 @@ fn mouse_held(button: u32) -> bool {
 @group(0) @binding(3) var<uniform> extra : Extra;
 
 //// END OF AUTOGEN
-
+////#include "./LodStage.wgsl"
+//// AUTOGEN fc9a31b8cb432046e89ab76f5a6a2a8330ff321235835002eb78f2c27636a523
 struct InputBuffer {
     threshold_factor: f32,
     model_view_projection: mat4x4<f32>,
 };
 
-struct ForceRenderFlag {
-  flag: u32 // if flag == 0 { false } else { true }
-}
-
 // Group 1 is for things that change once per model
 // TODO: Read back the patches_length on the CPU to know when we're going out of bounds
 // (And how far out of bounds)
@@ -163,11 +242,19 @@ struct ForceRenderFlag {
 @group(1) @binding(3) var<storage, read_write> render_buffer_8 : RenderBuffer;
 @group(1) @binding(4) var<storage, read_write> render_buffer_16 : RenderBuffer;
 @group(1) @binding(5) var<storage, read_write> render_buffer_32 : RenderBuffer;
-// Group 2 is for things that change multiple times per model
-@group(2) @binding(0) var<storage, read_write> dispatch_next : DispatchIndirectArgs;
-@group(2) @binding(1) var<storage, read> patches_from_buffer : PatchesRead;
-@group(2) @binding(2) var<storage, read_write> patches_to_buffer : Patches;
-@group(2) @binding(3) var<uniform> force_render: ForceRenderFlag;
+@group(1) @binding(6) var<storage, read_write> lod_tree : LodTree;
+@group(1) @binding(7) var<uniform> parameters : Parameters;
+@group(1) @binding(8) var instances : texture_2d<u32>;
+// Files that include this one decide where the patches come from, and where split patches go. They define
+// fn force_render_patch(quad_encoded: EncodedPatch) -> bool
+// fn write_children(children: array<EncodedPatch, 4>, count: u32) -> bool
+
+//// START Parameters
+// Replaced with the `// @param` annotations of the user code
+struct Parameters {
+  unused: u32,
+}
+//// END Parameters
 
 fn triangle_area(a: vec3f, b: vec3f, c: vec3f) -> f32 {
   return 0.5 * length(cross(b - a, c - a));
@@ -189,6 +276,23 @@ var<workgroup> u_lengths: array<array<f32, U_LENGTHS_X>, U_Y>;
 var<workgroup> v_lengths: array<array<f32, U_LENGTHS_X>, U_Y>;
 var<workgroup> frustum_sides: array<u32, 25>;
 
+/// Remembers how a patch was handled, so that the render stage can stitch it to its neighbours
+fn lod_tree_insert(key: EncodedPatch, splits: u32, quads_per_side: u32) {
+  let capacity = arrayLength(&lod_tree.nodes);
+  let hash = lod_node_hash(key);
+  for (var i = 0u; i < LOD_TREE_MAX_PROBES; i += 1u) {
+    let index = (hash + i) % capacity;
+    if (atomicExchange(&lod_tree.nodes[index].occupied, 1u) == 0u) {
+      lod_tree.nodes[index].key = key;
+      lod_tree.nodes[index].splits = splits;
+      lod_tree.nodes[index].quads_per_side = quads_per_side;
+      return;
+    }
+  }
+  // The tree is full. The neighbours of this patch will not be stitched, and the next frame cannot reuse the tree.
+  atomicStore(&lod_tree.overflowed, 1u);
+}
+
 /// Split the patch and write it to the output buffers
 fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length: array<f32, U_Y>) {
   // We use threshold_32, because after that, we don't need to split anymore.
@@ -211,7 +315,7 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
   let patch_bottom_left = patch_bottom_left_child(quad_encoded);
 
   let splits_bitflags = (u32(split_top) << 3) | (u32(split_bottom) << 2) | (u32(split_left) << 1) | u32(split_right);
-  if (splits_bitflags == 0u || force_render.flag != 0u) {
+  if (splits_bitflags == 0u || force_render_patch(quad_encoded)) {
     /* No splits, render the patch
     +---+---+
     |       |
@@ -232,26 +336,31 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
       if (write_index < render_buffer_32.patches_capacity) {
         render_buffer_32.patches[write_index] = quad_encoded;
       }
+      lod_tree_insert(quad_encoded, 0u, 16u);
     } else if (max_u_length > threshold_8.x || max_v_length > threshold_8.y) {
       let write_index = atomicAdd(&render_buffer_16.patches_length, 1u);
       if (write_index < render_buffer_16.patches_capacity) {
         render_buffer_16.patches[write_index] = quad_encoded;
       }
+      lod_tree_insert(quad_encoded, 0u, 8u);
     } else if (max_u_length > threshold_4.x || max_v_length > threshold_4.y) {
       let write_index = atomicAdd(&render_buffer_8.patches_length, 1u);
       if (write_index < render_buffer_8.patches_capacity) {
         render_buffer_8.patches[write_index] = quad_encoded;
       }
+      lod_tree_insert(quad_encoded, 0u, 4u);
     } else if (max_u_length > threshold_2.x || max_v_length > threshold_2.y) {
       let write_index = atomicAdd(&render_buffer_4.patches_length, 1u);
       if (write_index < render_buffer_4.patches_capacity) {
         render_buffer_4.patches[write_index] = quad_encoded;
       }
+      lod_tree_insert(quad_encoded, 0u, 2u);
     } else {
       let write_index = atomicAdd(&render_buffer_2.patches_length, 1u);
       if (write_index < render_buffer_2.patches_capacity) {
         render_buffer_2.patches[write_index] = quad_encoded;
       }
+      lod_tree_insert(quad_encoded, 0u, 1u);
     }
   } else if (splits_bitflags == 8u || splits_bitflags == 4u || splits_bitflags == 12u) {
     /* Split top or split bottom or split top-bottom
@@ -262,11 +371,11 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
     |       |    |   |   |   |   |   |
     +---+---+    +---+---+   +---+---+
     */
-    let write_index = atomicAdd(&patches_to_buffer.patches_length, 2u);
-    if write_index + 2 < patches_to_buffer.patches_capacity {
-      atomicAdd(&dispatch_next.x, 2u);
-      patches_to_buffer.patches[write_index + 0] = patch_left;
-      patches_to_buffer.patches[write_index + 1] = patch_right;
+    if (write_children(array(patch_left, patch_right, quad_encoded, quad_encoded), 2u)) { // Only the first 2 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
     }
   } else if (splits_bitflags == 2u || splits_bitflags == 1u || splits_bitflags == 3u) {
     /* Split left or split right or split left-right
@@ -277,11 +386,11 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
     |       |    |       |   |       |
     +---+---+    +---+---+   +---+---+
     */
-    let write_index = atomicAdd(&patches_to_buffer.patches_length, 2u);
-    if write_index + 2 < patches_to_buffer.patches_capacity {
-      atomicAdd(&dispatch_next.x, 2u);
-      patches_to_buffer.patches[write_index + 0] = patch_top;
-      patches_to_buffer.patches[write_index + 1] = patch_bottom;
+    if (write_children(array(patch_top, patch_bottom, quad_encoded, quad_encoded), 2u)) { // Only the first 2 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
     }
   } else if(splits_bitflags == 14 || splits_bitflags == 10) {
     /* Split top-bottom-left or split top-left
@@ -293,12 +402,12 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
     |   |   |    |       |
     +---+---+    +---+---+
     */
-    let write_index = atomicAdd(&patches_to_buffer.patches_length, 3u);
-    if write_index + 3 < patches_to_buffer.patches_capacity {
-      atomicAdd(&dispatch_next.x, 3u);
-      patches_to_buffer.patches[write_index + 0] = patch_right;
-      patches_to_buffer.patches[write_index + 1] = patch_top_left;
-      patches_to_buffer.patches[write_index + 2] = patch_bottom_left;
+    if (write_children(array(patch_right, patch_top_left, patch_bottom_left, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+      lod_tree_insert(patch_left, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
     }
   } else if(splits_bitflags == 13 || splits_bitflags == 5) {
     /* Split top-bottom-right or split bottom-right
@@ -310,12 +419,12 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
     |   |   |    |   |   |
     +---+---+    +---+---+
     */
-    let write_index = atomicAdd(&patches_to_buffer.patches_length, 3u);
-    if write_index + 3 < patches_to_buffer.patches_capacity {
-      atomicAdd(&dispatch_next.x, 3u);
-      patches_to_buffer.patches[write_index + 0] = patch_left;
-      patches_to_buffer.patches[write_index + 1] = patch_top_right;
-      patches_to_buffer.patches[write_index + 2] = patch_bottom_right;
+    if (write_children(array(patch_left, patch_top_right, patch_bottom_right, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+      lod_tree_insert(patch_right, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
     }
   } else if(splits_bitflags == 11 || splits_bitflags == 9) {
     /* Split top-left-right or split top-right
@@ -327,12 +436,12 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
     |       |    |       |
     +---+---+    +---+---+
     */
-    let write_index = atomicAdd(&patches_to_buffer.patches_length, 3u);
-    if write_index + 3 < patches_to_buffer.patches_capacity {
-      atomicAdd(&dispatch_next.x, 3u);
-      patches_to_buffer.patches[write_index + 0] = patch_top_left;
-      patches_to_buffer.patches[write_index + 1] = patch_top_right;
-      patches_to_buffer.patches[write_index + 2] = patch_bottom;
+    if (write_children(array(patch_top_left, patch_top_right, patch_bottom, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+      lod_tree_insert(patch_top, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
     }
   } else if(splits_bitflags == 7 || splits_bitflags == 6) {
     /* Split bottom-left-right or split bottom-left
@@ -344,12 +453,12 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
     |   |   |    |   |   |
     +---+---+    +---+---+
     */
-    let write_index = atomicAdd(&patches_to_buffer.patches_length, 3u);
-    if write_index + 3 < patches_to_buffer.patches_capacity {
-      atomicAdd(&dispatch_next.x, 3u);
-      patches_to_buffer.patches[write_index + 0] = patch_top;
-      patches_to_buffer.patches[write_index + 1] = patch_bottom_left;
-      patches_to_buffer.patches[write_index + 2] = patch_bottom_right;
+    if (write_children(array(patch_top, patch_bottom_left, patch_bottom_right, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+      lod_tree_insert(patch_bottom, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
     }
   } else if(splits_bitflags == 15) {
     /*
@@ -360,13 +469,11 @@ fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length:
     |   |   |
     +---+---+
     */
-    let write_index = atomicAdd(&patches_to_buffer.patches_length, 4u);
-    if write_index + 4 < patches_to_buffer.patches_capacity {
-      atomicAdd(&dispatch_next.x, 4u);
-      patches_to_buffer.patches[write_index + 0] = patch_top_left;
-      patches_to_buffer.patches[write_index + 1] = patch_top_right;
-      patches_to_buffer.patches[write_index + 2] = patch_bottom_right;
-      patches_to_buffer.patches[write_index + 3] = patch_bottom_left;
+    if (write_children(array(patch_top_left, patch_top_right, patch_bottom_right, patch_bottom_left), 4u)) {
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U | LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
     }
   }
 }
@@ -384,15 +491,10 @@ fn get_frustum_side(point_clip_space: vec4f) -> u32 {
   );
 }
 
-// assume a single work group
-@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
-fn main(@builtin(workgroup_id) workgroup_id : vec3<u32>, 
-        @builtin(local_invocation_id) local_invocation_id : vec3<u32>) {
-  let patch_index: u32 = workgroup_id.x;
-  let sample_index: u32 = local_invocation_id.x; // From 0 to 31 (WORKGROUP_SIZE - 1)
-  assert(patch_index < patches_from_buffer.patches_length); // We dispatch one per patch, so this is always true.
-  let quad_encoded = patches_from_buffer.patches[patch_index];
-  let quad = patch_decode(patches_from_buffer.patches[patch_index]);
+/// Measures a patch on the screen, and then splits or renders it. Needs the whole workgroup.
+/// sample_index goes from 0 to 31 (WORKGROUP_SIZE - 1)
+fn evaluate_patch(quad_encoded: EncodedPatch, sample_index: u32) {
+  let quad = patch_decode(quad_encoded);
   let quad_size = quad.max - quad.min;
 
   // Culling is done by checking if all samples are outside of exactly one of the frustum planes :)
@@ -404,9 +506,12 @@ fn main(@builtin(workgroup_id) workgroup_id : vec3<u32>,
     (quad_size.y / 4.0) * f32(extra_sample_index.y)
   );
   instance_id = quad_encoded.instance;
+  instance = instance_load(instances, instance_id);
+  // The instance transform is part of the culling and the LOD, so that instances can be spread out
+  let model_view_projection = input_buffer.model_view_projection * instance.transform;
   if (sample_index < 25) {
     let extra_sample = sampleObject(extra_sample_location);
-    let extra_clip_space = input_buffer.model_view_projection * vec4f(extra_sample.xyz, 1.0);
+    let extra_clip_space = model_view_projection * vec4f(extra_sample.xyz, 1.0);
     frustum_sides[sample_index] = get_frustum_side(extra_clip_space);
   }
   workgroupBarrier(); // wait for frustum_sides
@@ -419,6 +524,10 @@ fn main(@builtin(workgroup_id) workgroup_id : vec3<u32>,
   }
   // frustum_sides[0] now contains the combined frustum sides for the entire patch
   if (workgroupUniformLoad(&frustum_sides[0]) != 0u) {
+    if (sample_index == 0) {
+      // Remembered, so that the next frame can bring it back when it becomes visible
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
     return; // Skip the entire patch
   }
 
@@ -432,7 +541,7 @@ fn main(@builtin(workgroup_id) workgroup_id : vec3<u32>,
     + (quad_size.y / f32(U_Y)) * f32(u_v_sample_index.y)
   );
   let u_sample = sampleObject(u_sample_location);
-  let u_clip_space = input_buffer.model_view_projection * vec4f(u_sample.xyz, 1.0);
+  let u_clip_space = model_view_projection * vec4f(u_sample.xyz, 1.0);
   let u_screen_space = u_clip_space.xy / u_clip_space.w;
   u_samples[u_v_sample_index.y][u_v_sample_index.x] = u_screen_space;
 
@@ -443,7 +552,7 @@ fn main(@builtin(workgroup_id) workgroup_id : vec3<u32>,
     (quad_size.y / f32(U_X - 1)) * f32(u_v_sample_index.x),
   );
   let v_sample = sampleObject(v_sample_location);
-  let v_clip_space = input_buffer.model_view_projection * vec4f(v_sample.xyz, 1.0);
+  let v_clip_space = model_view_projection * vec4f(v_sample.xyz, 1.0);
   let v_screen_space = v_clip_space.xy / v_clip_space.w;
   v_samples[u_v_sample_index.y][u_v_sample_index.x] = v_screen_space;
 
@@ -479,4 +588,39 @@ fn main(@builtin(workgroup_id) workgroup_id : vec3<u32>,
 
   // Warning regarding storage barrier:
   // https://stackoverflow.com/questions/72035548/what-does-storagebarrier-in-webgpu-actually-do
+}
+//// END OF AUTOGEN
+
+struct ForceRenderFlag {
+  flag: u32 // if flag == 0 { false } else { true }
+}
+
+// Group 2 is for things that change multiple times per model
+@group(2) @binding(0) var<storage, read> patches_from_buffer : PatchesRead;
+@group(2) @binding(1) var<storage, read_write> patches_to_buffer : Patches;
+@group(2) @binding(2) var<uniform> force_render: ForceRenderFlag;
+
+fn force_render_patch(quad_encoded: EncodedPatch) -> bool {
+  return force_render.flag != 0u;
+}
+
+fn write_children(children: array<EncodedPatch, 4>, count: u32) -> bool {
+  let write_index = atomicAdd(&patches_to_buffer.patches_length, count);
+  if write_index + count < patches_to_buffer.patches_capacity {
+    atomicAdd(&patches_to_buffer.dispatch_x, count);
+    for (var i = 0u; i < count; i += 1u) {
+      patches_to_buffer.patches[write_index + i] = children[i];
+    }
+    return true;
+  }
+  return false;
+}
+
+// One workgroup per patch
+@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
+fn main(@builtin(workgroup_id) workgroup_id : vec3<u32>, 
+        @builtin(local_invocation_id) local_invocation_id : vec3<u32>) {
+  let patch_index: u32 = workgroup_id.x;
+  assert(patch_index < patches_from_buffer.patches_length); // We dispatch one per patch, so this is always true.
+  evaluate_patch(patches_from_buffer.patches[patch_index], local_invocation_id.x);
 }
\ No newline at end of file
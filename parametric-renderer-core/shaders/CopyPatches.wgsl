@@ -1,5 +1,5 @@
 ////#include "./Common.wgsl"
-//// AUTOGEN 6de14edf9918265eb2e1232f93b94c84430d0069898214379635e51c3d4c9550
+//// AUTOGEN d5bc2f7e981d9972b3c852bb1248465b910ae2d693454f5ffbc068cff688e21f
 struct EncodedPatch {
   u: u32,
   v: u32,
@@ -11,11 +11,19 @@ struct Patch {
   instance: u32
 };
 struct Patches {
+  // Indirect dispatch arguments for the next round, one workgroup per patch.
+  // They live in the same buffer to save a storage buffer binding.
+  dispatch_x: atomic<u32>,
+  dispatch_y: u32,
+  dispatch_z: u32,
   patches_length: atomic<u32>,
   patches_capacity: u32,
   patches : array<EncodedPatch>,
 };
 struct PatchesRead { // Is currently needed, see https://github.com/gpuweb/gpuweb/discussions/4438
+  dispatch_x: u32,
+  dispatch_y: u32,
+  dispatch_z: u32,
   patches_length: u32, // Same size and alignment as atomic<u32>. Should be legal, right?
   patches_capacity: u32,
   patches : array<EncodedPatch>,
@@ -30,11 +38,84 @@ struct RenderBufferRead {
   patches_capacity: u32,
   patches: array<EncodedPatch>,
 };
-struct DispatchIndirectArgs { // From https://docs.rs/wgpu/latest/wgpu/util/struct.DispatchIndirectArgs.html
-  x: atomic<u32>,
-  y: u32,
-  z: u32,
+// The data of one instance of a model, indexed by the instance of a patch
+struct Instance {
+  // Applied after sampleObject, relative to the model
+  transform: mat4x4<f32>,
+  normal_matrix: mat3x3<f32>,
+  // Multiplies the result of getColor
+  tint: vec3<f32>,
+  // Free for the user code
+  custom: vec4<f32>,
 };
+// Instances are stored in a rgba32uint texture, since the LOD stage has no storage buffer binding left.
+// Every instance takes up INSTANCE_TEXELS texels in the layout of a storage buffer, and a row has INSTANCES_PER_ROW of them.
+const INSTANCE_TEXELS: u32 = 9u;
+const INSTANCES_PER_ROW: u32 = 512u;
+fn instance_texel(instances: texture_2d<u32>, id: u32, texel: u32) -> vec4f {
+  let x = (id % INSTANCES_PER_ROW) * INSTANCE_TEXELS + texel;
+  return bitcast<vec4f>(textureLoad(instances, vec2(x, id / INSTANCES_PER_ROW), 0));
+}
+fn instance_load(instances: texture_2d<u32>, id: u32) -> Instance {
+  return Instance(
+    mat4x4(
+      instance_texel(instances, id, 0u),
+      instance_texel(instances, id, 1u),
+      instance_texel(instances, id, 2u),
+      instance_texel(instances, id, 3u)
+    ),
+    mat3x3(
+      instance_texel(instances, id, 4u).xyz,
+      instance_texel(instances, id, 5u).xyz,
+      instance_texel(instances, id, 6u).xyz
+    ),
+    instance_texel(instances, id, 7u).xyz,
+    instance_texel(instances, id, 8u)
+  );
+}
+// A hash map of the subdivision tree, so that the render stage can find the neighbours of a patch.
+// Every split, every rendered patch and every culled patch gets a node.
+struct LodNode {
+  // Set to 1 by whoever claims the slot
+  occupied: atomic<u32>,
+  key: EncodedPatch,
+  // LOD_SPLIT_U and LOD_SPLIT_V flags, 0 for a patch that got rendered
+  splits: u32,
+  // How many quads a rendered patch has along one side, 0 for a culled patch
+  quads_per_side: u32,
+};
+struct LodTree {
+  // Set when a node did not fit, which means that the tree is incomplete
+  overflowed: atomic<u32>,
+  nodes: array<LodNode>,
+};
+struct LodNodeRead {
+  occupied: u32,
+  key: EncodedPatch,
+  splits: u32,
+  quads_per_side: u32,
+};
+struct LodTreeRead {
+  overflowed: u32,
+  nodes: array<LodNodeRead>,
+};
+const LOD_SPLIT_U: u32 = 1u;
+const LOD_SPLIT_V: u32 = 2u;
+// Linear probing gives up after this many slots
+const LOD_TREE_MAX_PROBES: u32 = 64u;
+fn lod_node_hash(key: EncodedPatch) -> u32 {
+  // Integer hash from https://nullprogram.com/blog/2018/07/31/
+  var hash = (key.u * 0x9e3779b1u) ^ (key.v * 0x85ebca77u) ^ (key.instance * 0xc2b2ae3du);
+  hash ^= hash >> 16u;
+  hash *= 0x7feb352du;
+  hash ^= hash >> 15u;
+  hash *= 0x846ca68bu;
+  hash ^= hash >> 16u;
+  return hash;
+}
+fn lod_node_is(node_key: EncodedPatch, key: EncodedPatch) -> bool {
+  return node_key.u == key.u && node_key.v == key.v && node_key.instance == key.instance;
+}
 fn ceil_div(a: u32, b: u32) -> u32 { return (a + b - 1u) / b; }
 // Inspired from https://onrendering.com/data/papers/isubd/isubd.pdf
 fn patch_u_child(u: u32, child_bit: u32) -> u32 {
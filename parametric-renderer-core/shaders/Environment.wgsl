@@ -0,0 +1,170 @@
+// Precomputes the image based lighting maps from an equirectangular HDR image.
+// See https://learnopengl.com/PBR/IBL/Diffuse-irradiance and https://learnopengl.com/PBR/IBL/Specular-IBL
+
+struct BakeParams {
+    // Roughness of the prefiltered mip level
+    roughness: f32,
+    // Scales the environment, like the strength of a world in Blender
+    intensity: f32,
+    sample_count: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: BakeParams;
+@group(0) @binding(1) var equirectangular: texture_2d<f32>;
+@group(0) @binding(2) var environment: texture_cube<f32>;
+@group(0) @binding(3) var environment_sampler: sampler;
+@group(0) @binding(4) var output: texture_storage_2d_array<rgba16float, write>;
+
+const TWO_PI = 6.28318530718;
+
+/// Direction of a texel of a cubemap face. Follows the WebGPU cubemap conventions.
+fn cube_direction(face: u32, texel: vec2u, size: vec2u) -> vec3f {
+    let st = (vec2f(texel) + vec2f(0.5)) / vec2f(size) * 2.0 - vec2f(1.0);
+    var direction: vec3f;
+    switch face {
+        case 0u: { direction = vec3f(1.0, -st.y, -st.x); }
+        case 1u: { direction = vec3f(-1.0, -st.y, st.x); }
+        case 2u: { direction = vec3f(st.x, 1.0, st.y); }
+        case 3u: { direction = vec3f(st.x, -1.0, -st.y); }
+        case 4u: { direction = vec3f(st.x, -st.y, 1.0); }
+        default: { direction = vec3f(-st.x, -st.y, -1.0); }
+    }
+    return normalize(direction);
+}
+
+/// Inverse of the direction computed in environment.rs
+fn equirectangular_uv(direction: vec3f) -> vec2f {
+    const PI = 3.14159265359;
+    let u = atan2(direction.z, direction.x) / TWO_PI + 0.5;
+    let v = acos(clamp(direction.y, -1.0, 1.0)) / PI;
+    return vec2f(u, v);
+}
+
+fn tangent_to_world(tangent_space: vec3f, n: vec3f) -> vec3f {
+    var up = vec3f(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3f(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return tangent * tangent_space.x + bitangent * tangent_space.y + n * tangent_space.z;
+}
+
+fn radical_inverse_vdc(bits_in: u32) -> f32 {
+    var bits = bits_in;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2f {
+    return vec2f(f32(i) / f32(count), radical_inverse_vdc(i));
+}
+
+/// GGX importance sampling, returns a halfway vector around n
+fn importance_sample_ggx(xi: vec2f, n: vec3f, roughness: f32) -> vec3f {
+    let a = roughness * roughness;
+    let phi = TWO_PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return tangent_to_world(vec3f(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+}
+
+fn is_outside(id: vec3u) -> bool {
+    let size = textureDimensions(output);
+    return id.x >= size.x || id.y >= size.y;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn equirectangular_to_cube(@builtin(global_invocation_id) id: vec3u) {
+    if (is_outside(id)) {
+        return;
+    }
+    let direction = cube_direction(id.z, id.xy, textureDimensions(output));
+    let color = textureSampleLevel(equirectangular, environment_sampler, equirectangular_uv(direction), 0.0).rgb;
+    textureStore(output, id.xy, id.z, vec4f(color * params.intensity, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3u) {
+    if (is_outside(id)) {
+        return;
+    }
+    const PI = 3.14159265359;
+    let n = cube_direction(id.z, id.xy, textureDimensions(output));
+    let sample_delta = 0.05;
+    var irradiance = vec3f(0.0);
+    var sample_count = 0.0;
+    for (var phi = 0.0; phi < TWO_PI; phi += sample_delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += sample_delta) {
+            let tangent_sample = vec3f(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let direction = tangent_to_world(tangent_sample, n);
+            irradiance += textureSampleLevel(environment, environment_sampler, direction, 0.0).rgb * cos(theta) * sin(theta);
+            sample_count += 1.0;
+        }
+    }
+    irradiance = PI * irradiance / sample_count;
+    textureStore(output, id.xy, id.z, vec4f(irradiance, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3u) {
+    if (is_outside(id)) {
+        return;
+    }
+    // Assume that the view direction is the normal
+    let n = cube_direction(id.z, id.xy, textureDimensions(output));
+    let v = n;
+    var color = vec3f(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), n, params.roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            color += textureSampleLevel(environment, environment_sampler, l, 0.0).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+    textureStore(output, id.xy, id.z, vec4f(color / max(total_weight, 0.0001), 1.0));
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    // Note the different k for IBL
+    let k = (roughness * roughness) / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3u) {
+    if (is_outside(id)) {
+        return;
+    }
+    let size = textureDimensions(output);
+    let n_dot_v = (f32(id.x) + 0.5) / f32(size.x);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+    let v = vec3f(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3f(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.sample_count; i += 1u) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if (n_dot_l > 0.0) {
+            let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let g_vis = (g * v_dot_h) / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    let count = f32(params.sample_count);
+    textureStore(output, id.xy, 0, vec4f(scale / count, bias / count, 0.0, 1.0));
+}
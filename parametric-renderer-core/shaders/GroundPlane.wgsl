@@ -4,6 +4,61 @@ struct Uniforms {
     grid_scale: f32
 }
 @binding(0) @group(0) var<uniform> uniforms: Uniforms;
+@binding(1) @group(0) var<uniform> shadows: Shadows;
+@binding(2) @group(0) var shadow_map: texture_depth_2d_array;
+@binding(3) @group(0) var shadow_sampler: sampler_comparison;
+
+////#include "./Shadows.wgsl"
+//// AUTOGEN 2b503b1b36c712c3c9f4af888b9d49b9d1cbb93498b26b23a8f0ef40b93f4943
+struct ShadowCascade {
+  view_projection: mat4x4<f32>,
+  // Size of one shadow map texel in world space, used for the normal offset
+  texel_world_size: f32,
+}
+struct Shadows {
+  cascades: array<ShadowCascade, 3>,
+  // Index into the lights, or NO_SHADOW_LIGHT
+  light_index: u32,
+  // 1.0 / shadow map resolution
+  texel_size: f32,
+}
+const SHADOW_CASCADE_COUNT: u32 = 3;
+const NO_SHADOW_LIGHT: u32 = 0xffffffffu;
+// Reverse Z, so a positive bias moves the surface towards the light
+const SHADOW_DEPTH_BIAS: f32 = 0.0005;
+
+/// 3x3 percentage closer filtering. Each tap is already bilinearly filtered by the comparison sampler.
+fn shadow_pcf(shadow_map: texture_depth_2d_array, shadow_sampler: sampler_comparison, uv: vec2f, cascade: u32, depth: f32, texel_size: f32) -> f32 {
+  var lit = 0.0;
+  for (var y = -1; y <= 1; y += 1) {
+    for (var x = -1; x <= 1; x += 1) {
+      let offset = vec2f(f32(x), f32(y)) * texel_size;
+      lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, depth);
+    }
+  }
+  return lit / 9.0;
+}
+
+/// Returns 1.0 for a fully lit point and 0.0 for a fully shadowed point.
+/// Picks the first cascade that contains the point.
+fn sample_shadow(shadow_map: texture_depth_2d_array, shadow_sampler: sampler_comparison, shadows: Shadows, world_position: vec3f, normal: vec3f) -> f32 {
+  if (shadows.light_index == NO_SHADOW_LIGHT) {
+    return 1.0;
+  }
+  var cascades = shadows.cascades;
+  for (var i = 0u; i < SHADOW_CASCADE_COUNT; i += 1u) {
+    let offset_position = world_position + normal * (cascades[i].texel_world_size * 1.5);
+    let clip_position = cascades[i].view_projection * vec4f(offset_position, 1.0);
+    let ndc = clip_position.xyz / clip_position.w;
+    let uv = ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5);
+    if (all(uv >= vec2f(0.0)) && all(uv <= vec2f(1.0)) && ndc.z >= 0.0 && ndc.z <= 1.0) {
+      return shadow_pcf(shadow_map, shadow_sampler, uv, i, ndc.z + SHADOW_DEPTH_BIAS, shadows.texel_size);
+    }
+  }
+  return 1.0;
+}
+
+//// END OF AUTOGEN
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -11,7 +66,8 @@ struct VertexInput {
 }
 struct VertexOutput {
     @builtin(position) pos: vec4f,
-    @location(0) uv: vec2f
+    @location(0) uv: vec2f,
+    @location(1) world_position: vec3f,
 }
 
 // TODO: Remove this code duplication
@@ -25,9 +81,11 @@ struct FragmentOutput {
 fn vs_main(
     in: VertexInput,
 ) -> VertexOutput {
+    let world_position = uniforms.model_matrix * vec4f(in.position, 1.0);
     var output: VertexOutput;
-    output.pos = uniforms.view_projection_matrix * uniforms.model_matrix * vec4f(in.position, 1.0);
-    output.uv = (uniforms.model_matrix * vec4f(in.position, 1.0)).xz;
+    output.pos = uniforms.view_projection_matrix * world_position;
+    output.uv = world_position.xz;
+    output.world_position = world_position.xyz;
     return output;
 }
 
@@ -55,9 +113,13 @@ fn grid_to_color(grid: f32) -> f32 {
     return pow(color, 1.0 / 2.2); // Gamma correction helps a lot
 }
 
+// How dark a fully shadowed part of the ground plane gets
+const SHADOW_STRENGTH = 0.5;
+
 @fragment
 fn fs_main(
     @location(0) uv: vec2f,
+    @location(1) world_position: vec3f,
     @builtin(position) pos: vec4f
 ) -> FragmentOutput {
     let coord = uv.xy * uniforms.grid_scale;
@@ -71,8 +133,9 @@ fn fs_main(
     let fade_factor = fade_from_center(coord);
 
     let gradient = vec4f(vec3f(1.0), light_gradient(coord));
+    let grid = gradient + vec4f(vec3f(1.0), color * fade_factor);
+    let shadow = (1.0 - sample_shadow(shadow_map, shadow_sampler, shadows, world_position, vec3f(0.0, 1.0, 0.0))) * SHADOW_STRENGTH;
     var output: FragmentOutput;
-    output.color = gradient + vec4f(vec3f(1.0), color * fade_factor);
+    output.color = vec4f(grid.rgb * (1.0 - shadow), max(grid.a, shadow));
     return output;
 }
-
@@ -0,0 +1,361 @@
+struct InputBuffer {
+    threshold_factor: f32,
+    model_view_projection: mat4x4<f32>,
+};
+
+// Group 1 is for things that change once per model
+// TODO: Read back the patches_length on the CPU to know when we're going out of bounds
+// (And how far out of bounds)
+@group(1) @binding(0) var<uniform> input_buffer : InputBuffer;
+@group(1) @binding(1) var<storage, read_write> render_buffer_2 : RenderBuffer;
+@group(1) @binding(2) var<storage, read_write> render_buffer_4 : RenderBuffer;
+@group(1) @binding(3) var<storage, read_write> render_buffer_8 : RenderBuffer;
+@group(1) @binding(4) var<storage, read_write> render_buffer_16 : RenderBuffer;
+@group(1) @binding(5) var<storage, read_write> render_buffer_32 : RenderBuffer;
+@group(1) @binding(6) var<storage, read_write> lod_tree : LodTree;
+@group(1) @binding(7) var<uniform> parameters : Parameters;
+@group(1) @binding(8) var instances : texture_2d<u32>;
+// Files that include this one decide where the patches come from, and where split patches go. They define
+// fn force_render_patch(quad_encoded: EncodedPatch) -> bool
+// fn write_children(children: array<EncodedPatch, 4>, count: u32) -> bool
+
+//// START Parameters
+// Replaced with the `// @param` annotations of the user code
+struct Parameters {
+  unused: u32,
+}
+//// END Parameters
+
+fn triangle_area(a: vec3f, b: vec3f, c: vec3f) -> f32 {
+  return 0.5 * length(cross(b - a, c - a));
+}
+
+// 8 samples in the X direction
+const U_X = 8u;
+// and repeat that 4 times
+const U_Y = 4u;
+const WORKGROUP_SIZE = U_X * U_Y;
+
+// A vec2 with screen space coordinates
+alias vec2Screen = vec2<f32>;
+
+var<workgroup> u_samples: array<array<vec2Screen, U_X>, U_Y>;
+var<workgroup> v_samples: array<array<vec2Screen, U_X>, U_Y>;
+const U_LENGTHS_X = U_X - 1; // Last sample per row doesn't have a next sample
+var<workgroup> u_lengths: array<array<f32, U_LENGTHS_X>, U_Y>;
+var<workgroup> v_lengths: array<array<f32, U_LENGTHS_X>, U_Y>;
+var<workgroup> frustum_sides: array<u32, 25>;
+
+/// Remembers how a patch was handled, so that the render stage can stitch it to its neighbours
+fn lod_tree_insert(key: EncodedPatch, splits: u32, quads_per_side: u32) {
+  let capacity = arrayLength(&lod_tree.nodes);
+  let hash = lod_node_hash(key);
+  for (var i = 0u; i < LOD_TREE_MAX_PROBES; i += 1u) {
+    let index = (hash + i) % capacity;
+    if (atomicExchange(&lod_tree.nodes[index].occupied, 1u) == 0u) {
+      lod_tree.nodes[index].key = key;
+      lod_tree.nodes[index].splits = splits;
+      lod_tree.nodes[index].quads_per_side = quads_per_side;
+      return;
+    }
+  }
+  // The tree is full. The neighbours of this patch will not be stitched, and the next frame cannot reuse the tree.
+  atomicStore(&lod_tree.overflowed, 1u);
+}
+
+/// Split the patch and write it to the output buffers
+fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length: array<f32, U_Y>) {
+  // We use threshold_32, because after that, we don't need to split anymore.
+  // Instead, we need to compute the correct render buffer to write to.
+  let threshold_32 = (32.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+
+  let split_top = u_length[0] > threshold_32.x || u_length[1] > threshold_32.x;
+  let split_bottom = u_length[2] > threshold_32.x || u_length[3] > threshold_32.x;
+  let split_left = v_length[0] > threshold_32.y || v_length[1] > threshold_32.y;
+  let split_right = v_length[2] > threshold_32.y || v_length[3] > threshold_32.y;
+
+  let patch_top = patch_top_child(quad_encoded);
+  let patch_bottom = patch_bottom_child(quad_encoded);
+  let patch_left = patch_left_child(quad_encoded);
+  let patch_right = patch_right_child(quad_encoded);
+
+  let patch_top_left = patch_top_left_child(quad_encoded);
+  let patch_top_right = patch_top_right_child(quad_encoded);
+  let patch_bottom_right = patch_bottom_right_child(quad_encoded);
+  let patch_bottom_left = patch_bottom_left_child(quad_encoded);
+
+  let splits_bitflags = (u32(split_top) << 3) | (u32(split_bottom) << 2) | (u32(split_left) << 1) | u32(split_right);
+  if (splits_bitflags == 0u || force_render_patch(quad_encoded)) {
+    /* No splits, render the patch
+    +---+---+
+    |       |
+    +       +
+    |       |
+    +---+---+
+    */
+    let max_u_length = max(max(u_length[0], u_length[1]), max(u_length[2], u_length[3]));
+    let max_v_length = max(max(v_length[0], v_length[1]), max(v_length[2], v_length[3]));
+
+    let threshold_16 = (16.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+    let threshold_8 = (8.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+    let threshold_4 = (4.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+    let threshold_2 = (2.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+
+    if (max_u_length > threshold_16.x || max_v_length > threshold_16.y) {
+      let write_index = atomicAdd(&render_buffer_32.patches_length, 1u);
+      if (write_index < render_buffer_32.patches_capacity) {
+        render_buffer_32.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 16u);
+    } else if (max_u_length > threshold_8.x || max_v_length > threshold_8.y) {
+      let write_index = atomicAdd(&render_buffer_16.patches_length, 1u);
+      if (write_index < render_buffer_16.patches_capacity) {
+        render_buffer_16.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 8u);
+    } else if (max_u_length > threshold_4.x || max_v_length > threshold_4.y) {
+      let write_index = atomicAdd(&render_buffer_8.patches_length, 1u);
+      if (write_index < render_buffer_8.patches_capacity) {
+        render_buffer_8.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 4u);
+    } else if (max_u_length > threshold_2.x || max_v_length > threshold_2.y) {
+      let write_index = atomicAdd(&render_buffer_4.patches_length, 1u);
+      if (write_index < render_buffer_4.patches_capacity) {
+        render_buffer_4.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 2u);
+    } else {
+      let write_index = atomicAdd(&render_buffer_2.patches_length, 1u);
+      if (write_index < render_buffer_2.patches_capacity) {
+        render_buffer_2.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 1u);
+    }
+  } else if (splits_bitflags == 8u || splits_bitflags == 4u || splits_bitflags == 12u) {
+    /* Split top or split bottom or split top-bottom
+    => Split along the U axis
+    +---+---+    +---+---+   +---+---+
+    |   |   |    |       |   |   |   |
+    +       +    +       +   +   |   +
+    |       |    |   |   |   |   |   |
+    +---+---+    +---+---+   +---+---+
+    */
+    if (write_children(array(patch_left, patch_right, quad_encoded, quad_encoded), 2u)) { // Only the first 2 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if (splits_bitflags == 2u || splits_bitflags == 1u || splits_bitflags == 3u) {
+    /* Split left or split right or split left-right
+    => Split along the V axis
+    +---+---+    +---+---+   +---+---+
+    |       |    |       |   |       |
+    +---    +    +    ---+   +-------+
+    |       |    |       |   |       |
+    +---+---+    +---+---+   +---+---+
+    */
+    if (write_children(array(patch_top, patch_bottom, quad_encoded, quad_encoded), 2u)) { // Only the first 2 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 14 || splits_bitflags == 10) {
+    /* Split top-bottom-left or split top-left
+    => T-split
+    => Ambiguous T-split (1110 or 1011)
+    +---+---+    +---+---+
+    |   |   |    |   |   |
+    +---+   +    +---+   +
+    |   |   |    |       |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_right, patch_top_left, patch_bottom_left, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+      lod_tree_insert(patch_left, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 13 || splits_bitflags == 5) {
+    /* Split top-bottom-right or split bottom-right
+    => T-split
+    => Ambiguous T-split (1101 or 0111)
+    +---+---+    +---+---+
+    |   |   |    |       |
+    +   +---+    +   +---+
+    |   |   |    |   |   |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_left, patch_top_right, patch_bottom_right, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+      lod_tree_insert(patch_right, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 11 || splits_bitflags == 9) {
+    /* Split top-left-right or split top-right
+    => T-split
+    => Ambiguous T-split (1101 or 1011)
+    +---+---+    +---+---+
+    |   |   |    |   |   |
+    +---+---+    +   +---+
+    |       |    |       |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_top_left, patch_top_right, patch_bottom, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+      lod_tree_insert(patch_top, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 7 || splits_bitflags == 6) {
+    /* Split bottom-left-right or split bottom-left
+    => T-split
+    => Ambiguous T-split (1110 or 0111)
+    +---+---+    +---+---+
+    |       |    |       |
+    +---+---+    +---+   +
+    |   |   |    |   |   |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_top, patch_bottom_left, patch_bottom_right, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+      lod_tree_insert(patch_bottom, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 15) {
+    /*
+    Split all 4 ways
+    +---+---+
+    |   |   |
+    +---+---+
+    |   |   |
+    +---+---+
+    */
+    if (write_children(array(patch_top_left, patch_top_right, patch_bottom_right, patch_bottom_left), 4u)) {
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U | LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  }
+}
+
+/// Gets a bitflag for the frustum sides of a point in clip space. 6 bits are used, 1 for each side.
+/// Based on the equations in https://carmencincotti.com/2022-05-02/homogeneous-coordinates-clip-space-ndc/#clip-space
+fn get_frustum_side(point_clip_space: vec4f) -> u32 {
+  return u32(
+    (u32(point_clip_space.x < -point_clip_space.w) << 5u) |
+    (u32(point_clip_space.x >  point_clip_space.w) << 4u) |
+    (u32(point_clip_space.y < -point_clip_space.w) << 3u) |
+    (u32(point_clip_space.y >  point_clip_space.w) << 2u) |
+    (u32(point_clip_space.z < -point_clip_space.w) << 1u) |
+    (u32(point_clip_space.z >  point_clip_space.w) << 0u)
+  );
+}
+
+/// Measures a patch on the screen, and then splits or renders it. Needs the whole workgroup.
+/// sample_index goes from 0 to 31 (WORKGROUP_SIZE - 1)
+fn evaluate_patch(quad_encoded: EncodedPatch, sample_index: u32) {
+  let quad = patch_decode(quad_encoded);
+  let quad_size = quad.max - quad.min;
+
+  // Culling is done by checking if all samples are outside of exactly one of the frustum planes :)
+  // 5*5 = 25 extra samples for frustum culling
+  let extra_sample_index = vec2<u32>(sample_index % 5u, sample_index / 5u);
+  let extra_sample_location = quad.min + vec2(
+    // Divide by 4.0 because we have 5 samples, but we want to go from 0 to 1
+    (quad_size.x / 4.0) * f32(extra_sample_index.x),
+    (quad_size.y / 4.0) * f32(extra_sample_index.y)
+  );
+  instance_id = quad_encoded.instance;
+  instance = instance_load(instances, instance_id);
+  // The instance transform is part of the culling and the LOD, so that instances can be spread out
+  let model_view_projection = input_buffer.model_view_projection * instance.transform;
+  if (sample_index < 25) {
+    let extra_sample = sampleObject(extra_sample_location);
+    let extra_clip_space = model_view_projection * vec4f(extra_sample.xyz, 1.0);
+    frustum_sides[sample_index] = get_frustum_side(extra_clip_space);
+  }
+  workgroupBarrier(); // wait for frustum_sides
+  // Now parallel combine the frustum sides
+  for (var i: u32 = 16u; i > 0u; i >>= 1u) {
+    if (sample_index < i && sample_index + i < 25u) {
+      frustum_sides[sample_index] &= frustum_sides[sample_index + i];
+    }
+    workgroupBarrier();
+  }
+  // frustum_sides[0] now contains the combined frustum sides for the entire patch
+  if (workgroupUniformLoad(&frustum_sides[0]) != 0u) {
+    if (sample_index == 0) {
+      // Remembered, so that the next frame can bring it back when it becomes visible
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+    return; // Skip the entire patch
+  }
+
+  let u_v_sample_index = vec2<u32>(sample_index % U_X, sample_index / U_X);
+  
+  // 4*8 = 32 U samples
+  let u_sample_location = quad.min + vec2(
+    // 8 samples divide a quad into 7 parts
+    (quad_size.x / f32(U_X - 1)) * f32(u_v_sample_index.x),
+    (quad_size.y / f32(U_Y) / 2.0) // top offset
+    + (quad_size.y / f32(U_Y)) * f32(u_v_sample_index.y)
+  );
+  let u_sample = sampleObject(u_sample_location);
+  let u_clip_space = model_view_projection * vec4f(u_sample.xyz, 1.0);
+  let u_screen_space = u_clip_space.xy / u_clip_space.w;
+  u_samples[u_v_sample_index.y][u_v_sample_index.x] = u_screen_space;
+
+  // 4*8 = 32 V samples
+  let v_sample_location = quad.min + vec2(
+    (quad_size.x / f32(U_Y) / 2.0) // left offset
+    + (quad_size.x / f32(U_Y)) * f32(u_v_sample_index.y),
+    (quad_size.y / f32(U_X - 1)) * f32(u_v_sample_index.x),
+  );
+  let v_sample = sampleObject(v_sample_location);
+  let v_clip_space = model_view_projection * vec4f(v_sample.xyz, 1.0);
+  let v_screen_space = v_clip_space.xy / v_clip_space.w;
+  v_samples[u_v_sample_index.y][u_v_sample_index.x] = v_screen_space;
+
+
+  workgroupBarrier(); // wait for u_samples and v_samples
+  if (u_v_sample_index.x < U_X - 1) {
+    let u_length = distance(u_samples[u_v_sample_index.y][u_v_sample_index.x], u_samples[u_v_sample_index.y][u_v_sample_index.x + 1]);
+    u_lengths[u_v_sample_index.y][u_v_sample_index.x] = u_length;
+    // v might go in a different direction, but the array layout is the same
+    let v_length = distance(v_samples[u_v_sample_index.y][u_v_sample_index.x], v_samples[u_v_sample_index.y][u_v_sample_index.x + 1]);
+    v_lengths[u_v_sample_index.y][u_v_sample_index.x] = v_length;
+  }
+  workgroupBarrier(); // wait for u_lengths and v_lengths
+
+  // TODO: Test if this is faster with barriers instead
+  let u_length = array<f32, U_Y>(
+    u_lengths[0][0] + u_lengths[0][1] + u_lengths[0][2] + u_lengths[0][3] + u_lengths[0][4] + u_lengths[0][5] + u_lengths[0][6],
+    u_lengths[1][0] + u_lengths[1][1] + u_lengths[1][2] + u_lengths[1][3] + u_lengths[1][4] + u_lengths[1][5] + u_lengths[1][6],
+    u_lengths[2][0] + u_lengths[2][1] + u_lengths[2][2] + u_lengths[2][3] + u_lengths[2][4] + u_lengths[2][5] + u_lengths[2][6],
+    u_lengths[3][0] + u_lengths[3][1] + u_lengths[3][2] + u_lengths[3][3] + u_lengths[3][4] + u_lengths[3][5] + u_lengths[3][6]
+  );
+  let v_length = array<f32, U_Y>(
+    v_lengths[0][0] + v_lengths[0][1] + v_lengths[0][2] + v_lengths[0][3] + v_lengths[0][4] + v_lengths[0][5] + v_lengths[0][6],
+    v_lengths[1][0] + v_lengths[1][1] + v_lengths[1][2] + v_lengths[1][3] + v_lengths[1][4] + v_lengths[1][5] + v_lengths[1][6],
+    v_lengths[2][0] + v_lengths[2][1] + v_lengths[2][2] + v_lengths[2][3] + v_lengths[2][4] + v_lengths[2][5] + v_lengths[2][6],
+    v_lengths[3][0] + v_lengths[3][1] + v_lengths[3][2] + v_lengths[3][3] + v_lengths[3][4] + v_lengths[3][5] + v_lengths[3][6]
+  );
+
+  if(sample_index == 0) {
+    split_patch(quad_encoded, u_length, v_length);
+  }
+
+
+  // Warning regarding storage barrier:
+  // https://stackoverflow.com/questions/72035548/what-does-storagebarrier-in-webgpu-actually-do
+}
\ No newline at end of file
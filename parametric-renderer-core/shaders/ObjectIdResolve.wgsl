@@ -0,0 +1,15 @@
+// Integer textures cannot be resolved, so the object ID of the first sample is copied instead
+
+@group(0) @binding(0) var source: texture_multisampled_2d<u32>;
+
+// A single triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4f {
+  let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+  return vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4f) -> @location(0) u32 {
+  return textureLoad(source, vec2u(position.xy), 0).x;
+}
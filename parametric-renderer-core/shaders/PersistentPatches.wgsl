@@ -0,0 +1,721 @@
+//// START sampleObject
+fn sampleObject(input: vec2f) -> vec3f {
+  let a = time;
+  let b = screen;
+  let c = mouse;
+  let d = extra;
+  return vec3(input, 0.0); 
+}
+//// END sampleObject
+var<private> instance_id: u32;
+var<private> instance: Instance;
+
+////#include "./Common.wgsl"
+//// AUTOGEN d5bc2f7e981d9972b3c852bb1248465b910ae2d693454f5ffbc068cff688e21f
+struct EncodedPatch {
+  u: u32,
+  v: u32,
+  instance: u32
+};
+struct Patch {
+  min: vec2<f32>,
+  max: vec2<f32>,
+  instance: u32
+};
+struct Patches {
+  // Indirect dispatch arguments for the next round, one workgroup per patch.
+  // They live in the same buffer to save a storage buffer binding.
+  dispatch_x: atomic<u32>,
+  dispatch_y: u32,
+  dispatch_z: u32,
+  patches_length: atomic<u32>,
+  patches_capacity: u32,
+  patches : array<EncodedPatch>,
+};
+struct PatchesRead { // Is currently needed, see https://github.com/gpuweb/gpuweb/discussions/4438
+  dispatch_x: u32,
+  dispatch_y: u32,
+  dispatch_z: u32,
+  patches_length: u32, // Same size and alignment as atomic<u32>. Should be legal, right?
+  patches_capacity: u32,
+  patches : array<EncodedPatch>,
+};
+struct RenderBuffer {
+  patches_length: atomic<u32>,
+  patches_capacity: u32,
+  patches: array<EncodedPatch>,
+};
+struct RenderBufferRead {
+  patches_length: u32,
+  patches_capacity: u32,
+  patches: array<EncodedPatch>,
+};
+// The data of one instance of a model, indexed by the instance of a patch
+struct Instance {
+  // Applied after sampleObject, relative to the model
+  transform: mat4x4<f32>,
+  normal_matrix: mat3x3<f32>,
+  // Multiplies the result of getColor
+  tint: vec3<f32>,
+  // Free for the user code
+  custom: vec4<f32>,
+};
+// Instances are stored in a rgba32uint texture, since the LOD stage has no storage buffer binding left.
+// Every instance takes up INSTANCE_TEXELS texels in the layout of a storage buffer, and a row has INSTANCES_PER_ROW of them.
+const INSTANCE_TEXELS: u32 = 9u;
+const INSTANCES_PER_ROW: u32 = 512u;
+fn instance_texel(instances: texture_2d<u32>, id: u32, texel: u32) -> vec4f {
+  let x = (id % INSTANCES_PER_ROW) * INSTANCE_TEXELS + texel;
+  return bitcast<vec4f>(textureLoad(instances, vec2(x, id / INSTANCES_PER_ROW), 0));
+}
+fn instance_load(instances: texture_2d<u32>, id: u32) -> Instance {
+  return Instance(
+    mat4x4(
+      instance_texel(instances, id, 0u),
+      instance_texel(instances, id, 1u),
+      instance_texel(instances, id, 2u),
+      instance_texel(instances, id, 3u)
+    ),
+    mat3x3(
+      instance_texel(instances, id, 4u).xyz,
+      instance_texel(instances, id, 5u).xyz,
+      instance_texel(instances, id, 6u).xyz
+    ),
+    instance_texel(instances, id, 7u).xyz,
+    instance_texel(instances, id, 8u)
+  );
+}
+// A hash map of the subdivision tree, so that the render stage can find the neighbours of a patch.
+// Every split, every rendered patch and every culled patch gets a node.
+struct LodNode {
+  // Set to 1 by whoever claims the slot
+  occupied: atomic<u32>,
+  key: EncodedPatch,
+  // LOD_SPLIT_U and LOD_SPLIT_V flags, 0 for a patch that got rendered
+  splits: u32,
+  // How many quads a rendered patch has along one side, 0 for a culled patch
+  quads_per_side: u32,
+};
+struct LodTree {
+  // Set when a node did not fit, which means that the tree is incomplete
+  overflowed: atomic<u32>,
+  nodes: array<LodNode>,
+};
+struct LodNodeRead {
+  occupied: u32,
+  key: EncodedPatch,
+  splits: u32,
+  quads_per_side: u32,
+};
+struct LodTreeRead {
+  overflowed: u32,
+  nodes: array<LodNodeRead>,
+};
+const LOD_SPLIT_U: u32 = 1u;
+const LOD_SPLIT_V: u32 = 2u;
+// Linear probing gives up after this many slots
+const LOD_TREE_MAX_PROBES: u32 = 64u;
+fn lod_node_hash(key: EncodedPatch) -> u32 {
+  // Integer hash from https://nullprogram.com/blog/2018/07/31/
+  var hash = (key.u * 0x9e3779b1u) ^ (key.v * 0x85ebca77u) ^ (key.instance * 0xc2b2ae3du);
+  hash ^= hash >> 16u;
+  hash *= 0x7feb352du;
+  hash ^= hash >> 15u;
+  hash *= 0x846ca68bu;
+  hash ^= hash >> 16u;
+  return hash;
+}
+fn lod_node_is(node_key: EncodedPatch, key: EncodedPatch) -> bool {
+  return node_key.u == key.u && node_key.v == key.v && node_key.instance == key.instance;
+}
+fn ceil_div(a: u32, b: u32) -> u32 { return (a + b - 1u) / b; }
+// Inspired from https://onrendering.com/data/papers/isubd/isubd.pdf
+fn patch_u_child(u: u32, child_bit: u32) -> u32 {
+  return (u << 1) | (child_bit & 1);
+}
+fn patch_top_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(encoded.u, patch_u_child(encoded.v, 0u), encoded.instance);
+}
+fn patch_bottom_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(encoded.u, patch_u_child(encoded.v, 1u), encoded.instance);
+}
+fn patch_left_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(patch_u_child(encoded.u, 0u), encoded.v, encoded.instance);
+}
+fn patch_right_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(patch_u_child(encoded.u, 1u), encoded.v, encoded.instance);
+}
+fn patch_top_left_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_top_child(patch_left_child(encoded));
+}
+fn patch_top_right_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_top_child(patch_right_child(encoded));
+}
+fn patch_bottom_left_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_bottom_child(patch_left_child(encoded));
+}
+fn patch_bottom_right_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_bottom_child(patch_right_child(encoded));
+}
+fn patch_decode(encoded: EncodedPatch) -> Patch {
+  // First we go to the implicit 1u
+  let leading_zeroes_u = countLeadingZeros(encoded.u);
+  let u_bits = extractBits(encoded.u, 0u, 31u - leading_zeroes_u);
+  let u_max_bits = u_bits + 1u; // The end position of the patch
+  let leading_zeroes_v = countLeadingZeros(encoded.v);
+  let v_bits = extractBits(encoded.v, 0u, 31u - leading_zeroes_v);
+  let v_max_bits = v_bits + 1u;
+
+  // And every bit after that describes if we go left or right
+  // Conveniently, this is already what binary numbers do.
+  // 0b0.1 == 0.5
+  // 0b0.01 == 0.25
+  // 0b0.11 == 0.75
+  // And that directly corresponds to how floats work: mantissa * 2^exponent
+  // So we can just convert the bits to a float
+  // let u = f32(u_bits) * pow(2.0, -1.0 * f32(31 - leading_zeroes_u));
+  // And that's equivalent to the size of a patch, see formula below
+  let min_value = vec2f(
+    f32(u_bits) / f32(1u << (31u - leading_zeroes_u)),
+    f32(v_bits) / f32(1u << (31u - leading_zeroes_v))
+  );
+  let max_value = vec2f(
+    f32(u_max_bits) / f32(1u << (31u - leading_zeroes_u)),
+    f32(v_max_bits) / f32(1u << (31u - leading_zeroes_v))
+  );
+  
+  // The size of the patch is 1 / 2^(31 - leading_zeroes)
+  // let u_size = 1.0 / f32(2 << (31 - leading_zeroes_u));
+  // let v_size = 1.0 / f32(2 << (31 - leading_zeroes_v));
+  // But we care about this_patch.max == next_patch.min, 
+  // so we need to do the floating point calculations more carefully
+  
+  return Patch(min_value, max_value, encoded.instance);
+}
+
+fn assert(condition: bool) {
+  // TODO: Implement this
+}
+//// END OF AUTOGEN
+////#include "./EvaluateImage.wgsl"
+//// AUTOGEN ced6a506909abff01241bc184a7d6c3a0bede71b7a4d6b1f07430a81dbc637e9
+struct Time {
+  elapsed: f32,
+  delta: f32,
+  frame: u32,
+}
+struct Screen {
+  resolution: vec2<u32>,
+  inv_resolution: vec2<f32>,
+}
+struct Mouse {
+  pos: vec2<f32>,
+  buttons: u32,
+}
+struct Extra {
+  hot_value: f32
+}
+fn mouse_held(button: u32) -> bool {
+  return (mouse.buttons & button) != 0u;
+}
+// Group 0 is for constants that change once per frame at most
+@group(0) @binding(0) var<uniform> time : Time;
+@group(0) @binding(1) var<uniform> screen : Screen;
+@group(0) @binding(2) var<uniform> mouse : Mouse;
+@group(0) @binding(3) var<uniform> extra : Extra;
+
+//// END OF AUTOGEN
+////#include "./LodStage.wgsl"
+//// AUTOGEN fc9a31b8cb432046e89ab76f5a6a2a8330ff321235835002eb78f2c27636a523
+struct InputBuffer {
+    threshold_factor: f32,
+    model_view_projection: mat4x4<f32>,
+};
+
+// Group 1 is for things that change once per model
+// TODO: Read back the patches_length on the CPU to know when we're going out of bounds
+// (And how far out of bounds)
+@group(1) @binding(0) var<uniform> input_buffer : InputBuffer;
+@group(1) @binding(1) var<storage, read_write> render_buffer_2 : RenderBuffer;
+@group(1) @binding(2) var<storage, read_write> render_buffer_4 : RenderBuffer;
+@group(1) @binding(3) var<storage, read_write> render_buffer_8 : RenderBuffer;
+@group(1) @binding(4) var<storage, read_write> render_buffer_16 : RenderBuffer;
+@group(1) @binding(5) var<storage, read_write> render_buffer_32 : RenderBuffer;
+@group(1) @binding(6) var<storage, read_write> lod_tree : LodTree;
+@group(1) @binding(7) var<uniform> parameters : Parameters;
+@group(1) @binding(8) var instances : texture_2d<u32>;
+// Files that include this one decide where the patches come from, and where split patches go. They define
+// fn force_render_patch(quad_encoded: EncodedPatch) -> bool
+// fn write_children(children: array<EncodedPatch, 4>, count: u32) -> bool
+
+//// START Parameters
+// Replaced with the `// @param` annotations of the user code
+struct Parameters {
+  unused: u32,
+}
+//// END Parameters
+
+fn triangle_area(a: vec3f, b: vec3f, c: vec3f) -> f32 {
+  return 0.5 * length(cross(b - a, c - a));
+}
+
+// 8 samples in the X direction
+const U_X = 8u;
+// and repeat that 4 times
+const U_Y = 4u;
+const WORKGROUP_SIZE = U_X * U_Y;
+
+// A vec2 with screen space coordinates
+alias vec2Screen = vec2<f32>;
+
+var<workgroup> u_samples: array<array<vec2Screen, U_X>, U_Y>;
+var<workgroup> v_samples: array<array<vec2Screen, U_X>, U_Y>;
+const U_LENGTHS_X = U_X - 1; // Last sample per row doesn't have a next sample
+var<workgroup> u_lengths: array<array<f32, U_LENGTHS_X>, U_Y>;
+var<workgroup> v_lengths: array<array<f32, U_LENGTHS_X>, U_Y>;
+var<workgroup> frustum_sides: array<u32, 25>;
+
+/// Remembers how a patch was handled, so that the render stage can stitch it to its neighbours
+fn lod_tree_insert(key: EncodedPatch, splits: u32, quads_per_side: u32) {
+  let capacity = arrayLength(&lod_tree.nodes);
+  let hash = lod_node_hash(key);
+  for (var i = 0u; i < LOD_TREE_MAX_PROBES; i += 1u) {
+    let index = (hash + i) % capacity;
+    if (atomicExchange(&lod_tree.nodes[index].occupied, 1u) == 0u) {
+      lod_tree.nodes[index].key = key;
+      lod_tree.nodes[index].splits = splits;
+      lod_tree.nodes[index].quads_per_side = quads_per_side;
+      return;
+    }
+  }
+  // The tree is full. The neighbours of this patch will not be stitched, and the next frame cannot reuse the tree.
+  atomicStore(&lod_tree.overflowed, 1u);
+}
+
+/// Split the patch and write it to the output buffers
+fn split_patch(quad_encoded: EncodedPatch, u_length: array<f32, U_Y>, v_length: array<f32, U_Y>) {
+  // We use threshold_32, because after that, we don't need to split anymore.
+  // Instead, we need to compute the correct render buffer to write to.
+  let threshold_32 = (32.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+
+  let split_top = u_length[0] > threshold_32.x || u_length[1] > threshold_32.x;
+  let split_bottom = u_length[2] > threshold_32.x || u_length[3] > threshold_32.x;
+  let split_left = v_length[0] > threshold_32.y || v_length[1] > threshold_32.y;
+  let split_right = v_length[2] > threshold_32.y || v_length[3] > threshold_32.y;
+
+  let patch_top = patch_top_child(quad_encoded);
+  let patch_bottom = patch_bottom_child(quad_encoded);
+  let patch_left = patch_left_child(quad_encoded);
+  let patch_right = patch_right_child(quad_encoded);
+
+  let patch_top_left = patch_top_left_child(quad_encoded);
+  let patch_top_right = patch_top_right_child(quad_encoded);
+  let patch_bottom_right = patch_bottom_right_child(quad_encoded);
+  let patch_bottom_left = patch_bottom_left_child(quad_encoded);
+
+  let splits_bitflags = (u32(split_top) << 3) | (u32(split_bottom) << 2) | (u32(split_left) << 1) | u32(split_right);
+  if (splits_bitflags == 0u || force_render_patch(quad_encoded)) {
+    /* No splits, render the patch
+    +---+---+
+    |       |
+    +       +
+    |       |
+    +---+---+
+    */
+    let max_u_length = max(max(u_length[0], u_length[1]), max(u_length[2], u_length[3]));
+    let max_v_length = max(max(v_length[0], v_length[1]), max(v_length[2], v_length[3]));
+
+    let threshold_16 = (16.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+    let threshold_8 = (8.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+    let threshold_4 = (4.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+    let threshold_2 = (2.0 * screen.inv_resolution) * input_buffer.threshold_factor;
+
+    if (max_u_length > threshold_16.x || max_v_length > threshold_16.y) {
+      let write_index = atomicAdd(&render_buffer_32.patches_length, 1u);
+      if (write_index < render_buffer_32.patches_capacity) {
+        render_buffer_32.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 16u);
+    } else if (max_u_length > threshold_8.x || max_v_length > threshold_8.y) {
+      let write_index = atomicAdd(&render_buffer_16.patches_length, 1u);
+      if (write_index < render_buffer_16.patches_capacity) {
+        render_buffer_16.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 8u);
+    } else if (max_u_length > threshold_4.x || max_v_length > threshold_4.y) {
+      let write_index = atomicAdd(&render_buffer_8.patches_length, 1u);
+      if (write_index < render_buffer_8.patches_capacity) {
+        render_buffer_8.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 4u);
+    } else if (max_u_length > threshold_2.x || max_v_length > threshold_2.y) {
+      let write_index = atomicAdd(&render_buffer_4.patches_length, 1u);
+      if (write_index < render_buffer_4.patches_capacity) {
+        render_buffer_4.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 2u);
+    } else {
+      let write_index = atomicAdd(&render_buffer_2.patches_length, 1u);
+      if (write_index < render_buffer_2.patches_capacity) {
+        render_buffer_2.patches[write_index] = quad_encoded;
+      }
+      lod_tree_insert(quad_encoded, 0u, 1u);
+    }
+  } else if (splits_bitflags == 8u || splits_bitflags == 4u || splits_bitflags == 12u) {
+    /* Split top or split bottom or split top-bottom
+    => Split along the U axis
+    +---+---+    +---+---+   +---+---+
+    |   |   |    |       |   |   |   |
+    +       +    +       +   +   |   +
+    |       |    |   |   |   |   |   |
+    +---+---+    +---+---+   +---+---+
+    */
+    if (write_children(array(patch_left, patch_right, quad_encoded, quad_encoded), 2u)) { // Only the first 2 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if (splits_bitflags == 2u || splits_bitflags == 1u || splits_bitflags == 3u) {
+    /* Split left or split right or split left-right
+    => Split along the V axis
+    +---+---+    +---+---+   +---+---+
+    |       |    |       |   |       |
+    +---    +    +    ---+   +-------+
+    |       |    |       |   |       |
+    +---+---+    +---+---+   +---+---+
+    */
+    if (write_children(array(patch_top, patch_bottom, quad_encoded, quad_encoded), 2u)) { // Only the first 2 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 14 || splits_bitflags == 10) {
+    /* Split top-bottom-left or split top-left
+    => T-split
+    => Ambiguous T-split (1110 or 1011)
+    +---+---+    +---+---+
+    |   |   |    |   |   |
+    +---+   +    +---+   +
+    |   |   |    |       |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_right, patch_top_left, patch_bottom_left, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+      lod_tree_insert(patch_left, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 13 || splits_bitflags == 5) {
+    /* Split top-bottom-right or split bottom-right
+    => T-split
+    => Ambiguous T-split (1101 or 0111)
+    +---+---+    +---+---+
+    |   |   |    |       |
+    +   +---+    +   +---+
+    |   |   |    |   |   |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_left, patch_top_right, patch_bottom_right, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U, 0u);
+      lod_tree_insert(patch_right, LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 11 || splits_bitflags == 9) {
+    /* Split top-left-right or split top-right
+    => T-split
+    => Ambiguous T-split (1101 or 1011)
+    +---+---+    +---+---+
+    |   |   |    |   |   |
+    +---+---+    +   +---+
+    |       |    |       |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_top_left, patch_top_right, patch_bottom, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+      lod_tree_insert(patch_top, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 7 || splits_bitflags == 6) {
+    /* Split bottom-left-right or split bottom-left
+    => T-split
+    => Ambiguous T-split (1110 or 0111)
+    +---+---+    +---+---+
+    |       |    |       |
+    +---+---+    +---+   +
+    |   |   |    |   |   |
+    +---+---+    +---+---+
+    */
+    if (write_children(array(patch_top, patch_bottom_left, patch_bottom_right, quad_encoded), 3u)) { // Only the first 3 are used
+      lod_tree_insert(quad_encoded, LOD_SPLIT_V, 0u);
+      lod_tree_insert(patch_bottom, LOD_SPLIT_U, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  } else if(splits_bitflags == 15) {
+    /*
+    Split all 4 ways
+    +---+---+
+    |   |   |
+    +---+---+
+    |   |   |
+    +---+---+
+    */
+    if (write_children(array(patch_top_left, patch_top_right, patch_bottom_right, patch_bottom_left), 4u)) {
+      lod_tree_insert(quad_encoded, LOD_SPLIT_U | LOD_SPLIT_V, 0u);
+    } else {
+      // Out of space. The next frame tries again.
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+  }
+}
+
+/// Gets a bitflag for the frustum sides of a point in clip space. 6 bits are used, 1 for each side.
+/// Based on the equations in https://carmencincotti.com/2022-05-02/homogeneous-coordinates-clip-space-ndc/#clip-space
+fn get_frustum_side(point_clip_space: vec4f) -> u32 {
+  return u32(
+    (u32(point_clip_space.x < -point_clip_space.w) << 5u) |
+    (u32(point_clip_space.x >  point_clip_space.w) << 4u) |
+    (u32(point_clip_space.y < -point_clip_space.w) << 3u) |
+    (u32(point_clip_space.y >  point_clip_space.w) << 2u) |
+    (u32(point_clip_space.z < -point_clip_space.w) << 1u) |
+    (u32(point_clip_space.z >  point_clip_space.w) << 0u)
+  );
+}
+
+/// Measures a patch on the screen, and then splits or renders it. Needs the whole workgroup.
+/// sample_index goes from 0 to 31 (WORKGROUP_SIZE - 1)
+fn evaluate_patch(quad_encoded: EncodedPatch, sample_index: u32) {
+  let quad = patch_decode(quad_encoded);
+  let quad_size = quad.max - quad.min;
+
+  // Culling is done by checking if all samples are outside of exactly one of the frustum planes :)
+  // 5*5 = 25 extra samples for frustum culling
+  let extra_sample_index = vec2<u32>(sample_index % 5u, sample_index / 5u);
+  let extra_sample_location = quad.min + vec2(
+    // Divide by 4.0 because we have 5 samples, but we want to go from 0 to 1
+    (quad_size.x / 4.0) * f32(extra_sample_index.x),
+    (quad_size.y / 4.0) * f32(extra_sample_index.y)
+  );
+  instance_id = quad_encoded.instance;
+  instance = instance_load(instances, instance_id);
+  // The instance transform is part of the culling and the LOD, so that instances can be spread out
+  let model_view_projection = input_buffer.model_view_projection * instance.transform;
+  if (sample_index < 25) {
+    let extra_sample = sampleObject(extra_sample_location);
+    let extra_clip_space = model_view_projection * vec4f(extra_sample.xyz, 1.0);
+    frustum_sides[sample_index] = get_frustum_side(extra_clip_space);
+  }
+  workgroupBarrier(); // wait for frustum_sides
+  // Now parallel combine the frustum sides
+  for (var i: u32 = 16u; i > 0u; i >>= 1u) {
+    if (sample_index < i && sample_index + i < 25u) {
+      frustum_sides[sample_index] &= frustum_sides[sample_index + i];
+    }
+    workgroupBarrier();
+  }
+  // frustum_sides[0] now contains the combined frustum sides for the entire patch
+  if (workgroupUniformLoad(&frustum_sides[0]) != 0u) {
+    if (sample_index == 0) {
+      // Remembered, so that the next frame can bring it back when it becomes visible
+      lod_tree_insert(quad_encoded, 0u, 0u);
+    }
+    return; // Skip the entire patch
+  }
+
+  let u_v_sample_index = vec2<u32>(sample_index % U_X, sample_index / U_X);
+  
+  // 4*8 = 32 U samples
+  let u_sample_location = quad.min + vec2(
+    // 8 samples divide a quad into 7 parts
+    (quad_size.x / f32(U_X - 1)) * f32(u_v_sample_index.x),
+    (quad_size.y / f32(U_Y) / 2.0) // top offset
+    + (quad_size.y / f32(U_Y)) * f32(u_v_sample_index.y)
+  );
+  let u_sample = sampleObject(u_sample_location);
+  let u_clip_space = model_view_projection * vec4f(u_sample.xyz, 1.0);
+  let u_screen_space = u_clip_space.xy / u_clip_space.w;
+  u_samples[u_v_sample_index.y][u_v_sample_index.x] = u_screen_space;
+
+  // 4*8 = 32 V samples
+  let v_sample_location = quad.min + vec2(
+    (quad_size.x / f32(U_Y) / 2.0) // left offset
+    + (quad_size.x / f32(U_Y)) * f32(u_v_sample_index.y),
+    (quad_size.y / f32(U_X - 1)) * f32(u_v_sample_index.x),
+  );
+  let v_sample = sampleObject(v_sample_location);
+  let v_clip_space = model_view_projection * vec4f(v_sample.xyz, 1.0);
+  let v_screen_space = v_clip_space.xy / v_clip_space.w;
+  v_samples[u_v_sample_index.y][u_v_sample_index.x] = v_screen_space;
+
+
+  workgroupBarrier(); // wait for u_samples and v_samples
+  if (u_v_sample_index.x < U_X - 1) {
+    let u_length = distance(u_samples[u_v_sample_index.y][u_v_sample_index.x], u_samples[u_v_sample_index.y][u_v_sample_index.x + 1]);
+    u_lengths[u_v_sample_index.y][u_v_sample_index.x] = u_length;
+    // v might go in a different direction, but the array layout is the same
+    let v_length = distance(v_samples[u_v_sample_index.y][u_v_sample_index.x], v_samples[u_v_sample_index.y][u_v_sample_index.x + 1]);
+    v_lengths[u_v_sample_index.y][u_v_sample_index.x] = v_length;
+  }
+  workgroupBarrier(); // wait for u_lengths and v_lengths
+
+  // TODO: Test if this is faster with barriers instead
+  let u_length = array<f32, U_Y>(
+    u_lengths[0][0] + u_lengths[0][1] + u_lengths[0][2] + u_lengths[0][3] + u_lengths[0][4] + u_lengths[0][5] + u_lengths[0][6],
+    u_lengths[1][0] + u_lengths[1][1] + u_lengths[1][2] + u_lengths[1][3] + u_lengths[1][4] + u_lengths[1][5] + u_lengths[1][6],
+    u_lengths[2][0] + u_lengths[2][1] + u_lengths[2][2] + u_lengths[2][3] + u_lengths[2][4] + u_lengths[2][5] + u_lengths[2][6],
+    u_lengths[3][0] + u_lengths[3][1] + u_lengths[3][2] + u_lengths[3][3] + u_lengths[3][4] + u_lengths[3][5] + u_lengths[3][6]
+  );
+  let v_length = array<f32, U_Y>(
+    v_lengths[0][0] + v_lengths[0][1] + v_lengths[0][2] + v_lengths[0][3] + v_lengths[0][4] + v_lengths[0][5] + v_lengths[0][6],
+    v_lengths[1][0] + v_lengths[1][1] + v_lengths[1][2] + v_lengths[1][3] + v_lengths[1][4] + v_lengths[1][5] + v_lengths[1][6],
+    v_lengths[2][0] + v_lengths[2][1] + v_lengths[2][2] + v_lengths[2][3] + v_lengths[2][4] + v_lengths[2][5] + v_lengths[2][6],
+    v_lengths[3][0] + v_lengths[3][1] + v_lengths[3][2] + v_lengths[3][3] + v_lengths[3][4] + v_lengths[3][5] + v_lengths[3][6]
+  );
+
+  if(sample_index == 0) {
+    split_patch(quad_encoded, u_length, v_length);
+  }
+
+
+  // Warning regarding storage barrier:
+  // https://stackoverflow.com/questions/72035548/what-does-storagebarrier-in-webgpu-actually-do
+}
+//// END OF AUTOGEN
+
+// A patch that a workgroup pushed. The fields are atomics, so that a patch only gets picked up once all of it is visible.
+// Every field is nonzero after it has been written, since the patch encoding has an implicit 1 bit.
+struct WorkItem {
+  u: atomic<u32>,
+  v: atomic<u32>,
+  instance_plus_one: atomic<u32>,
+};
+
+// Cleared to zero at the start of every frame
+struct WorkQueue {
+  // Set when a workgroup gave up waiting for an item. Read back by the CPU, which then switches to the rounds kernel.
+  stalled: atomic<u32>,
+  // Next item to claim. The seeds come first, followed by the pushed items.
+  head: atomic<u32>,
+  // Number of pushed items, including the ones that did not fit
+  tail: atomic<u32>,
+  // Number of seeds and items that are done
+  finished: atomic<u32>,
+  items: array<WorkItem>,
+};
+
+struct PersistentInput {
+  // Patches this deep are rendered, even if they are still too large on the screen
+  max_depth: u32,
+};
+
+// Group 2 is for things that change multiple times per model
+@group(2) @binding(0) var<storage, read> seeds : PatchesRead;
+@group(2) @binding(1) var<storage, read_write> queue : WorkQueue;
+@group(2) @binding(2) var<uniform> persistent_input : PersistentInput;
+
+// Marks an item slot that got reserved, but the patches did not fit
+const SKIPPED_ITEM: u32 = 0xffffffffu;
+// Safeguard against hanging the GPU if the queue ever ends up in a broken state.
+// WebGPU does not guarantee that other workgroups make progress while one waits, so this stays far below a GPU timeout.
+const MAX_SPINS: u32 = 1u << 14u;
+
+fn force_render_patch(quad_encoded: EncodedPatch) -> bool {
+  let u_depth = 31u - countLeadingZeros(quad_encoded.u);
+  let v_depth = 31u - countLeadingZeros(quad_encoded.v);
+  return max(u_depth, v_depth) >= persistent_input.max_depth;
+}
+
+fn write_children(children: array<EncodedPatch, 4>, count: u32) -> bool {
+  let capacity = arrayLength(&queue.items);
+  let write_index = atomicAdd(&queue.tail, count);
+  if write_index + count <= capacity {
+    for (var i = 0u; i < count; i += 1u) {
+      atomicStore(&queue.items[write_index + i].u, children[i].u);
+      atomicStore(&queue.items[write_index + i].v, children[i].v);
+      atomicStore(&queue.items[write_index + i].instance_plus_one, children[i].instance + 1u);
+    }
+    return true;
+  }
+  // Nobody will ever work on these, so they are done already
+  atomicAdd(&queue.finished, count);
+  for (var i = write_index; i < capacity; i += 1u) {
+    atomicStore(&queue.items[i].u, 1u);
+    atomicStore(&queue.items[i].v, 1u);
+    atomicStore(&queue.items[i].instance_plus_one, SKIPPED_ITEM);
+  }
+  return false;
+}
+
+struct ClaimedItem {
+  quad_encoded: EncodedPatch,
+  // The queue is empty, and every workgroup is idle
+  done: u32,
+};
+
+var<workgroup> claimed_item: ClaimedItem;
+
+/// Takes the next patch from the queue. Waits for the patch, if another workgroup is about to push it.
+fn claim_item() -> ClaimedItem {
+  let seed_count = seeds.dispatch_x;
+  let capacity = arrayLength(&queue.items);
+  var index = atomicAdd(&queue.head, 1u);
+  for (var spins = 0u; spins < MAX_SPINS; spins += 1u) {
+    if (index < seed_count) {
+      return ClaimedItem(seeds.patches[index], 0u);
+    }
+    let item_index = index - seed_count;
+    if (item_index >= capacity) {
+      // Did not fit into the queue, and already counts as finished
+      index = atomicAdd(&queue.head, 1u);
+      continue;
+    }
+    let u = atomicLoad(&queue.items[item_index].u);
+    let v = atomicLoad(&queue.items[item_index].v);
+    let instance_plus_one = atomicLoad(&queue.items[item_index].instance_plus_one);
+    if (u != 0u && v != 0u && instance_plus_one != 0u) {
+      if (instance_plus_one == SKIPPED_ITEM) {
+        index = atomicAdd(&queue.head, 1u);
+        continue;
+      }
+      return ClaimedItem(EncodedPatch(u, v, instance_plus_one - 1u), 0u);
+    }
+    // Finished has to be read first. A workgroup pushes its children before it finishes its own item.
+    let finished = atomicLoad(&queue.finished);
+    if (finished == seed_count + atomicLoad(&queue.tail)) {
+      return ClaimedItem(EncodedPatch(0u, 0u, 0u), 1u);
+    }
+  }
+  // The claimed item is lost, which leaves a hole in the surface.
+  // So the next frame does not reuse the LOD tree, and the CPU stops using this kernel.
+  atomicStore(&lod_tree.overflowed, 1u);
+  atomicStore(&queue.stalled, 1u);
+  return ClaimedItem(EncodedPatch(0u, 0u, 0u), 1u);
+}
+
+/// Persistent threads: A fixed number of workgroups keeps taking patches from the queue until the LOD stage is done.
+/// Split patches go back into the same queue, so there is no fixed number of rounds.
+@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
+fn main(@builtin(local_invocation_id) local_invocation_id : vec3<u32>) {
+  let sample_index = local_invocation_id.x;
+  loop {
+    if (sample_index == 0u) {
+      claimed_item = claim_item();
+    }
+    let item = workgroupUniformLoad(&claimed_item);
+    if (item.done != 0u) {
+      break;
+    }
+    evaluate_patch(item.quad_encoded, sample_index);
+    if (sample_index == 0u) {
+      atomicAdd(&queue.finished, 1u);
+    }
+  }
+}
\ No newline at end of file
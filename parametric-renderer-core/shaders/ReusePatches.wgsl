@@ -0,0 +1,300 @@
+////#include "./Common.wgsl"
+//// AUTOGEN d5bc2f7e981d9972b3c852bb1248465b910ae2d693454f5ffbc068cff688e21f
+struct EncodedPatch {
+  u: u32,
+  v: u32,
+  instance: u32
+};
+struct Patch {
+  min: vec2<f32>,
+  max: vec2<f32>,
+  instance: u32
+};
+struct Patches {
+  // Indirect dispatch arguments for the next round, one workgroup per patch.
+  // They live in the same buffer to save a storage buffer binding.
+  dispatch_x: atomic<u32>,
+  dispatch_y: u32,
+  dispatch_z: u32,
+  patches_length: atomic<u32>,
+  patches_capacity: u32,
+  patches : array<EncodedPatch>,
+};
+struct PatchesRead { // Is currently needed, see https://github.com/gpuweb/gpuweb/discussions/4438
+  dispatch_x: u32,
+  dispatch_y: u32,
+  dispatch_z: u32,
+  patches_length: u32, // Same size and alignment as atomic<u32>. Should be legal, right?
+  patches_capacity: u32,
+  patches : array<EncodedPatch>,
+};
+struct RenderBuffer {
+  patches_length: atomic<u32>,
+  patches_capacity: u32,
+  patches: array<EncodedPatch>,
+};
+struct RenderBufferRead {
+  patches_length: u32,
+  patches_capacity: u32,
+  patches: array<EncodedPatch>,
+};
+// The data of one instance of a model, indexed by the instance of a patch
+struct Instance {
+  // Applied after sampleObject, relative to the model
+  transform: mat4x4<f32>,
+  normal_matrix: mat3x3<f32>,
+  // Multiplies the result of getColor
+  tint: vec3<f32>,
+  // Free for the user code
+  custom: vec4<f32>,
+};
+// Instances are stored in a rgba32uint texture, since the LOD stage has no storage buffer binding left.
+// Every instance takes up INSTANCE_TEXELS texels in the layout of a storage buffer, and a row has INSTANCES_PER_ROW of them.
+const INSTANCE_TEXELS: u32 = 9u;
+const INSTANCES_PER_ROW: u32 = 512u;
+fn instance_texel(instances: texture_2d<u32>, id: u32, texel: u32) -> vec4f {
+  let x = (id % INSTANCES_PER_ROW) * INSTANCE_TEXELS + texel;
+  return bitcast<vec4f>(textureLoad(instances, vec2(x, id / INSTANCES_PER_ROW), 0));
+}
+fn instance_load(instances: texture_2d<u32>, id: u32) -> Instance {
+  return Instance(
+    mat4x4(
+      instance_texel(instances, id, 0u),
+      instance_texel(instances, id, 1u),
+      instance_texel(instances, id, 2u),
+      instance_texel(instances, id, 3u)
+    ),
+    mat3x3(
+      instance_texel(instances, id, 4u).xyz,
+      instance_texel(instances, id, 5u).xyz,
+      instance_texel(instances, id, 6u).xyz
+    ),
+    instance_texel(instances, id, 7u).xyz,
+    instance_texel(instances, id, 8u)
+  );
+}
+// A hash map of the subdivision tree, so that the render stage can find the neighbours of a patch.
+// Every split, every rendered patch and every culled patch gets a node.
+struct LodNode {
+  // Set to 1 by whoever claims the slot
+  occupied: atomic<u32>,
+  key: EncodedPatch,
+  // LOD_SPLIT_U and LOD_SPLIT_V flags, 0 for a patch that got rendered
+  splits: u32,
+  // How many quads a rendered patch has along one side, 0 for a culled patch
+  quads_per_side: u32,
+};
+struct LodTree {
+  // Set when a node did not fit, which means that the tree is incomplete
+  overflowed: atomic<u32>,
+  nodes: array<LodNode>,
+};
+struct LodNodeRead {
+  occupied: u32,
+  key: EncodedPatch,
+  splits: u32,
+  quads_per_side: u32,
+};
+struct LodTreeRead {
+  overflowed: u32,
+  nodes: array<LodNodeRead>,
+};
+const LOD_SPLIT_U: u32 = 1u;
+const LOD_SPLIT_V: u32 = 2u;
+// Linear probing gives up after this many slots
+const LOD_TREE_MAX_PROBES: u32 = 64u;
+fn lod_node_hash(key: EncodedPatch) -> u32 {
+  // Integer hash from https://nullprogram.com/blog/2018/07/31/
+  var hash = (key.u * 0x9e3779b1u) ^ (key.v * 0x85ebca77u) ^ (key.instance * 0xc2b2ae3du);
+  hash ^= hash >> 16u;
+  hash *= 0x7feb352du;
+  hash ^= hash >> 15u;
+  hash *= 0x846ca68bu;
+  hash ^= hash >> 16u;
+  return hash;
+}
+fn lod_node_is(node_key: EncodedPatch, key: EncodedPatch) -> bool {
+  return node_key.u == key.u && node_key.v == key.v && node_key.instance == key.instance;
+}
+fn ceil_div(a: u32, b: u32) -> u32 { return (a + b - 1u) / b; }
+// Inspired from https://onrendering.com/data/papers/isubd/isubd.pdf
+fn patch_u_child(u: u32, child_bit: u32) -> u32 {
+  return (u << 1) | (child_bit & 1);
+}
+fn patch_top_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(encoded.u, patch_u_child(encoded.v, 0u), encoded.instance);
+}
+fn patch_bottom_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(encoded.u, patch_u_child(encoded.v, 1u), encoded.instance);
+}
+fn patch_left_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(patch_u_child(encoded.u, 0u), encoded.v, encoded.instance);
+}
+fn patch_right_child(encoded: EncodedPatch) -> EncodedPatch {
+  return EncodedPatch(patch_u_child(encoded.u, 1u), encoded.v, encoded.instance);
+}
+fn patch_top_left_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_top_child(patch_left_child(encoded));
+}
+fn patch_top_right_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_top_child(patch_right_child(encoded));
+}
+fn patch_bottom_left_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_bottom_child(patch_left_child(encoded));
+}
+fn patch_bottom_right_child(encoded: EncodedPatch) -> EncodedPatch {
+  return patch_bottom_child(patch_right_child(encoded));
+}
+fn patch_decode(encoded: EncodedPatch) -> Patch {
+  // First we go to the implicit 1u
+  let leading_zeroes_u = countLeadingZeros(encoded.u);
+  let u_bits = extractBits(encoded.u, 0u, 31u - leading_zeroes_u);
+  let u_max_bits = u_bits + 1u; // The end position of the patch
+  let leading_zeroes_v = countLeadingZeros(encoded.v);
+  let v_bits = extractBits(encoded.v, 0u, 31u - leading_zeroes_v);
+  let v_max_bits = v_bits + 1u;
+
+  // And every bit after that describes if we go left or right
+  // Conveniently, this is already what binary numbers do.
+  // 0b0.1 == 0.5
+  // 0b0.01 == 0.25
+  // 0b0.11 == 0.75
+  // And that directly corresponds to how floats work: mantissa * 2^exponent
+  // So we can just convert the bits to a float
+  // let u = f32(u_bits) * pow(2.0, -1.0 * f32(31 - leading_zeroes_u));
+  // And that's equivalent to the size of a patch, see formula below
+  let min_value = vec2f(
+    f32(u_bits) / f32(1u << (31u - leading_zeroes_u)),
+    f32(v_bits) / f32(1u << (31u - leading_zeroes_v))
+  );
+  let max_value = vec2f(
+    f32(u_max_bits) / f32(1u << (31u - leading_zeroes_u)),
+    f32(v_max_bits) / f32(1u << (31u - leading_zeroes_v))
+  );
+  
+  // The size of the patch is 1 / 2^(31 - leading_zeroes)
+  // let u_size = 1.0 / f32(2 << (31 - leading_zeroes_u));
+  // let v_size = 1.0 / f32(2 << (31 - leading_zeroes_v));
+  // But we care about this_patch.max == next_patch.min, 
+  // so we need to do the floating point calculations more carefully
+  
+  return Patch(min_value, max_value, encoded.instance);
+}
+
+fn assert(condition: bool) {
+  // TODO: Implement this
+}
+//// END OF AUTOGEN
+
+struct ReuseInput {
+  // For starting over from the roots, when the last tree is incomplete
+  instance_count: u32,
+};
+
+@group(0) @binding(0) var<storage, read> lod_tree : LodTreeRead;
+@group(0) @binding(1) var<storage, read_write> patches_to_buffer : Patches;
+@group(0) @binding(2) var<uniform> reuse_input : ReuseInput;
+
+fn lod_tree_find(key: EncodedPatch) -> i32 {
+  let capacity = arrayLength(&lod_tree.nodes);
+  let hash = lod_node_hash(key);
+  for (var i = 0u; i < LOD_TREE_MAX_PROBES; i += 1u) {
+    let index = (hash + i) % capacity;
+    if (lod_tree.nodes[index].occupied == 0u) {
+      return -1;
+    }
+    if (lod_node_is(lod_tree.nodes[index].key, key)) {
+      return i32(index);
+    }
+  }
+  return -1;
+}
+
+fn is_split(key: EncodedPatch) -> bool {
+  let index = lod_tree_find(key);
+  return index >= 0 && lod_tree.nodes[index].splits != 0u;
+}
+
+/// A split node whose children are all leaves can be merged again
+fn has_split_child(key: EncodedPatch, splits: u32) -> bool {
+  let split_u = (splits & LOD_SPLIT_U) != 0u;
+  let split_v = (splits & LOD_SPLIT_V) != 0u;
+  for (var u_bit = 0u; u_bit < select(1u, 2u, split_u); u_bit += 1u) {
+    for (var v_bit = 0u; v_bit < select(1u, 2u, split_v); v_bit += 1u) {
+      var child = key;
+      if (split_u) {
+        child.u = patch_u_child(key.u, u_bit);
+      }
+      if (split_v) {
+        child.v = patch_u_child(key.v, v_bit);
+      }
+      if (is_split(child)) {
+        return true;
+      }
+    }
+  }
+  return false;
+}
+
+/// The node that got split to create this one. Is -1 for the roots.
+fn find_parent(key: EncodedPatch) -> i32 {
+  let u_parent = key.u >> 1u;
+  let v_parent = key.v >> 1u;
+  if (u_parent != 0u) {
+    let index = lod_tree_find(EncodedPatch(u_parent, key.v, key.instance));
+    if (index >= 0 && lod_tree.nodes[index].splits == LOD_SPLIT_U) {
+      return index;
+    }
+  }
+  if (v_parent != 0u) {
+    let index = lod_tree_find(EncodedPatch(key.u, v_parent, key.instance));
+    if (index >= 0 && lod_tree.nodes[index].splits == LOD_SPLIT_V) {
+      return index;
+    }
+  }
+  if (u_parent != 0u && v_parent != 0u) {
+    let index = lod_tree_find(EncodedPatch(u_parent, v_parent, key.instance));
+    if (index >= 0 && lod_tree.nodes[index].splits == (LOD_SPLIT_U | LOD_SPLIT_V)) {
+      return index;
+    }
+  }
+  return -1;
+}
+
+fn write_patch(encoded: EncodedPatch) {
+  let write_index = atomicAdd(&patches_to_buffer.patches_length, 1u);
+  if (write_index < patches_to_buffer.patches_capacity) {
+    atomicAdd(&patches_to_buffer.dispatch_x, 1u);
+    patches_to_buffer.patches[write_index] = encoded;
+  }
+}
+
+const WORKGROUP_SIZE = 64u;
+
+/// Turns the subdivision tree of the last frame into the starting patches of this frame.
+/// Leaves are kept, and splits whose children are all leaves are merged back together.
+/// The LOD stage then splits them again, if they still need to be split.
+/// So every frame, a patch can get one level coarser, or a few levels finer.
+@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
+fn main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+  let index = global_id.x;
+  if (lod_tree.overflowed != 0u) {
+    if (index < reuse_input.instance_count) {
+      write_patch(EncodedPatch(1u, 1u, index));
+    }
+    return;
+  }
+  if (index >= arrayLength(&lod_tree.nodes) || lod_tree.nodes[index].occupied == 0u) {
+    return;
+  }
+
+  let node = lod_tree.nodes[index];
+  if (node.splits != 0u && has_split_child(node.key, node.splits)) {
+    return; // The children take care of themselves
+  }
+  let parent = find_parent(node.key);
+  if (parent >= 0 && !has_split_child(lod_tree.nodes[parent].key, lod_tree.nodes[parent].splits)) {
+    return; // Merged into the parent
+  }
+  write_patch(node.key);
+}
\ No newline at end of file
@@ -10,12 +10,17 @@ fn sampleObject(input: vec2f) -> vec3f {
 //// START getColor
 fn getColor(input: vec2f) -> vec3f {
   if material.has_texture != 0u {
-    return textureSample(t_diffuse, linear_sampler, input * material.texture_scale).rgb;
+    return textureSample(t_diffuse, t_diffuse_sampler, input * material.texture_scale).rgb;
   } else {
     return material.color_roughness.rgb;
   }
 }
 //// END getColor
+//// START sampleNormal
+fn sampleNormal(input: vec2f) -> vec3f {
+  return finiteDifferenceNormal(input, normal_step);
+}
+//// END sampleNormal
 
 // following https://www.martinpalko.com/triplanar-mapping/
 fn calculateTriplanarColor(input: vec3f, normal: vec3f) -> vec3f {
@@ -24,9 +29,9 @@ fn calculateTriplanarColor(input: vec3f, normal: vec3f) -> vec3f {
     let zuv = input.xy;
     let xuv = input.zy;
 
-    let yDiff = textureSample (t_diffuse, linear_sampler, yuv).rgb;
-    let xDiff = textureSample (t_diffuse, linear_sampler, xuv).rgb;
-    let zDiff = textureSample (t_diffuse, linear_sampler, zuv).rgb;
+    let yDiff = textureSample (t_diffuse, t_diffuse_sampler, yuv).rgb;
+    let xDiff = textureSample (t_diffuse, t_diffuse_sampler, xuv).rgb;
+    let zDiff = textureSample (t_diffuse, t_diffuse_sampler, zuv).rgb;
     let blendWeights = normalize(abs(normal));
 
   	return xDiff * blendWeights.x + yDiff * blendWeights.y + zDiff * blendWeights.z;
@@ -36,9 +41,53 @@ fn calculateTriplanarColor(input: vec3f, normal: vec3f) -> vec3f {
 }
 
 var<private> instance_id: u32;
+var<private> instance: Instance;
+/// Step size in (u,v) for the finite differences of the default sampleNormal.
+var<private> normal_step: vec2f;
+
+// The finest render mesh has 16 quads per side, so this is half of a quad.
+const NORMAL_STEP_FACTOR = 1.0 / 32.0;
+// Below this, the finite differences mostly measure floating point noise
+const MIN_NORMAL_STEP = 1e-5;
+
+/// Unnormalized normal from central differences. Close to the borders of the domain, the differences become one-sided.
+fn centralDifferenceNormal(input: vec2f, step: vec2f) -> vec3f {
+  let u_min = max(input.x - step.x, 0.0);
+  let u_max = min(input.x + step.x, 1.0);
+  let v_min = max(input.y - step.y, 0.0);
+  let v_max = min(input.y + step.y, 1.0);
+  let du = sampleObject(vec2f(u_max, input.y)) - sampleObject(vec2f(u_min, input.y));
+  let dv = sampleObject(vec2f(input.x, v_max)) - sampleObject(vec2f(input.x, v_min));
+  let normal = cross(du, dv);
+  // Parallel or zero length derivatives, like at the pole of a sphere
+  if (length(normal) <= 1e-6 * length(du) * length(dv)) {
+    return vec3f(0.0);
+  }
+  return normal;
+}
+
+/// Unnormalized derivative along u, with the same one-sided differences at the borders as the normal.
+fn centralDifferenceTangent(input: vec2f, step: vec2f) -> vec3f {
+  let u_min = max(input.x - step.x, 0.0);
+  let u_max = min(input.x + step.x, 1.0);
+  return sampleObject(vec2f(u_max, input.y)) - sampleObject(vec2f(u_min, input.y));
+}
+
+fn finiteDifferenceNormal(input: vec2f, step: vec2f) -> vec3f {
+  let normal = centralDifferenceNormal(input, step);
+  if (any(normal != vec3f(0.0))) {
+    return normalize(normal);
+  }
+  // Degenerate point. Its neighbours towards the center of the domain have a well defined tangent plane.
+  let nudged_normal = centralDifferenceNormal(input + sign(vec2f(0.5) - input) * step, step);
+  if (any(nudged_normal != vec3f(0.0))) {
+    return normalize(nudged_normal);
+  }
+  return vec3f(0.0, 1.0, 0.0);
+}
 
 ////#include "./Common.wgsl"
-//// AUTOGEN 6de14edf9918265eb2e1232f93b94c84430d0069898214379635e51c3d4c9550
+//// AUTOGEN d5bc2f7e981d9972b3c852bb1248465b910ae2d693454f5ffbc068cff688e21f
 struct EncodedPatch {
   u: u32,
   v: u32,
@@ -50,11 +99,19 @@ struct Patch {
   instance: u32
 };
 struct Patches {
+  // Indirect dispatch arguments for the next round, one workgroup per patch.
+  // They live in the same buffer to save a storage buffer binding.
+  dispatch_x: atomic<u32>,
+  dispatch_y: u32,
+  dispatch_z: u32,
   patches_length: atomic<u32>,
   patches_capacity: u32,
   patches : array<EncodedPatch>,
 };
 struct PatchesRead { // Is currently needed, see https://github.com/gpuweb/gpuweb/discussions/4438
+  dispatch_x: u32,
+  dispatch_y: u32,
+  dispatch_z: u32,
   patches_length: u32, // Same size and alignment as atomic<u32>. Should be legal, right?
   patches_capacity: u32,
   patches : array<EncodedPatch>,
@@ -69,11 +126,84 @@ struct RenderBufferRead {
   patches_capacity: u32,
   patches: array<EncodedPatch>,
 };
-struct DispatchIndirectArgs { // From https://docs.rs/wgpu/latest/wgpu/util/struct.DispatchIndirectArgs.html
-  x: atomic<u32>,
-  y: u32,
-  z: u32,
+// The data of one instance of a model, indexed by the instance of a patch
+struct Instance {
+  // Applied after sampleObject, relative to the model
+  transform: mat4x4<f32>,
+  normal_matrix: mat3x3<f32>,
+  // Multiplies the result of getColor
+  tint: vec3<f32>,
+  // Free for the user code
+  custom: vec4<f32>,
 };
+// Instances are stored in a rgba32uint texture, since the LOD stage has no storage buffer binding left.
+// Every instance takes up INSTANCE_TEXELS texels in the layout of a storage buffer, and a row has INSTANCES_PER_ROW of them.
+const INSTANCE_TEXELS: u32 = 9u;
+const INSTANCES_PER_ROW: u32 = 512u;
+fn instance_texel(instances: texture_2d<u32>, id: u32, texel: u32) -> vec4f {
+  let x = (id % INSTANCES_PER_ROW) * INSTANCE_TEXELS + texel;
+  return bitcast<vec4f>(textureLoad(instances, vec2(x, id / INSTANCES_PER_ROW), 0));
+}
+fn instance_load(instances: texture_2d<u32>, id: u32) -> Instance {
+  return Instance(
+    mat4x4(
+      instance_texel(instances, id, 0u),
+      instance_texel(instances, id, 1u),
+      instance_texel(instances, id, 2u),
+      instance_texel(instances, id, 3u)
+    ),
+    mat3x3(
+      instance_texel(instances, id, 4u).xyz,
+      instance_texel(instances, id, 5u).xyz,
+      instance_texel(instances, id, 6u).xyz
+    ),
+    instance_texel(instances, id, 7u).xyz,
+    instance_texel(instances, id, 8u)
+  );
+}
+// A hash map of the subdivision tree, so that the render stage can find the neighbours of a patch.
+// Every split, every rendered patch and every culled patch gets a node.
+struct LodNode {
+  // Set to 1 by whoever claims the slot
+  occupied: atomic<u32>,
+  key: EncodedPatch,
+  // LOD_SPLIT_U and LOD_SPLIT_V flags, 0 for a patch that got rendered
+  splits: u32,
+  // How many quads a rendered patch has along one side, 0 for a culled patch
+  quads_per_side: u32,
+};
+struct LodTree {
+  // Set when a node did not fit, which means that the tree is incomplete
+  overflowed: atomic<u32>,
+  nodes: array<LodNode>,
+};
+struct LodNodeRead {
+  occupied: u32,
+  key: EncodedPatch,
+  splits: u32,
+  quads_per_side: u32,
+};
+struct LodTreeRead {
+  overflowed: u32,
+  nodes: array<LodNodeRead>,
+};
+const LOD_SPLIT_U: u32 = 1u;
+const LOD_SPLIT_V: u32 = 2u;
+// Linear probing gives up after this many slots
+const LOD_TREE_MAX_PROBES: u32 = 64u;
+fn lod_node_hash(key: EncodedPatch) -> u32 {
+  // Integer hash from https://nullprogram.com/blog/2018/07/31/
+  var hash = (key.u * 0x9e3779b1u) ^ (key.v * 0x85ebca77u) ^ (key.instance * 0xc2b2ae3du);
+  hash ^= hash >> 16u;
+  hash *= 0x7feb352du;
+  hash ^= hash >> 15u;
+  hash *= 0x846ca68bu;
+  hash ^= hash >> 16u;
+  return hash;
+}
+fn lod_node_is(node_key: EncodedPatch, key: EncodedPatch) -> bool {
+  return node_key.u == key.u && node_key.v == key.v && node_key.instance == key.instance;
+}
 fn ceil_div(a: u32, b: u32) -> u32 { return (a + b - 1u) / b; }
 // Inspired from https://onrendering.com/data/papers/isubd/isubd.pdf
 fn patch_u_child(u: u32, child_bit: u32) -> u32 {
@@ -174,6 +304,58 @@ fn mouse_held(button: u32) -> bool {
 //// END OF AUTOGEN
 
 
+////#include "./Shadows.wgsl"
+//// AUTOGEN 2b503b1b36c712c3c9f4af888b9d49b9d1cbb93498b26b23a8f0ef40b93f4943
+struct ShadowCascade {
+  view_projection: mat4x4<f32>,
+  // Size of one shadow map texel in world space, used for the normal offset
+  texel_world_size: f32,
+}
+struct Shadows {
+  cascades: array<ShadowCascade, 3>,
+  // Index into the lights, or NO_SHADOW_LIGHT
+  light_index: u32,
+  // 1.0 / shadow map resolution
+  texel_size: f32,
+}
+const SHADOW_CASCADE_COUNT: u32 = 3;
+const NO_SHADOW_LIGHT: u32 = 0xffffffffu;
+// Reverse Z, so a positive bias moves the surface towards the light
+const SHADOW_DEPTH_BIAS: f32 = 0.0005;
+
+/// 3x3 percentage closer filtering. Each tap is already bilinearly filtered by the comparison sampler.
+fn shadow_pcf(shadow_map: texture_depth_2d_array, shadow_sampler: sampler_comparison, uv: vec2f, cascade: u32, depth: f32, texel_size: f32) -> f32 {
+  var lit = 0.0;
+  for (var y = -1; y <= 1; y += 1) {
+    for (var x = -1; x <= 1; x += 1) {
+      let offset = vec2f(f32(x), f32(y)) * texel_size;
+      lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, depth);
+    }
+  }
+  return lit / 9.0;
+}
+
+/// Returns 1.0 for a fully lit point and 0.0 for a fully shadowed point.
+/// Picks the first cascade that contains the point.
+fn sample_shadow(shadow_map: texture_depth_2d_array, shadow_sampler: sampler_comparison, shadows: Shadows, world_position: vec3f, normal: vec3f) -> f32 {
+  if (shadows.light_index == NO_SHADOW_LIGHT) {
+    return 1.0;
+  }
+  var cascades = shadows.cascades;
+  for (var i = 0u; i < SHADOW_CASCADE_COUNT; i += 1u) {
+    let offset_position = world_position + normal * (cascades[i].texel_world_size * 1.5);
+    let clip_position = cascades[i].view_projection * vec4f(offset_position, 1.0);
+    let ndc = clip_position.xyz / clip_position.w;
+    let uv = ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5);
+    if (all(uv >= vec2f(0.0)) && all(uv <= vec2f(1.0)) && ndc.z >= 0.0 && ndc.z <= 1.0) {
+      return shadow_pcf(shadow_map, shadow_sampler, uv, i, ndc.z + SHADOW_DEPTH_BIAS, shadows.texel_size);
+    }
+  }
+  return 1.0;
+}
+
+//// END OF AUTOGEN
+
 alias Vec3Padded = vec4<f32>;
 
 struct Camera {
@@ -197,7 +379,6 @@ struct LightSource {
 }
 
 struct Lights {
-    ambient: Vec3Padded,
     // TODO: Directional light
     points_length: u32,
     points: array<LightSource>,
@@ -211,8 +392,33 @@ struct VertexInput {
 }
 
 struct Model {
-    model_similarity: mat4x4<f32>,
-    object_id: u32
+    model_matrix: mat4x4<f32>,
+    object_id: u32,
+    // is a boolean
+    receive_shadows: u32,
+    // Inverse transpose of the model matrix, for non-uniform scaling
+    normal_matrix: mat3x3<f32>,
+}
+
+// Visualizations of the LOD algorithm, see DebugView in game.rs
+const DEBUG_VIEW_NONE: u32 = 0;
+const DEBUG_VIEW_WIREFRAME: u32 = 1;
+const DEBUG_VIEW_PATCH_BOUNDARIES: u32 = 2;
+const DEBUG_VIEW_PATCH_SIZE: u32 = 3;
+const DEBUG_VIEW_UV: u32 = 4;
+const DEBUG_VIEW_NORMALS: u32 = 5;
+const DEBUG_VIEW_INSTANCE_ID: u32 = 6;
+
+struct Debug {
+    view: u32,
+}
+
+// One per patch size, since each patch size is drawn separately
+struct PatchInfo {
+    // The render size of the patches, 2, 4, 8, 16 or 32
+    size: u32,
+    // How many quads the render mesh has along one side
+    quads_per_side: u32,
 }
 
 struct Material {
@@ -224,16 +430,60 @@ struct Material {
     emissive_metallic: vec4<f32>,
     // is a boolean
     has_texture: u32,
-    texture_scale: vec2f
+    texture_scale: vec2f,
+    // 1.0 is fully opaque
+    opacity: f32,
+    // is a boolean, multiplies the opacity with the alpha of the texture
+    alpha_from_texture: u32,
+    // are booleans, for the optional glTF-style texture maps
+    has_normal_texture: u32,
+    has_metallic_roughness_texture: u32,
+    has_occlusion_texture: u32,
+    has_emissive_texture: u32,
 }
 
 @group(0) @binding(4) var<uniform> camera: Camera;
 @group(0) @binding(5) var<storage, read> lights: Lights;
 @group(0) @binding(6) var linear_sampler: sampler;
+@group(0) @binding(7) var<uniform> shadows: Shadows;
+@group(0) @binding(8) var shadow_map: texture_depth_2d_array;
+@group(0) @binding(9) var shadow_sampler: sampler_comparison;
+@group(0) @binding(10) var irradiance_map: texture_cube<f32>;
+@group(0) @binding(11) var prefiltered_map: texture_cube<f32>;
+@group(0) @binding(12) var brdf_lut: texture_2d<f32>;
+@group(0) @binding(13) var environment_sampler: sampler;
+
+@group(0) @binding(14) var<uniform> debug: Debug;
+
+// Has to match the number of mip levels in environment.rs
+const PREFILTERED_MIP_COUNT: u32 = 5;
 @group(1) @binding(1) var<uniform> model: Model;
 @group(1) @binding(2) var<storage, read> render_buffer: RenderBufferRead;
 @group(1) @binding(3) var<uniform> material: Material;
 @group(1) @binding(4) var t_diffuse: texture_2d<f32>;
+@group(1) @binding(5) var<uniform> patch_info: PatchInfo;
+@group(1) @binding(6) var t_diffuse_sampler: sampler;
+// Tangent space normal map
+@group(1) @binding(7) var t_normal: texture_2d<f32>;
+@group(1) @binding(8) var t_normal_sampler: sampler;
+// Roughness in the green channel, metallic in the blue channel
+@group(1) @binding(9) var t_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(10) var t_metallic_roughness_sampler: sampler;
+// Ambient occlusion in the red channel
+@group(1) @binding(11) var t_occlusion: texture_2d<f32>;
+@group(1) @binding(12) var t_occlusion_sampler: sampler;
+@group(1) @binding(13) var t_emissive: texture_2d<f32>;
+@group(1) @binding(14) var t_emissive_sampler: sampler;
+@group(1) @binding(15) var<storage, read> lod_tree: LodTreeRead;
+@group(1) @binding(16) var<uniform> parameters: Parameters;
+@group(1) @binding(17) var instances: texture_2d<u32>;
+
+//// START Parameters
+// Replaced with the `// @param` annotations of the user code
+struct Parameters {
+  unused: u32,
+}
+//// END Parameters
 
 
 
@@ -558,7 +808,11 @@ struct VertexOutput {
     @location(0) world_normal: vec3<f32>,
     @location(1) world_position: vec3<f32>,
     @location(2) texture_coords: vec2<f32>,
-    @location(3) color: vec4<f32>,
+    // (u,v) inside of the patch
+    @location(3) patch_coords: vec2<f32>,
+    @location(4) @interpolate(flat) instance_id: u32,
+    // Points along the u direction of the surface, for normal mapping
+    @location(5) world_tangent: vec3<f32>,
 }
 
 const color_options = array<vec4f,8>(
@@ -573,47 +827,204 @@ const color_options = array<vec4f,8>(
 );
 
 
+// Bounds the walk down the subdivision tree
+const LOD_TREE_MAX_DEPTH: u32 = 32u;
+// A step just outside of a patch. Small enough to never skip over a patch, and still exact in a f32.
+const LOD_TREE_EPSILON: f32 = 1.0 / 1048576.0;
+
+fn lod_tree_find(key: EncodedPatch) -> i32 {
+    let capacity = arrayLength(&lod_tree.nodes);
+    let hash = lod_node_hash(key);
+    for (var i = 0u; i < LOD_TREE_MAX_PROBES; i += 1u) {
+        let index = (hash + i) % capacity;
+        if (lod_tree.nodes[index].occupied == 0u) {
+            return -1;
+        }
+        if (lod_node_is(lod_tree.nodes[index].key, key)) {
+            return i32(index);
+        }
+    }
+    return -1;
+}
+
+/// Walks down the subdivision tree to the rendered patch that covers the point.
+/// Returns the distance between its vertices, or 0 if no patch was rendered there.
+fn vertex_spacing_at(point: vec2f, instance: u32) -> vec2f {
+    if (any(point < vec2f(0.0)) || any(point > vec2f(1.0))) {
+        return vec2f(0.0);
+    }
+    var key = EncodedPatch(1u, 1u, instance);
+    for (var depth = 0u; depth < LOD_TREE_MAX_DEPTH; depth += 1u) {
+        let index = lod_tree_find(key);
+        if (index < 0) {
+            return vec2f(0.0);
+        }
+        let node = lod_tree.nodes[index];
+        let quad = patch_decode(key);
+        if (node.splits == 0u) {
+            if (node.quads_per_side == 0u) {
+                // Culled
+                return vec2f(0.0);
+            }
+            return (quad.max - quad.min) / f32(node.quads_per_side);
+        }
+        let center = (quad.min + quad.max) * 0.5;
+        if ((node.splits & LOD_SPLIT_U) != 0u) {
+            key.u = patch_u_child(key.u, u32(point.x >= center.x));
+        }
+        if ((node.splits & LOD_SPLIT_V) != 0u) {
+            key.v = patch_u_child(key.v, u32(point.y >= center.y));
+        }
+    }
+    return vec2f(0.0);
+}
+
+// A vertex on the edge of a patch gets moved onto the straight line from a to b,
+// which is exactly where the coarser neighbouring patch draws its edge.
+struct EdgeStitch {
+    a: vec2f,
+    b: vec2f,
+    t: f32,
+}
+
+/// Avoids T-junctions between patches of different resolutions.
+/// Both sides of an edge agree on the coarser of their two vertex spacings. Spacings are powers of two,
+/// so the vertices of the finer side either lie on the coarser grid, or get moved between two of its vertices.
+fn stitch_edge_vertex(quad: Patch, uv: vec2f) -> EdgeStitch {
+    let quad_size = quad.max - quad.min;
+    let point = quad.min + quad_size * uv;
+    let spacing = quad_size / f32(patch_info.quads_per_side);
+    // Points just outside of the patch. At the corners, we look at the neighbour that shares the most of our edge.
+    let inside = clamp(point, quad.min + LOD_TREE_EPSILON, quad.max - LOD_TREE_EPSILON);
+    let outside = select(quad.max + LOD_TREE_EPSILON, quad.min - LOD_TREE_EPSILON, uv == vec2f(0.0));
+
+    if (uv.x == 0.0 || uv.x == 1.0) {
+        let neighbour = vertex_spacing_at(vec2f(outside.x, inside.y), quad.instance).y;
+        if (neighbour > spacing.y) {
+            let start = floor(point.y / neighbour) * neighbour;
+            let t = (point.y - start) / neighbour;
+            if (t != 0.0) {
+                return EdgeStitch(vec2f(point.x, start), vec2f(point.x, start + neighbour), t);
+            }
+        }
+    }
+    if (uv.y == 0.0 || uv.y == 1.0) {
+        let neighbour = vertex_spacing_at(vec2f(inside.x, outside.y), quad.instance).x;
+        if (neighbour > spacing.x) {
+            let start = floor(point.x / neighbour) * neighbour;
+            let t = (point.x - start) / neighbour;
+            if (t != 0.0) {
+                return EdgeStitch(vec2f(start, point.y), vec2f(start + neighbour, point.y), t);
+            }
+        }
+    }
+    return EdgeStitch(point, point, 0.0);
+}
+
 @vertex
 fn vs_main(
     in: VertexInput,
 ) -> VertexOutput {
     let quad = patch_decode(render_buffer.patches[in.instance_index]);
-    let quad_point = mix(quad.min, quad.max, in.uv);
     instance_id = quad.instance;
-    let pos = sampleObject(quad_point);
-    let world_pos = model.model_similarity * vec4<f32>(pos, 1.0);
+    instance = instance_load(instances, instance_id);
+    normal_step = max((quad.max - quad.min) * NORMAL_STEP_FACTOR, vec2f(MIN_NORMAL_STEP));
+    let stitch = stitch_edge_vertex(quad, in.uv);
+    let quad_point = mix(stitch.a, stitch.b, stitch.t);
+    var pos = sampleObject(stitch.a);
+    var normal = sampleNormal(stitch.a);
+    if (stitch.t != 0.0) {
+        pos = mix(pos, sampleObject(stitch.b), stitch.t);
+        normal = mix(normal, sampleNormal(stitch.b), stitch.t);
+    }
+    let model_matrix = model.model_matrix * instance.transform;
+    let world_pos = model_matrix * vec4<f32>(pos, 1.0);
 
 
     var out: VertexOutput;
     out.clip_position = camera.projection * camera.view * world_pos;
     out.world_position = world_pos.xyz;
     out.texture_coords = quad_point;
-    let normal = vec3<f32>(0.0, -1.0, 0.0); // TODO: We'll compute this later
-    out.world_normal = (model.model_similarity * vec4<f32>(normal, 0.0)).xyz; // Only uniform scaling
-
-    let i = in.instance_index % 8;
-    if (i == 0) {
-    out.color = color_options[0];
-    } else if (i == 1) {
-    out.color = color_options[1];
-    } else if (i == 2) {
-    out.color = color_options[2];
-    } else if (i == 3) {
-    out.color = color_options[3];
-    } else if (i == 4) {
-    out.color = color_options[4];
-    } else if (i == 5) {
-    out.color = color_options[5];
-    } else if (i == 6) {
-    out.color = color_options[6];
-    } else if (i == 7) {
-    out.color = color_options[7];
-    }else {
-    out.color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    out.world_normal = model.normal_matrix * instance.normal_matrix * normal;
+    out.patch_coords = in.uv;
+    out.instance_id = quad.instance;
+    if (material.has_normal_texture != 0u) {
+        let tangent = centralDifferenceTangent(quad_point, normal_step);
+        out.world_tangent = (model_matrix * vec4<f32>(tangent, 0.0)).xyz;
     }
     return out;
 }
 
+/// Applies the normal map, with a tangent frame from the derivatives of the surface.
+fn perturb_normal(n: vec3f, world_tangent: vec3f, sampled: vec3f) -> vec3f {
+    // Gram-Schmidt, since the interpolated tangent is not quite orthogonal to the normal
+    var t = world_tangent - n * dot(n, world_tangent);
+    if (dot(t, t) < 1e-12) {
+        // Degenerate u derivative, like at the pole of a sphere. Any tangent is better than none.
+        t = cross(n, select(vec3f(1.0, 0.0, 0.0), vec3f(0.0, 1.0, 0.0), abs(n.x) > 0.9));
+    }
+    t = normalize(t);
+    let b = cross(n, t);
+    let tangent_normal = sampled * 2.0 - 1.0;
+    return normalize(t * tangent_normal.x + b * tangent_normal.y + n * tangent_normal.z);
+}
+
+// Colors for the patch sizes 2, 4, 8, 16 and 32, from coarse to fine
+const patch_size_colors = array<vec3f,5>(
+    vec3f(0.0, 0.0, 1.0),
+    vec3f(0.0, 1.0, 1.0),
+    vec3f(0.0, 1.0, 0.0),
+    vec3f(1.0, 1.0, 0.0),
+    vec3f(1.0, 0.0, 0.0),
+);
+
+/// 1.0 on the lines of a grid with the given number of cells, and 0.0 elsewhere. Lines are about one pixel wide.
+fn grid_lines(coords: vec2f, cells: f32, with_diagonals: bool) -> f32 {
+    let p = coords * cells;
+    let distance = abs(fract(p - 0.5) - 0.5) / fwidth(p);
+    var line_distance = min(distance.x, distance.y);
+    if (with_diagonals) {
+        // The render mesh splits each quad along the diagonal where u + v is a whole number
+        let diagonal = p.x + p.y;
+        line_distance = min(line_distance, abs(fract(diagonal - 0.5) - 0.5) / fwidth(diagonal));
+    }
+    return 1.0 - clamp(line_distance, 0.0, 1.0);
+}
+
+fn debug_color(in: VertexOutput, n: vec3f, v: vec3f, shaded: vec3f) -> vec3f {
+    // Keeps the shape readable for the flat colors
+    let shading = 0.3 + 0.7 * clamped_dot(n, v);
+    switch debug.view {
+        case DEBUG_VIEW_WIREFRAME: {
+            // Also works when the pipeline only rasterizes the lines
+            let line = grid_lines(in.patch_coords, f32(patch_info.quads_per_side), true);
+            return mix(shaded * 0.2, vec3f(1.0), line);
+        }
+        case DEBUG_VIEW_PATCH_BOUNDARIES: {
+            let line = grid_lines(in.patch_coords, 1.0, false);
+            return mix(shaded, vec3f(1.0, 0.2, 0.0), line);
+        }
+        case DEBUG_VIEW_PATCH_SIZE: {
+            let index = min(firstTrailingBit(patch_info.size), 5u) - 1u;
+            var colors = patch_size_colors;
+            return colors[index] * shading;
+        }
+        case DEBUG_VIEW_UV: {
+            return vec3f(in.texture_coords, 0.0) * shading;
+        }
+        case DEBUG_VIEW_NORMALS: {
+            return n * 0.5 + 0.5;
+        }
+        case DEBUG_VIEW_INSTANCE_ID: {
+            var colors = color_options;
+            return colors[in.instance_id % 8u].rgb * shading;
+        }
+        default: {
+            return shaded;
+        }
+    }
+}
+
 struct FragmentOutput {
   @location(0) color: vec4f,
   @location(1) object_id: u32,
@@ -625,19 +1036,60 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
       // silly hack to get the auto layout to pick up on the uniforms
       let a = sampleObject(vec2f(0.0)); 
     }
+    instance_id = in.instance_id;
+    instance = instance_load(instances, instance_id);
+    // Sampled before any non-uniform control flow
+    let uv = in.texture_coords * material.texture_scale;
+    let texture_alpha = textureSample(t_diffuse, t_diffuse_sampler, uv).a;
+    let sampled_normal = textureSample(t_normal, t_normal_sampler, uv).rgb;
+    let sampled_metallic_roughness = textureSample(t_metallic_roughness, t_metallic_roughness_sampler, uv).rgb;
+    let sampled_occlusion = textureSample(t_occlusion, t_occlusion_sampler, uv).r;
+    let sampled_emissive = textureSample(t_emissive, t_emissive_sampler, uv).rgb;
+    var alpha = material.opacity;
+    if (material.alpha_from_texture != 0u && material.has_texture != 0u) {
+        alpha *= texture_alpha;
+    }
+
     let v = normalize(camera.world_position.xyz - in.world_position);
-    // let n = normalize(in.world_normal);
-    let n = normalize(-cross(dpdxFine(in.world_position), dpdyFine(in.world_position)));
+    var n = normalize(in.world_normal);
+    // Parametric surfaces are rendered double-sided, so light the side facing the camera
+    if (dot(n, v) < 0.0) {
+      n = -n;
+    }
+    if (material.has_normal_texture != 0u) {
+      n = perturb_normal(n, in.world_tangent, sampled_normal);
+    }
+
+    // Same convention as glTF, where the texture values multiply the factors
+    var metallic = material.emissive_metallic.a;
+    var roughness = material.color_roughness.a;
+    if (material.has_metallic_roughness_texture != 0u) {
+      roughness *= sampled_metallic_roughness.g;
+      metallic *= sampled_metallic_roughness.b;
+    }
+    var emissive = material.emissive_metallic.rgb;
+    if (material.has_emissive_texture != 0u) {
+      emissive *= sampled_emissive;
+    }
+    var occlusion = 1.0;
+    if (material.has_occlusion_texture != 0u) {
+      occlusion = sampled_occlusion;
+    }
 
     var materialInfo = MaterialInfo(
-        getColor(in.texture_coords),
+        getColor(in.texture_coords) * instance.tint,
         vec3f(0.04),
         vec3f(1.0),
         vec3f(0.0),
         1.0,
         1.0
     );
-    materialInfo = getMetallicRoughnessInfo(materialInfo, material.emissive_metallic.a, material.color_roughness.a);
+    materialInfo = getMetallicRoughnessInfo(materialInfo, metallic, roughness);
+
+    var shadow = 1.0;
+    if (model.receive_shadows != 0u) {
+        shadow = sample_shadow(shadow_map, shadow_sampler, shadows, in.world_position, n);
+    }
 
     var f_diffuse = vec3f(0.0);
     var f_specular = vec3f(0.0);
@@ -656,7 +1108,10 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
 
         let l = normalize(pointToLight); // Direction from surface point to light
         let h = normalize(l + v);        // Direction of the vector between l and v, called halfway vector
-        let intensity: vec3f = getLighIntensity(light, pointToLight);
+        var intensity: vec3f = getLighIntensity(light, pointToLight);
+        if (i == shadows.light_index) {
+            intensity *= shadow;
+        }
         let NdotL = clamped_dot(n, l);
         if(NdotL > 0.0) {
             let NdotV = clamped_dot(n, v);
@@ -682,18 +1137,29 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
         }
     }
 
-    let ambient: vec3f = lights.ambient.rgb * materialInfo.baseColor;
+    // Image based lighting, with the split sum approximation
+    let NdotV = clamped_dot(n, v);
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2f(NdotV, roughness), 0.0).rg;
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, n, 0.0).rgb;
+    let prefiltered = textureSampleLevel(
+        prefiltered_map,
+        environment_sampler,
+        reflect(-v, n),
+        roughness * f32(PREFILTERED_MIP_COUNT - 1u)
+    ).rgb;
+    let ambient: vec3f = (irradiance * materialInfo.c_diff
+        + prefiltered * (materialInfo.f0 * brdf.x + materialInfo.f90 * brdf.y) * materialInfo.specularWeight) * occlusion;
 
     let color = f_diffuse * 2.0 
         + f_specular * 2.0 
         + ambient 
-        + material.emissive_metallic.rgb;
+        + emissive;
 
     var fragmentOutput: FragmentOutput;
-    fragmentOutput.color = vec4f(color, 1.0);
+    // Premultiplied alpha
+    fragmentOutput.color = vec4f(debug_color(in, n, v, color) * alpha, alpha);
     fragmentOutput.object_id = model.object_id;
     return fragmentOutput;
-    // return in.color; TODO: Why does this cause z-buffer fighting?
 }
 
  
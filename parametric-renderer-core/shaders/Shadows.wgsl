@@ -0,0 +1,47 @@
+struct ShadowCascade {
+  view_projection: mat4x4<f32>,
+  // Size of one shadow map texel in world space, used for the normal offset
+  texel_world_size: f32,
+}
+struct Shadows {
+  cascades: array<ShadowCascade, 3>,
+  // Index into the lights, or NO_SHADOW_LIGHT
+  light_index: u32,
+  // 1.0 / shadow map resolution
+  texel_size: f32,
+}
+const SHADOW_CASCADE_COUNT: u32 = 3;
+const NO_SHADOW_LIGHT: u32 = 0xffffffffu;
+// Reverse Z, so a positive bias moves the surface towards the light
+const SHADOW_DEPTH_BIAS: f32 = 0.0005;
+
+/// 3x3 percentage closer filtering. Each tap is already bilinearly filtered by the comparison sampler.
+fn shadow_pcf(shadow_map: texture_depth_2d_array, shadow_sampler: sampler_comparison, uv: vec2f, cascade: u32, depth: f32, texel_size: f32) -> f32 {
+  var lit = 0.0;
+  for (var y = -1; y <= 1; y += 1) {
+    for (var x = -1; x <= 1; x += 1) {
+      let offset = vec2f(f32(x), f32(y)) * texel_size;
+      lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, depth);
+    }
+  }
+  return lit / 9.0;
+}
+
+/// Returns 1.0 for a fully lit point and 0.0 for a fully shadowed point.
+/// Picks the first cascade that contains the point.
+fn sample_shadow(shadow_map: texture_depth_2d_array, shadow_sampler: sampler_comparison, shadows: Shadows, world_position: vec3f, normal: vec3f) -> f32 {
+  if (shadows.light_index == NO_SHADOW_LIGHT) {
+    return 1.0;
+  }
+  var cascades = shadows.cascades;
+  for (var i = 0u; i < SHADOW_CASCADE_COUNT; i += 1u) {
+    let offset_position = world_position + normal * (cascades[i].texel_world_size * 1.5);
+    let clip_position = cascades[i].view_projection * vec4f(offset_position, 1.0);
+    let ndc = clip_position.xyz / clip_position.w;
+    let uv = ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5);
+    if (all(uv >= vec2f(0.0)) && all(uv <= vec2f(1.0)) && ndc.z >= 0.0 && ndc.z <= 1.0) {
+      return shadow_pcf(shadow_map, shadow_sampler, uv, i, ndc.z + SHADOW_DEPTH_BIAS, shadows.texel_size);
+    }
+  }
+  return 1.0;
+}
@@ -1,11 +1,13 @@
 struct Uniforms {
     view_projection_matrix: mat4x4f,
-    background_color: vec4f,
-    sun_direction: vec4f,
 }
 
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
+@group(0) @binding(1)
+var environment: texture_cube<f32>;
+@group(0) @binding(2)
+var environment_sampler: sampler;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -42,9 +44,7 @@ fn vs_main(
 fn fs_main(
     in: VertexOutput
 ) -> FragmentOutput {
-    let ambient_light = 1.0;
-    let directional_light = pow(clamp(dot(normalize(in.tex_coords), uniforms.sun_direction.xyz), 0.0, 1.0), 3.0) * 0.4;
-    let color = uniforms.background_color.rgb * (ambient_light + directional_light);
+    let color = textureSampleLevel(environment, environment_sampler, normalize(in.tex_coords), 0.0).rgb;
     var output: FragmentOutput;
     output.color = vec4f(color, 1.0);
     return output;
@@ -0,0 +1,199 @@
+// Standard library for parametric shaders, which is available in every user shader.
+// Everything is prefixed with `std_` or `STD_`, so that it does not collide with user code.
+// Bump STD_VERSION when a function changes its behaviour.
+
+const STD_VERSION: u32 = 1u;
+
+// Math constants
+
+const STD_PI: f32 = 3.14159265358979;
+const STD_TAU: f32 = 6.28318530717959;
+const STD_HALF_PI: f32 = 1.57079632679490;
+const STD_E: f32 = 2.71828182845905;
+
+// Rotations and transforms. Angles are in radians.
+
+fn std_rotate_x(p: vec3f, angle: f32) -> vec3f {
+  let c = cos(angle);
+  let s = sin(angle);
+  return vec3f(p.x, c * p.y - s * p.z, s * p.y + c * p.z);
+}
+
+fn std_rotate_y(p: vec3f, angle: f32) -> vec3f {
+  let c = cos(angle);
+  let s = sin(angle);
+  return vec3f(c * p.x + s * p.z, p.y, -s * p.x + c * p.z);
+}
+
+fn std_rotate_z(p: vec3f, angle: f32) -> vec3f {
+  let c = cos(angle);
+  let s = sin(angle);
+  return vec3f(c * p.x - s * p.y, s * p.x + c * p.y, p.z);
+}
+
+/// Rotates around an axis through the origin, which does not have to be normalized
+fn std_rotate_axis(p: vec3f, axis: vec3f, angle: f32) -> vec3f {
+  let k = normalize(axis);
+  let c = cos(angle);
+  let s = sin(angle);
+  return p * c + cross(k, p) * s + k * dot(k, p) * (1.0 - c);
+}
+
+/// Scales, then rotates around x, y and z in that order, then translates
+fn std_transform(p: vec3f, translation: vec3f, rotation: vec3f, scale: vec3f) -> vec3f {
+  let rotated = std_rotate_z(std_rotate_y(std_rotate_x(p * scale, rotation.x), rotation.y), rotation.z);
+  return rotated + translation;
+}
+
+// Noise
+
+fn std_mod289(x: vec4f) -> vec4f {
+  return x - floor(x * (1.0 / 289.0)) * 289.0;
+}
+
+fn std_permute(x: vec4f) -> vec4f {
+  return std_mod289(((x * 34.0) + 10.0) * x);
+}
+
+fn std_taylor_inv_sqrt(r: vec4f) -> vec4f {
+  return 1.79284291400159 - 0.85373472095314 * r;
+}
+
+fn std_fade(t: vec2f) -> vec2f {
+  return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+/// Classic Perlin noise, roughly in [-1, 1]
+fn std_gradient_noise(P: vec2f) -> f32 {
+  var Pi = floor(P.xyxy) + vec4f(0.0, 0.0, 1.0, 1.0);
+  let Pf = fract(P.xyxy) - vec4f(0.0, 0.0, 1.0, 1.0);
+  Pi = std_mod289(Pi);
+  let ix = Pi.xzxz;
+  let iy = Pi.yyww;
+  let fx = Pf.xzxz;
+  let fy = Pf.yyww;
+
+  let i = std_permute(std_permute(ix) + iy);
+
+  var gx = fract(i * (1.0 / 41.0)) * 2.0 - 1.0;
+  let gy = abs(gx) - 0.5;
+  let tx = floor(gx + 0.5);
+  gx = gx - tx;
+
+  var g00 = vec2f(gx.x, gy.x);
+  var g10 = vec2f(gx.y, gy.y);
+  var g01 = vec2f(gx.z, gy.z);
+  var g11 = vec2f(gx.w, gy.w);
+
+  let norm = std_taylor_inv_sqrt(vec4f(dot(g00, g00), dot(g01, g01), dot(g10, g10), dot(g11, g11)));
+  g00 *= norm.x;
+  g01 *= norm.y;
+  g10 *= norm.z;
+  g11 *= norm.w;
+
+  let n00 = dot(g00, vec2f(fx.x, fy.x));
+  let n10 = dot(g10, vec2f(fx.y, fy.y));
+  let n01 = dot(g01, vec2f(fx.z, fy.z));
+  let n11 = dot(g11, vec2f(fx.w, fy.w));
+
+  let fade_xy = std_fade(Pf.xy);
+  let n_x = mix(vec2f(n00, n01), vec2f(n10, n11), fade_xy.x);
+  let n_xy = mix(n_x.x, n_x.y, fade_xy.y);
+  return 2.3 * n_xy;
+}
+
+/// Pseudo random value in [0, 1) for each point
+fn std_hash(p: vec2f) -> f32 {
+  let q = fract(p * vec2f(123.34, 456.21));
+  let r = q + dot(q, q + 45.32);
+  return fract(r.x * r.y);
+}
+
+/// Smoothly interpolated random values on a grid, in [0, 1]
+fn std_value_noise(p: vec2f) -> f32 {
+  let i = floor(p);
+  let f = std_fade(fract(p));
+  let a = std_hash(i);
+  let b = std_hash(i + vec2f(1.0, 0.0));
+  let c = std_hash(i + vec2f(0.0, 1.0));
+  let d = std_hash(i + vec2f(1.0, 1.0));
+  return mix(mix(a, b, f.x), mix(c, d, f.x), f.y);
+}
+
+/// Sums octaves of gradient noise, where each octave has double the frequency and half the amplitude
+fn std_fbm(p: vec2f, octaves: u32) -> f32 {
+  var result = 0.0;
+  var amplitude = 0.5;
+  var frequency = 1.0;
+  for (var i = 0u; i < octaves; i++) {
+    result += amplitude * std_gradient_noise(p * frequency);
+    amplitude *= 0.5;
+    frequency *= 2.0;
+  }
+  return result;
+}
+
+// Primitive surfaces. They take the (u, v) input of sampleObject, with z pointing up.
+
+fn std_sphere(input: vec2f, radius: f32) -> vec3f {
+  let polar = input.x * STD_PI;
+  let azimuth = input.y * STD_TAU;
+  return radius * vec3f(sin(polar) * cos(azimuth), sin(polar) * sin(azimuth), cos(polar));
+}
+
+/// A ring around the z axis
+fn std_torus(input: vec2f, major_radius: f32, minor_radius: f32) -> vec3f {
+  let around = input.x * STD_TAU;
+  let tube = input.y * STD_TAU;
+  let distance = major_radius + minor_radius * cos(tube);
+  return vec3f(distance * cos(around), distance * sin(around), minor_radius * sin(tube));
+}
+
+/// Rotates a profile around the z axis. The profile is a (radius, height) pair for the v coordinate.
+fn std_revolve(u: f32, profile: vec2f) -> vec3f {
+  let angle = u * STD_TAU;
+  return vec3f(profile.x * cos(angle), profile.x * sin(angle), profile.y);
+}
+
+/// The side of a cylinder, from z = 0 to z = height
+fn std_cylinder(input: vec2f, radius: f32, height: f32) -> vec3f {
+  return std_revolve(input.x, vec2f(radius, input.y * height));
+}
+
+/// The side of a cone, with the base at z = 0 and the tip at z = height
+fn std_cone(input: vec2f, radius: f32, height: f32) -> vec3f {
+  return std_revolve(input.x, vec2f(radius * (1.0 - input.y), input.y * height));
+}
+
+/// A square in the xy plane, centered at the origin
+fn std_plane(input: vec2f, size: f32) -> vec3f {
+  return vec3f((input - 0.5) * size, 0.0);
+}
+
+// Blending and morphing
+
+/// Like mix, but eases in and out
+fn std_morph(a: vec3f, b: vec3f, t: f32) -> vec3f {
+  return mix(a, b, smoothstep(0.0, 1.0, t));
+}
+
+/// Goes from 0 to 1 and back with a period of one second, for animations with `time.elapsed`
+fn std_ping_pong(t: f32) -> f32 {
+  return 0.5 - 0.5 * cos(t * STD_TAU);
+}
+
+/// A minimum without a sharp crease, where k is the size of the smooth region
+fn std_smooth_min(a: f32, b: f32, k: f32) -> f32 {
+  let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+  return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+fn std_smooth_max(a: f32, b: f32, k: f32) -> f32 {
+  return -std_smooth_min(-a, -b, k);
+}
+
+/// Blends between surfaces with weights that do not have to add up to one
+fn std_blend3(a: vec3f, b: vec3f, c: vec3f, weights: vec3f) -> vec3f {
+  let w = weights / max(weights.x + weights.y + weights.z, 1e-6);
+  return a * w.x + b * w.y + c * w.z;
+}
@@ -0,0 +1,110 @@
+// Turns the HDR image into the final image on the screen
+
+const TONE_MAPPING_NONE: u32 = 0u;
+const TONE_MAPPING_ACES: u32 = 1u;
+const TONE_MAPPING_AGX: u32 = 2u;
+
+struct ToneMappingParams {
+  // Linear multiplier, from the exposure in stops
+  exposure: f32,
+  tone_mapping: u32,
+  // is a boolean
+  has_lut: u32,
+}
+
+@group(0) @binding(0) var hdr: texture_2d<f32>;
+@group(0) @binding(1) var hdr_sampler: sampler;
+@group(0) @binding(2) var<uniform> params: ToneMappingParams;
+// Color grading lookup table, from sRGB encoded colors to sRGB encoded colors
+@group(0) @binding(3) var lut: texture_3d<f32>;
+@group(0) @binding(4) var lut_sampler: sampler;
+
+struct VertexOutput {
+  @builtin(position) position: vec4f,
+  @location(0) uv: vec2f,
+}
+
+// A single triangle that covers the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+  let uv = vec2f(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+  var out: VertexOutput;
+  out.position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+  out.uv = uv;
+  return out;
+}
+
+// Fit by Krzysztof Narkowicz, see https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+fn aces(color: vec3f) -> vec3f {
+  let x = color * 0.6;
+  return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3f(0.0), vec3f(1.0));
+}
+
+// Minimal AgX by Benjamin Wrensch, see https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx_contrast(x: vec3f) -> vec3f {
+  let x2 = x * x;
+  let x4 = x2 * x2;
+  return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3f) -> vec3f {
+  let min_ev = -12.47393;
+  let max_ev = 4.026069;
+  let agx_mat = mat3x3f(
+    0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+    0.0784335999999992, 0.878468636469772, 0.0784336,
+    0.0792237451477643, 0.0791661274605434, 0.879142973793104
+  );
+  let agx_mat_inv = mat3x3f(
+    1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+    -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+    -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+  );
+  var c = agx_mat * color;
+  c = clamp(log2(max(c, vec3f(1e-10))), vec3f(min_ev), vec3f(max_ev));
+  c = (c - min_ev) / (max_ev - min_ev);
+  c = agx_contrast(c);
+  c = agx_mat_inv * c;
+  // Back from the display encoding to linear, since the render target does the sRGB encoding
+  return pow(max(c, vec3f(0.0)), vec3f(2.2));
+}
+
+fn linear_to_srgb(color: vec3f) -> vec3f {
+  let low = color * 12.92;
+  let high = 1.055 * pow(color, vec3f(1.0 / 2.4)) - 0.055;
+  return select(high, low, color <= vec3f(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3f) -> vec3f {
+  let low = color / 12.92;
+  let high = pow((color + 0.055) / 1.055, vec3f(2.4));
+  return select(high, low, color <= vec3f(0.04045));
+}
+
+fn color_grading(color: vec3f) -> vec3f {
+  let size = f32(textureDimensions(lut).x);
+  let encoded = linear_to_srgb(clamp(color, vec3f(0.0), vec3f(1.0)));
+  // Sample the centers of the outermost texels for 0.0 and 1.0
+  let coords = encoded * ((size - 1.0) / size) + 0.5 / size;
+  return srgb_to_linear(textureSampleLevel(lut, lut_sampler, coords, 0.0).rgb);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+  var color = textureSampleLevel(hdr, hdr_sampler, in.uv, 0.0).rgb * params.exposure;
+  switch params.tone_mapping {
+    case TONE_MAPPING_ACES: {
+      color = aces(color);
+    }
+    case TONE_MAPPING_AGX: {
+      color = agx(color);
+    }
+    default: {
+      color = clamp(color, vec3f(0.0), vec3f(1.0));
+    }
+  }
+  if (params.has_lut != 0u) {
+    color = color_grading(color);
+  }
+  return vec4f(color, 1.0);
+}
@@ -7,9 +7,14 @@ use renderer_core::{
         camera_controller::{self, CameraController, IsCameraController},
         orbitcam_controller::LogarithmicDistance,
     },
-    game::{ModelInfo, ShaderId, ShaderInfo, TextureData, TextureId, TextureInfo},
+    color_grading::ColorGradingLut,
+    game::{
+        EnvironmentInfo, LightInfo, ModelInfo, ShaderId, ShaderInfo, TextureData, TextureId,
+        TextureInfo,
+    },
+    hdr::HdrImage,
     input::WinitAppHelper,
-    time::TimeStats,
+    time::{Seconds, TimeStats},
 };
 use std::sync::{Arc, Mutex};
 use wasm_bindgen::{JsError, JsValue, prelude::wasm_bindgen};
@@ -17,7 +22,9 @@ use web_sys::{HtmlCanvasElement, ImageBitmap};
 use winit::event_loop::{EventLoop, EventLoopProxy};
 
 use crate::wasm_abi::{
-    WasmCompilationMessage, WasmFrameTime, WasmModelInfo, WasmPosition, WasmShaderInfo,
+    WasmClockMode, WasmCompilationMessage, WasmDebugView, WasmFrameTime, WasmLightInfo,
+    WasmLodKernel, WasmLodMode, WasmModelInfo, WasmParameterValues, WasmPosition,
+    WasmPostProcessInfo, WasmSamplerInfo, WasmShaderInfo, WasmShaderParameters,
 };
 
 #[wasm_bindgen]
@@ -79,9 +86,13 @@ impl WasmApplication {
             .map(|v| ModelInfo {
                 id: v.id,
                 transform: v.transform.into(),
+                parent: v.parent,
                 material_info: v.material_info.into(),
                 shader_id: ShaderId(v.shader_id),
                 instance_count: v.instance_count,
+                instances: v.instances.into_iter().map(Into::into).collect(),
+                cast_shadows: v.cast_shadows,
+                receive_shadows: v.receive_shadows,
             })
             .collect::<Vec<_>>();
         let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), |app| {
@@ -93,6 +104,45 @@ impl WasmApplication {
         .await;
     }
 
+    /// Sets the lights of the scene. `None` restores the default lights.
+    pub async fn update_lights(&self, js_lights: Option<Vec<WasmLightInfo>>) {
+        let lights = match js_lights {
+            Some(js_lights) => js_lights.into_iter().map(LightInfo::from).collect(),
+            None => LightInfo::default_lights(),
+        };
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), |app| {
+            app.renderer.as_mut().map(|renderer| {
+                renderer.update_lights(&lights);
+            });
+            app.app.update_lights(lights);
+        })
+        .await;
+    }
+
+    /// Sets the environment from the bytes of a Radiance .hdr image. `None` restores the default sky.
+    pub async fn set_environment(
+        &self,
+        hdr: Option<Vec<u8>>,
+        intensity: f32,
+    ) -> Result<(), JsError> {
+        let environment = match hdr {
+            Some(hdr) => Some(Arc::new(EnvironmentInfo {
+                image: HdrImage::from_radiance(&hdr).map_err(|e| JsError::new(&e.to_string()))?,
+                intensity,
+            })),
+            None => None,
+        };
+        run_on_main(self.event_loop_proxy.clone().unwrap(), |app| {
+            if let Some(renderer) = &app.renderer {
+                renderer.set_environment(environment.clone())?;
+            }
+            app.app.set_environment(environment);
+            Ok(())
+        })
+        .await
+        .map_err(|e: anyhow::Error| JsError::new(&e.to_string()))
+    }
+
     pub async fn update_shader(&self, shader_info: WasmShaderInfo) {
         let shader_id = ShaderId(shader_info.id);
         let info = ShaderInfo {
@@ -100,19 +150,53 @@ impl WasmApplication {
             code: shader_info.code,
         };
 
-        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), {
-            let shader_id = shader_id.clone();
-            move |app| {
-                let on_shader_compiled = app.on_shader_compiled.clone();
-                app.renderer.as_mut().map(|renderer| {
-                    any_spawner::Executor::spawn_local(renderer.set_shader(
-                        shader_id.clone(),
-                        &info,
-                        on_shader_compiled,
-                    ))
-                });
-                app.app.set_shader(shader_id, info);
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.update_shader(shader_id, info);
+        })
+        .await;
+    }
+
+    /// Code that shaders can `////#include` by name. `None` removes the file.
+    pub async fn update_shader_file(&self, name: String, code: Option<String>) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.update_shader_file(name, code);
+        })
+        .await;
+    }
+
+    /// The parameters of the last version of the shader that compiled
+    pub async fn get_shader_parameters(&self, id: String) -> Option<WasmShaderParameters> {
+        run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.renderer
+                .as_ref()
+                .and_then(|renderer| renderer.shader_parameters(&ShaderId(id)))
+        })
+        .await
+        .map(|parameters| WasmShaderParameters {
+            parameters: parameters.parameters.iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Shaders whose new version is still being compiled. They render with the previous version meanwhile.
+    pub async fn get_compiling_shaders(&self) -> Vec<String> {
+        run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.renderer
+                .as_ref()
+                .map(|renderer| renderer.compiling_shaders())
+                .unwrap_or_default()
+        })
+        .await
+        .into_iter()
+        .map(|id| id.0)
+        .collect()
+    }
+
+    pub async fn set_model_parameters(&self, model_id: String, values: WasmParameterValues) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            if let Some(renderer) = &app.renderer {
+                renderer.set_model_parameters(&model_id, values.values.clone());
             }
+            app.app.model_parameters.insert(model_id, values.values);
         })
         .await;
     }
@@ -128,7 +212,12 @@ impl WasmApplication {
         .await;
     }
 
-    pub async fn update_texture(&self, texture_id: String, image: ImageBitmap) {
+    pub async fn update_texture(
+        &self,
+        texture_id: String,
+        image: ImageBitmap,
+        sampler: Option<WasmSamplerInfo>,
+    ) {
         let id = TextureId(texture_id);
         let info = TextureInfo {
             width: image.width(),
@@ -137,6 +226,7 @@ impl WasmApplication {
             data: TextureData::Image(image),
             #[cfg(not(target_arch = "wasm32"))]
             data: TextureData::Bytes(vec![0, 0, 0]),
+            sampler: sampler.map(Into::into).unwrap_or_default(),
         };
 
         let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), {
@@ -231,6 +321,26 @@ impl WasmApplication {
         .await;
     }
 
+    pub async fn set_lod_mode(&self, lod_mode: WasmLodMode) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.app.lod_mode = lod_mode.into();
+            if let Some(renderer) = &app.renderer {
+                renderer.set_lod_mode(app.app.lod_mode);
+            }
+        })
+        .await;
+    }
+
+    pub async fn set_lod_kernel(&self, lod_kernel: WasmLodKernel) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.app.lod_kernel = lod_kernel.into();
+            if let Some(renderer) = &app.renderer {
+                renderer.set_lod_kernel(app.app.lod_kernel);
+            }
+        })
+        .await;
+    }
+
     pub async fn set_hot_value(&self, value: f32) {
         let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
             if let Some(renderer) = &app.renderer {
@@ -240,6 +350,89 @@ impl WasmApplication {
         .await;
     }
 
+    pub async fn set_debug_view(&self, debug_view: WasmDebugView) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.app.debug_view = debug_view.into();
+            if let Some(renderer) = &app.renderer {
+                renderer.set_debug_view(app.app.debug_view);
+            }
+        })
+        .await;
+    }
+
+    pub async fn set_sample_count(&self, sample_count: u32) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.app.sample_count = sample_count;
+            if let Some(renderer) = &app.renderer {
+                renderer.set_sample_count(sample_count);
+            }
+        })
+        .await;
+    }
+
+    pub async fn set_post_process(&self, post_process: WasmPostProcessInfo) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.app.post_process = post_process.into();
+            if let Some(renderer) = &app.renderer {
+                renderer.set_post_process(app.app.post_process.clone());
+            }
+        })
+        .await;
+    }
+
+    pub async fn set_clock(&self, mode: WasmClockMode) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.app.clock = mode.into();
+            if let Some(renderer) = &app.renderer {
+                renderer.set_clock(app.app.clock);
+            }
+        })
+        .await;
+    }
+
+    /// Jumps to a point in time, in seconds
+    pub async fn set_elapsed_time(&self, elapsed: f32) {
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            if let Some(renderer) = &app.renderer {
+                renderer.set_elapsed(Seconds(elapsed));
+            }
+        })
+        .await;
+    }
+
+    /// Renders the current frame at `scale` times the canvas resolution, and returns it as a PNG file
+    pub async fn capture_frame(&self, scale: f32) -> Result<Vec<u8>, JsError> {
+        let capture = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            app.renderer
+                .as_ref()
+                .map(|renderer| renderer.capture_frame(scale))
+        })
+        .await
+        .ok_or_else(|| JsError::new("Renderer is not ready"))?;
+        capture
+            .await
+            .and_then(|image| image.to_png())
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Takes a LUT in the .cube format
+    pub async fn set_color_grading_lut(&self, cube: Option<String>) -> Result<(), JsError> {
+        let color_grading = match cube {
+            Some(cube) => Some(Arc::new(
+                ColorGradingLut::from_cube(&cube).map_err(|e| JsError::new(&e.to_string()))?,
+            )),
+            None => None,
+        };
+        let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
+            if let Some(renderer) = &app.renderer {
+                renderer.set_color_grading(color_grading.clone());
+            }
+            app.app.color_grading = color_grading;
+        })
+        .await;
+        Ok(())
+    }
+
     pub async fn focus_on(&self, position: WasmPosition) {
         let _ = run_on_main(self.event_loop_proxy.clone().unwrap(), move |app| {
             app.app.camera_controller.focus_on(position.into());
@@ -1,6 +1,13 @@
 #![allow(non_snake_case)]
 
-use renderer_core::game::TextureId;
+use renderer_core::{
+    game::{
+        AddressMode, BloomInfo, DebugView, FilterMode, LodKernel, LodMode, PostProcessInfo,
+        SamplerInfo, TextureId, ToneMapping,
+    },
+    shader_parameters::{ParameterValues, ShaderParameter},
+    time::{ClockMode, Seconds},
+};
 use serde::{Deserialize, Serialize};
 use tsify_next::Tsify;
 
@@ -8,10 +15,51 @@ use tsify_next::Tsify;
 #[tsify(into_wasm_abi, from_wasm_abi)]
 pub struct WasmModelInfo {
     pub id: String,
+    /// Relative to the parent, if there is one
     pub transform: WasmTransform,
+    /// The ID of another model, which this one moves with
+    #[serde(default)]
+    pub parent: Option<String>,
     pub material_info: WasmMaterialInfo,
     pub shader_id: String,
     pub instance_count: u32,
+    /// Data for each instance, which the shader code can read
+    #[serde(default)]
+    pub instances: Vec<WasmInstanceInfo>,
+    #[serde(default = "default_true")]
+    pub cast_shadows: bool,
+    #[serde(default = "default_true")]
+    pub receive_shadows: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct WasmInstanceInfo {
+    /// Relative to the model
+    pub transform: WasmTransform,
+    #[serde(default = "default_tint")]
+    pub tint: [f32; 3],
+    /// Free for the shader code
+    #[serde(default)]
+    pub custom: [f32; 4],
+}
+
+fn default_tint() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+impl From<WasmInstanceInfo> for renderer_core::game::InstanceInfo {
+    fn from(v: WasmInstanceInfo) -> Self {
+        renderer_core::game::InstanceInfo {
+            transform: v.transform.into(),
+            tint: v.tint.into(),
+            custom: v.custom.into(),
+        }
+    }
 }
 
 #[derive(Tsify, Serialize, Deserialize)]
@@ -19,7 +67,29 @@ pub struct WasmModelInfo {
 pub struct WasmTransform {
     pub position: [f32; 3],
     pub rotation: [f32; 3],
-    pub scale: f32,
+    pub scale: WasmScale,
+    /// Column-major 4x4 matrix, which replaces the position, rotation and scale.
+    /// The last row is ignored, since the transform has to be affine.
+    #[serde(default)]
+    pub matrix: Option<[f32; 16]>,
+}
+
+/// A single number scales uniformly
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(untagged)]
+pub enum WasmScale {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+impl From<WasmScale> for glam::Vec3 {
+    fn from(value: WasmScale) -> Self {
+        match value {
+            WasmScale::Uniform(scale) => glam::Vec3::splat(scale),
+            WasmScale::PerAxis(scale) => scale.into(),
+        }
+    }
 }
 
 #[derive(Tsify, Serialize, Deserialize)]
@@ -49,7 +119,10 @@ impl From<WasmTransform> for renderer_core::transform::Transform {
                 v.rotation[1],
                 v.rotation[2],
             ),
-            scale: v.scale,
+            scale: v.scale.into(),
+            matrix: v
+                .matrix
+                .map(|matrix| glam::Affine3A::from_mat4(glam::Mat4::from_cols_array(&matrix))),
         }
     }
 }
@@ -62,7 +135,23 @@ pub struct WasmMaterialInfo {
     pub roughness: f32,
     pub metallic: f32,
     pub diffuse_texture: Option<String>,
+    #[serde(default)]
+    pub normal_texture: Option<String>,
+    #[serde(default)]
+    pub metallic_roughness_texture: Option<String>,
+    #[serde(default)]
+    pub occlusion_texture: Option<String>,
+    #[serde(default)]
+    pub emissive_texture: Option<String>,
     pub texture_scale: [f32; 2],
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub alpha_from_texture: bool,
+}
+
+fn default_opacity() -> f32 {
+    1.0
 }
 
 impl From<WasmMaterialInfo> for renderer_core::game::MaterialInfo {
@@ -73,7 +162,62 @@ impl From<WasmMaterialInfo> for renderer_core::game::MaterialInfo {
             roughness: v.roughness,
             metallic: v.metallic,
             diffuse_texture: v.diffuse_texture.map(TextureId),
+            normal_texture: v.normal_texture.map(TextureId),
+            metallic_roughness_texture: v.metallic_roughness_texture.map(TextureId),
+            occlusion_texture: v.occlusion_texture.map(TextureId),
+            emissive_texture: v.emissive_texture.map(TextureId),
             texture_scale: v.texture_scale.into(),
+            opacity: v.opacity,
+            alpha_from_texture: v.alpha_from_texture,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WasmLightInfo {
+    Point {
+        position: [f32; 3],
+        range: f32,
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Directional {
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+    },
+}
+
+impl From<WasmLightInfo> for renderer_core::game::LightInfo {
+    fn from(v: WasmLightInfo) -> Self {
+        use renderer_core::game::{LightInfo, LightKind};
+        match v {
+            WasmLightInfo::Point {
+                position,
+                range,
+                color,
+                intensity,
+            } => LightInfo {
+                kind: LightKind::Point {
+                    position: position.into(),
+                    range,
+                },
+                color: color.into(),
+                intensity,
+            },
+            WasmLightInfo::Directional {
+                direction,
+                color,
+                intensity,
+            } => LightInfo {
+                kind: LightKind::Directional {
+                    direction: direction.into(),
+                },
+                color: color.into(),
+                intensity,
+            },
         }
     }
 }
@@ -104,6 +248,70 @@ impl From<wgpu::CompilationMessage> for WasmCompilationMessage {
     }
 }
 
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmLodMode {
+    Rebuild,
+    Incremental,
+}
+
+impl From<WasmLodMode> for LodMode {
+    fn from(v: WasmLodMode) -> Self {
+        match v {
+            WasmLodMode::Rebuild => Self::Rebuild,
+            WasmLodMode::Incremental => Self::Incremental,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WasmLodKernel {
+    Rounds,
+    /// Subdivides until the patches are small enough, or `max_depth` splits away from the root
+    Persistent {
+        max_depth: u32,
+    },
+}
+
+impl From<WasmLodKernel> for LodKernel {
+    fn from(v: WasmLodKernel) -> Self {
+        match v {
+            WasmLodKernel::Rounds => Self::Rounds,
+            WasmLodKernel::Persistent { max_depth } => Self::Persistent { max_depth },
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmDebugView {
+    None,
+    Wireframe,
+    PatchBoundaries,
+    PatchSize,
+    Uv,
+    Normals,
+    InstanceId,
+}
+
+impl From<WasmDebugView> for DebugView {
+    fn from(v: WasmDebugView) -> Self {
+        match v {
+            WasmDebugView::None => Self::None,
+            WasmDebugView::Wireframe => Self::Wireframe,
+            WasmDebugView::PatchBoundaries => Self::PatchBoundaries,
+            WasmDebugView::PatchSize => Self::PatchSize,
+            WasmDebugView::Uv => Self::Uv,
+            WasmDebugView::Normals => Self::Normals,
+            WasmDebugView::InstanceId => Self::InstanceId,
+        }
+    }
+}
+
 #[derive(Tsify, Serialize, Deserialize)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
 pub enum WasmCompilationMessageType {
@@ -146,3 +354,159 @@ impl From<wgpu::SourceLocation> for WasmSourceLocation {
         }
     }
 }
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct WasmSamplerInfo {
+    #[serde(default)]
+    pub address_mode: WasmAddressMode,
+    #[serde(default)]
+    pub filter: WasmFilterMode,
+    #[serde(default = "default_anisotropy")]
+    pub anisotropy: u16,
+}
+
+fn default_anisotropy() -> u16 {
+    1
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmAddressMode {
+    #[default]
+    Repeat,
+    MirrorRepeat,
+    ClampToEdge,
+}
+
+#[derive(Tsify, Serialize, Deserialize, Default)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmFilterMode {
+    Nearest,
+    #[default]
+    Linear,
+}
+
+impl From<WasmSamplerInfo> for SamplerInfo {
+    fn from(v: WasmSamplerInfo) -> Self {
+        Self {
+            address_mode: match v.address_mode {
+                WasmAddressMode::Repeat => AddressMode::Repeat,
+                WasmAddressMode::MirrorRepeat => AddressMode::MirrorRepeat,
+                WasmAddressMode::ClampToEdge => AddressMode::ClampToEdge,
+            },
+            filter: match v.filter {
+                WasmFilterMode::Nearest => FilterMode::Nearest,
+                WasmFilterMode::Linear => FilterMode::Linear,
+            },
+            anisotropy: v.anisotropy,
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct WasmPostProcessInfo {
+    /// In stops
+    pub exposure: f32,
+    pub tone_mapping: WasmToneMapping,
+    #[serde(default)]
+    pub bloom: Option<WasmBloomInfo>,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmToneMapping {
+    None,
+    Aces,
+    Agx,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct WasmBloomInfo {
+    pub intensity: f32,
+    pub threshold: f32,
+}
+
+impl From<WasmPostProcessInfo> for PostProcessInfo {
+    fn from(v: WasmPostProcessInfo) -> Self {
+        Self {
+            exposure: v.exposure,
+            tone_mapping: match v.tone_mapping {
+                WasmToneMapping::None => ToneMapping::None,
+                WasmToneMapping::Aces => ToneMapping::Aces,
+                WasmToneMapping::Agx => ToneMapping::AgX,
+            },
+            bloom: v.bloom.map(|bloom| BloomInfo {
+                intensity: bloom.intensity,
+                threshold: bloom.threshold,
+            }),
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WasmClockMode {
+    RealTime,
+    /// Advances by the same number of seconds every frame
+    FixedStep {
+        step: f32,
+    },
+    Paused,
+}
+
+impl From<WasmClockMode> for ClockMode {
+    fn from(v: WasmClockMode) -> Self {
+        match v {
+            WasmClockMode::RealTime => ClockMode::RealTime,
+            WasmClockMode::FixedStep { step } => ClockMode::FixedStep(Seconds(step)),
+            WasmClockMode::Paused => ClockMode::Paused,
+        }
+    }
+}
+
+/// Describes one `// @param` annotation, so that a UI can show a slider for it
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct WasmShaderParameter {
+    pub name: String,
+    /// The WGSL type, like f32 or vec3f
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// One value per component
+    pub default: Vec<f32>,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+impl From<&ShaderParameter> for WasmShaderParameter {
+    fn from(v: &ShaderParameter) -> Self {
+        Self {
+            name: v.name.clone(),
+            ty: v.ty.wgsl_name().to_string(),
+            default: v.default.clone(),
+            min: v.range.map(|(min, _)| min),
+            max: v.range.map(|(_, max)| max),
+        }
+    }
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct WasmShaderParameters {
+    pub parameters: Vec<WasmShaderParameter>,
+}
+
+/// Parameter values by name, with one number per component
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct WasmParameterValues {
+    #[tsify(type = "Record<string, number[]>")]
+    pub values: ParameterValues,
+}
@@ -150,7 +150,7 @@ fn sampleObject(input: vec2f) -> vec3f { return vec3(input, 0.0); }
 //// END OF AUTOGEN
 
 struct Model {
-    model_similarity: mat4x4<f32>,
+    model_matrix: mat4x4<f32>,
 }
 
 struct OutputBuffer {
@@ -200,7 +200,7 @@ fn main(
 
     // Sample corner
     let pos = sampleObject(quad_point);
-    let world_pos = /*model.model_similarity * */vec4<f32>(pos, 1.0);
+    let world_pos = /*model.model_matrix * */vec4<f32>(pos, 1.0);
 
     // Prepare output
     var out: VertexOutput;
@@ -2,9 +2,17 @@ import { getBuffers, renderEncoder, type LodStageBuffers } from "@/webgpu-hook";
 import init, {
   WasmApplication,
   type WasmModelInfo,
+  type WasmLightInfo,
   type WasmShaderInfo,
   type WasmCompilationMessage,
+  type WasmDebugView,
+  type WasmLodMode,
+  type WasmLodKernel,
+  type WasmShaderParameters,
+  type WasmSamplerInfo,
+  type WasmPostProcessInfo,
   type WasmFrameTime,
+  type WasmClockMode,
 } from "../../parametric-renderer-core/pkg";
 
 await init();
@@ -24,6 +32,18 @@ export class WgpuEngine {
     );
     await this.taskQueue;
   }
+  async updateLights(js_lights: WasmLightInfo[] | null) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.update_lights(js_lights)
+    );
+    await this.taskQueue;
+  }
+  async setEnvironment(hdr: Uint8Array | null, intensity: number) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_environment(hdr, intensity)
+    );
+    await this.taskQueue;
+  }
   async updateShader(shader_info: WasmShaderInfo) {
     this.taskQueue = this.taskQueue.then(() =>
       this.engine.update_shader(shader_info)
@@ -34,9 +54,59 @@ export class WgpuEngine {
     this.taskQueue = this.taskQueue.then(() => this.engine.remove_shader(id));
     await this.taskQueue;
   }
-  async updateTexture(texture_info: { id: string; bitmap: ImageBitmap }) {
+  /** Code that shaders can include with `////#include "name"`. Shaders can also include other shaders by id or label. */
+  async updateShaderFile(name: string, code: string) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.update_shader_file(name, code)
+    );
+    await this.taskQueue;
+  }
+  async removeShaderFile(name: string) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.update_shader_file(name, null)
+    );
+    await this.taskQueue;
+  }
+  async updateTexture(texture_info: {
+    id: string;
+    bitmap: ImageBitmap;
+    sampler?: WasmSamplerInfo;
+  }) {
     this.taskQueue = this.taskQueue.then(() =>
-      this.engine.update_texture(texture_info.id, texture_info.bitmap)
+      this.engine.update_texture(
+        texture_info.id,
+        texture_info.bitmap,
+        texture_info.sampler
+      )
+    );
+    await this.taskQueue;
+  }
+  /** The `// @param` annotations of a shader, for generating sliders. Undefined until the shader has compiled. */
+  async getShaderParameters(
+    id: string
+  ): Promise<WasmShaderParameters | undefined> {
+    let { promise, resolve } = Promise.withResolvers<
+      WasmShaderParameters | undefined
+    >();
+    this.taskQueue = this.taskQueue.then(async () => {
+      resolve(await this.engine.get_shader_parameters(id));
+    });
+    await this.taskQueue;
+    return promise;
+  }
+  /** Ids of the shaders that are still compiling. Until they finish, the previous version is rendered. */
+  async getCompilingShaders(): Promise<string[]> {
+    let { promise, resolve } = Promise.withResolvers<string[]>();
+    this.taskQueue = this.taskQueue.then(async () => {
+      resolve(await this.engine.get_compiling_shaders());
+    });
+    await this.taskQueue;
+    return promise;
+  }
+  /** Values by parameter name, with one number per component. Missing values use the shader defaults. */
+  async setModelParameters(modelId: string, values: Record<string, number[]>) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_model_parameters(modelId, { values })
     );
     await this.taskQueue;
   }
@@ -90,12 +160,75 @@ export class WgpuEngine {
     );
     await this.taskQueue;
   }
+  /** Incremental reuses the patches of the last frame, which is cheaper for small camera movements. */
+  async setLodMode(lodMode: WasmLodMode) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_lod_mode(lodMode)
+    );
+    await this.taskQueue;
+  }
+  /** Persistent subdivides in a single pass, until the patches are small enough. */
+  async setLodKernel(lodKernel: WasmLodKernel) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_lod_kernel(lodKernel)
+    );
+    await this.taskQueue;
+  }
   async setHotValue(value: number) {
     this.taskQueue = this.taskQueue.then(() =>
       this.engine.set_hot_value(value)
     );
     await this.taskQueue;
   }
+  async setDebugView(debugView: WasmDebugView) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_debug_view(debugView)
+    );
+    await this.taskQueue;
+  }
+  /** Gets capped to what the GPU supports. 1 disables multisampling. */
+  async setSampleCount(sampleCount: number) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_sample_count(sampleCount)
+    );
+    await this.taskQueue;
+  }
+  async setPostProcess(postProcess: WasmPostProcessInfo) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_post_process(postProcess)
+    );
+    await this.taskQueue;
+  }
+  /** Takes the contents of a .cube file, or null to disable color grading */
+  async setColorGradingLut(cube: string | null) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_color_grading_lut(cube)
+    );
+    await this.taskQueue;
+  }
+  /** A paused or fixed step clock makes animated shaders reproducible */
+  async setClock(mode: WasmClockMode) {
+    this.taskQueue = this.taskQueue.then(() => this.engine.set_clock(mode));
+    await this.taskQueue;
+  }
+  async setElapsedTime(seconds: number) {
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.set_elapsed_time(seconds)
+    );
+    await this.taskQueue;
+  }
+  /** Renders the current frame at a multiple of the canvas resolution */
+  async captureFrame(scale: number): Promise<Blob> {
+    let { promise, resolve, reject } = Promise.withResolvers<Blob>();
+    this.taskQueue = this.taskQueue.then(() =>
+      this.engine.capture_frame(scale).then(
+        (png) => resolve(new Blob([png], { type: "image/png" })),
+        reject
+      )
+    );
+    await this.taskQueue;
+    return promise;
+  }
 
   async focusOn(position: [number, number, number]) {
     this.taskQueue = this.taskQueue.then(() => this.engine.focus_on(position));
@@ -15,7 +15,13 @@ export const MaterialParameterSchema = z.object({
   metallic: z.number(),
   emissive: z.tuple([z.number(), z.number(), z.number()]),
   diffuseTexture: z.string().optional(),
+  normalTexture: z.string().optional(),
+  metallicRoughnessTexture: z.string().optional(),
+  occlusionTexture: z.string().optional(),
+  emissiveTexture: z.string().optional(),
   textureScale: z.tuple([z.number(), z.number()]).optional(),
+  opacity: z.number().optional(),
+  alphaFromTexture: z.boolean().optional(),
 });
 
 export const ModelSchema = z.object({
@@ -32,9 +38,29 @@ export const ModelSchema = z.object({
 
 export type SerializedModel = z.infer<typeof ModelSchema>;
 
+export const LightSchema = z.discriminatedUnion("type", [
+  z.object({
+    type: z.literal("point"),
+    position: z.tuple([z.number(), z.number(), z.number()]),
+    range: z.number(),
+    color: z.tuple([z.number(), z.number(), z.number()]),
+    intensity: z.number(),
+  }),
+  z.object({
+    type: z.literal("directional"),
+    direction: z.tuple([z.number(), z.number(), z.number()]),
+    color: z.tuple([z.number(), z.number(), z.number()]),
+    intensity: z.number(),
+  }),
+]);
+
+export type SerializedLight = z.infer<typeof LightSchema>;
+
 export const SceneFileSchema = z.object({
   $schema: z.literal(SceneFileSchemaUrl),
   models: z.array(ModelSchema),
+  /** When missing, the default lights are used. */
+  lights: z.array(LightSchema).optional(),
   description: z.string().optional(),
 });
 
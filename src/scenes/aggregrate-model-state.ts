@@ -22,8 +22,14 @@ export function commonModelState(
       metallic: 0,
       emissive: ReadonlyVector3.zero,
       diffuseTexture: null,
+      normalTexture: null,
+      metallicRoughnessTexture: null,
+      occlusionTexture: null,
+      emissiveTexture: null,
       textureWidth: 1,
       textureHeight: 1,
+      opacity: 1,
+      alphaFromTexture: false,
     },
     instanceCount: 1,
   });
@@ -33,6 +39,7 @@ type AggregatableValue =
   | string
   | FilePath
   | number
+  | boolean
   | ReadonlyVector3
   | ReadonlyEulerAngles
   | {
@@ -46,6 +53,7 @@ function aggregrateValues<T extends AggregatableValue>(
 ): T {
   if (
     typeof defaultValue === "number" ||
+    typeof defaultValue === "boolean" ||
     typeof defaultValue === "string" ||
     defaultValue === null
   ) {
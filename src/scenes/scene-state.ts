@@ -1,6 +1,7 @@
 import { computed, ref, shallowRef, type ComputedRef, type Ref } from "vue";
 import {
   SceneFileSchemaUrl,
+  type SerializedLight,
   type SerializedModel,
   type SerializedScene,
 } from "@/filesystem/scene-file";
@@ -78,8 +79,15 @@ export type MaterialParameter = {
   metallic: number;
   emissive: ReadonlyVector3;
   diffuseTexture: string | null;
+  normalTexture: string | null;
+  metallicRoughnessTexture: string | null;
+  occlusionTexture: string | null;
+  emissiveTexture: string | null;
   textureWidth: number;
   textureHeight: number;
+  /** 1 is fully opaque */
+  opacity: number;
+  alphaFromTexture: boolean;
 };
 
 export type VirtualModelState = {
@@ -95,6 +103,8 @@ export type VirtualModelState = {
 
 export interface VirtualSceneState {
   models: VirtualModelState[];
+  /** `null` means that the default lights are used. */
+  lights: SerializedLight[] | null;
   description?: string;
 }
 
@@ -116,6 +126,7 @@ export class VirtualScene {
 
   clear(): void {
     this.state.value.models = [];
+    this.state.value.lights = null;
   }
 
   serialize(): SerializedScene {
@@ -123,12 +134,14 @@ export class VirtualScene {
     return {
       $schema: SceneFileSchemaUrl,
       models,
+      lights: this.state.value.lights ?? undefined,
       description: this.state.value.description,
     };
   }
 
   fromSerialized(data: SerializedScene): void {
     this.state.value.models = data.models.map(deserializeModel);
+    this.state.value.lights = data.lights ?? null;
     this.state.value.description = data.description;
   }
 
@@ -161,6 +174,7 @@ export class VirtualScene {
 export function useVirtualScene() {
   const state = ref<VirtualSceneState>({
     models: [],
+    lights: null,
   });
   const api = shallowRef<VirtualScene>(new VirtualScene(state));
 
@@ -185,7 +199,13 @@ function serializeModel(model: VirtualModelState): SerializedModel {
       metallic: model.material.metallic,
       emissive: model.material.emissive.serialize(),
       diffuseTexture: model.material.diffuseTexture ?? undefined,
+      normalTexture: model.material.normalTexture ?? undefined,
+      metallicRoughnessTexture: model.material.metallicRoughnessTexture ?? undefined,
+      occlusionTexture: model.material.occlusionTexture ?? undefined,
+      emissiveTexture: model.material.emissiveTexture ?? undefined,
       textureScale: [model.material.textureWidth, model.material.textureHeight],
+      opacity: model.material.opacity,
+      alphaFromTexture: model.material.alphaFromTexture,
     },
     instanceCount: model.instanceCount,
   };
@@ -206,8 +226,14 @@ function deserializeModel(data: SerializedModel): VirtualModelState {
       metallic: data.material.metallic,
       emissive: ReadonlyVector3.fromSerialized(data.material.emissive),
       diffuseTexture: data.material.diffuseTexture ?? null,
+      normalTexture: data.material.normalTexture ?? null,
+      metallicRoughnessTexture: data.material.metallicRoughnessTexture ?? null,
+      occlusionTexture: data.material.occlusionTexture ?? null,
+      emissiveTexture: data.material.emissiveTexture ?? null,
       textureWidth: data.material.textureScale?.[0] ?? 1,
       textureHeight: data.material.textureScale?.[1] ?? 1,
+      opacity: data.material.opacity ?? 1,
+      alphaFromTexture: data.material.alphaFromTexture ?? false,
     },
     instanceCount: data.instanceCount,
   };